@@ -0,0 +1,283 @@
+use crate::database::database_client::DatabaseClient;
+use crate::domain::job::CallbackDelivery;
+use crate::domain::job::CallbackState;
+use crate::domain::job::Job;
+use crate::domain::job::JobStatus;
+use anyhow::Result;
+use common::counter;
+use common::messaging::retry::RetryPolicy;
+use hmac::Hmac;
+use hmac::KeyInit as _;
+use hmac::Mac as _;
+use sha2::Sha256;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+counter!(
+    CALLBACK_DELIVERED_COUNTER,
+    "callback_dispatcher_delivered",
+    "Number of job callbacks delivered"
+);
+counter!(
+    CALLBACK_FAILED_ATTEMPT_COUNTER,
+    "callback_dispatcher_failed_attempts",
+    "Number of job callback delivery attempts that failed"
+);
+counter!(
+    CALLBACK_ABANDONED_COUNTER,
+    "callback_dispatcher_abandoned",
+    "Number of job callbacks abandoned after exhausting their attempts"
+);
+counter!(
+    DISPATCHER_ERROR_COUNTER,
+    "callback_dispatcher_errors",
+    "Number of errors encountered by the callback dispatcher"
+);
+
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+pub const JOB_ID_HEADER: &str = "X-Job-Id";
+
+/// Body posted to the callback URL of a finished job.
+#[derive(serde::Serialize)]
+struct CallbackPayload {
+    job_id: String,
+    status: JobStatus,
+    operations: usize,
+    completed_operations: usize,
+    failed_operations: usize,
+}
+
+/// Background dispatcher notifying the callback URL of the jobs reaching a
+/// terminal state.
+///
+/// Every instance runs the dispatcher. A callback is scheduled when its job
+/// reaches a terminal status, then leased the same way as the outbox records:
+/// it is posted by one dispatcher at a time, and retried with a backoff on
+/// failure. Every attempt is appended to the delivery log of the job.
+pub struct CallbackDispatcher {
+    database_client: Arc<DatabaseClient>,
+    http_client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl CallbackDispatcher {
+    const CONCURRENCY: usize = 2;
+    const MAX_ATTEMPTS: u32 = 8;
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_mins(5);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    const LEASE_DURATION: Duration = Duration::from_secs(30);
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(database_client: Arc<DatabaseClient>) -> Result<Self> {
+        tracing::debug!("Initializing the callback dispatcher");
+
+        Ok(Self {
+            database_client,
+            http_client: reqwest::Client::builder()
+                .timeout(Self::REQUEST_TIMEOUT)
+                .build()?,
+            retry: RetryPolicy::exponential(Self::MAX_ATTEMPTS)
+                .with_backoff(Self::INITIAL_BACKOFF, Self::MAX_BACKOFF),
+        })
+    }
+
+    pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
+        tracing::debug!("Start the callback dispatcher");
+
+        (0..Self::CONCURRENCY)
+            .map(|_| {
+                let database_client = Arc::clone(&self.database_client);
+                let http_client = self.http_client.clone();
+                let retry = self.retry.clone();
+                let shutdown = shutdown.clone();
+
+                tokio::spawn(async move {
+                    Self::worker_dispatcher(database_client, http_client, retry, shutdown).await;
+                    Ok(())
+                })
+            })
+            .collect()
+    }
+
+    async fn worker_dispatcher(
+        database_client: Arc<DatabaseClient>,
+        http_client: reqwest::Client,
+        retry: RetryPolicy,
+        shutdown: CancellationToken,
+    ) {
+        let owner = common::application::instance_id();
+
+        loop {
+            let claimed = tokio::select! {
+                () = shutdown.cancelled() => return,
+                result = database_client
+                    .job_repository()
+                    .claim_callback(owner, Self::LEASE_DURATION) => result,
+            };
+            match claimed {
+                Ok(Some(job)) => {
+                    if let Err(err) =
+                        Self::dispatch_callback(&job, &database_client, &http_client, &retry).await
+                    {
+                        tracing::error!(
+                            "Failed to dispatch the callback of job {}: {err}",
+                            job.id()
+                        );
+
+                        DISPATCHER_ERROR_COUNTER.add(1, &[]);
+                    }
+
+                    // Look for another callback right away
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::error!("Failed to claim a job callback: {err}");
+
+                    DISPATCHER_ERROR_COUNTER.add(1, &[]);
+                }
+            }
+
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(job_id = job.id()))]
+    async fn dispatch_callback(
+        job: &Job,
+        database_client: &DatabaseClient,
+        http_client: &reqwest::Client,
+        retry: &RetryPolicy,
+    ) -> Result<()> {
+        let owner = common::application::instance_id();
+        let job_id = job.id();
+        let Some(callback) = job.callback() else {
+            return Ok(());
+        };
+
+        let status = job.status();
+
+        // Only the jobs stored before the callbacks were scheduled on their terminal status
+        if !status.is_terminal() {
+            return database_client
+                .job_repository()
+                .defer_callback(&job_id, owner)
+                .await;
+        }
+
+        let payload = serde_json::to_vec(&CallbackPayload {
+            job_id: job_id.clone(),
+            status,
+            operations: job.operations(),
+            completed_operations: job.completed_operations(),
+            failed_operations: job.failed_operations(),
+        })?;
+
+        let attempt = callback.attempts() + 1;
+        tracing::info!("Posting the callback of job {job_id}, attempt {attempt}");
+
+        let mut request = http_client
+            .post(callback.url())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(JOB_ID_HEADER, &job_id);
+        if let Some(secret) = callback.secret() {
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs();
+            request = request
+                .header(SIGNATURE_TIMESTAMP_HEADER, timestamp)
+                .header(SIGNATURE_HEADER, sign(secret, timestamp, &payload)?);
+        }
+
+        let delivery = match request.body(payload).send().await {
+            Ok(response) if response.status().is_success() => {
+                CallbackDelivery::new(attempt, Some(response.status().as_u16()), None)
+            }
+            Ok(response) => CallbackDelivery::new(
+                attempt,
+                Some(response.status().as_u16()),
+                Some(format!("Unexpected status {}", response.status())),
+            ),
+            Err(err) => CallbackDelivery::new(attempt, None, Some(err.to_string())),
+        };
+
+        let (state, delay) = if delivery.error().is_none() {
+            tracing::info!("Callback of job {job_id} delivered");
+
+            CALLBACK_DELIVERED_COUNTER.add(1, &[]);
+
+            (CallbackState::Delivered, Duration::ZERO)
+        } else if attempt >= retry.max_attempts() {
+            tracing::error!("Giving up on the callback of job {job_id} after {attempt} attempts");
+
+            CALLBACK_ABANDONED_COUNTER.add(1, &[]);
+
+            (CallbackState::Failed, Duration::ZERO)
+        } else {
+            tracing::warn!(
+                "Failed to deliver the callback of job {job_id}: {}",
+                delivery.error().unwrap_or_default()
+            );
+
+            CALLBACK_FAILED_ATTEMPT_COUNTER.add(1, &[]);
+
+            (CallbackState::Pending, retry.backoff(attempt))
+        };
+
+        database_client
+            .job_repository()
+            .record_callback_delivery(&job_id, owner, &delivery, state, delay)
+            .await
+    }
+}
+
+/// Signs `<timestamp>.<payload>` with `secret`, as the `sha256=<hex digest>`
+/// value of the signature header. The receivers reject the old timestamps, so
+/// that a captured delivery cannot be replayed.
+fn sign(secret: &str, timestamp: u64, payload: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    let signature =
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .fold(String::from("sha256="), |mut signature, byte| {
+                let _ = write!(signature, "{byte:02x}");
+                signature
+            });
+
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sign;
+
+    #[test]
+    fn sign_matches_the_reference_hmac() {
+        // Arrange
+        let secret = "key";
+        let payload = b"The quick brown fox jumps over the lazy dog";
+
+        // Act
+        let signature = sign(secret, 1_700_000_000, payload).unwrap();
+
+        // Assert
+        assert_eq!(
+            signature,
+            "sha256=2f658d6aef4f246e91cd741bbcded7479e9605f9d41c9e248122a117e0e1765b"
+        );
+    }
+}
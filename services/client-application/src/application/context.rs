@@ -1,5 +1,14 @@
+use crate::application::callback_dispatcher::CallbackDispatcher;
+use crate::application::dependency_resolver::DependencyResolver;
+use crate::application::dispatch_registry::DispatchRegistry;
+use crate::application::job_definition_scheduler::JobDefinitionScheduler;
+use crate::application::job_event_hub::JobEventHub;
+use crate::application::job_event_notifier::JobEventNotifier;
+use crate::application::progress_reconciler::ProgressReconciler;
 use crate::database::database_client::DatabaseClient;
+use crate::database::outbox_relay::OutboxRelay;
 use crate::http::JobController;
+use crate::http::JobDefinitionController;
 use crate::http::OperationController;
 use crate::messaging::consumer::MessageConsumer;
 use crate::messaging::producer::MessageProducer;
@@ -8,19 +17,30 @@ use axum::Router;
 use axum::extract::DefaultBodyLimit;
 use axum::handler::Handler as _;
 use axum::routing::get;
+use axum::routing::post;
+use common::http::AdminGuard;
+use common::http::DeadLetterController;
 use common::http::HttpServer;
+use common::messaging::producer::flush_producer;
 use futures::future::try_join_all;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal::unix::SignalKind;
 use tokio::signal::unix::signal;
 use tokio_util::sync::CancellationToken;
 
 const HTTP_PORT: u16 = 8080;
+const PRODUCER_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
 const BODY_LIMIT: DefaultBodyLimit = DefaultBodyLimit::max(10 * 1024 * 1024); // 10MB
 
 pub struct ApplicationState {
     database_client: Arc<DatabaseClient>,
     message_producer: Arc<MessageProducer>,
+    dispatch_registry: Arc<DispatchRegistry>,
+    outbox_relay: Arc<OutboxRelay>,
+    job_event_hub: Arc<JobEventHub>,
+    job_event_notifier: Arc<JobEventNotifier>,
+    progress_reconciler: ProgressReconciler,
 }
 
 impl ApplicationState {
@@ -31,30 +51,92 @@ impl ApplicationState {
     pub fn message_producer(&self) -> &MessageProducer {
         &self.message_producer
     }
+
+    pub fn dispatch_registry(&self) -> &DispatchRegistry {
+        &self.dispatch_registry
+    }
+
+    pub fn outbox_relay(&self) -> &OutboxRelay {
+        &self.outbox_relay
+    }
+
+    pub const fn job_event_hub(&self) -> &Arc<JobEventHub> {
+        &self.job_event_hub
+    }
+
+    pub fn job_event_notifier(&self) -> &JobEventNotifier {
+        &self.job_event_notifier
+    }
+
+    pub const fn progress_reconciler(&self) -> &ProgressReconciler {
+        &self.progress_reconciler
+    }
 }
 
 pub type SharedApplicationState = Arc<ApplicationState>;
 
 pub struct Application {
     consumer: MessageConsumer,
+    outbox_relay: Arc<OutboxRelay>,
+    job_event_notifier: Arc<JobEventNotifier>,
+    callback_dispatcher: CallbackDispatcher,
+    job_definition_scheduler: JobDefinitionScheduler,
     http_server: HttpServer,
 }
 
 pub async fn create_application() -> Result<Application> {
     let database_client = Arc::new(DatabaseClient::new().await?);
     let message_producer = Arc::new(MessageProducer::new()?);
-    let consumer = MessageConsumer::new(Arc::clone(&database_client))?;
+    let dispatch_registry = Arc::new(DispatchRegistry::default());
+    let job_event_hub = Arc::new(JobEventHub::default());
+    let job_event_notifier = Arc::new(JobEventNotifier::new(Arc::clone(&message_producer)));
+    let dependency_resolver = Arc::new(DependencyResolver::new(
+        Arc::clone(&database_client),
+        Arc::clone(&message_producer),
+    ));
+    let consumer = MessageConsumer::new(
+        Arc::clone(&database_client),
+        dependency_resolver,
+        Arc::clone(&dispatch_registry),
+        Arc::clone(&job_event_hub),
+        Arc::clone(&job_event_notifier),
+    )?;
+    let outbox_relay = Arc::new(OutboxRelay::new(
+        Arc::clone(&database_client),
+        Arc::clone(&message_producer),
+        Arc::clone(&dispatch_registry),
+    ));
+
+    let callback_dispatcher = CallbackDispatcher::new(Arc::clone(&database_client))?;
+
+    let progress_reconciler = ProgressReconciler::new(Arc::clone(&database_client));
+
+    let job_definition_scheduler =
+        JobDefinitionScheduler::new(Arc::clone(&database_client), Arc::clone(&outbox_relay));
 
     let application_state = Arc::new(ApplicationState {
         database_client,
         message_producer,
+        dispatch_registry,
+        outbox_relay: Arc::clone(&outbox_relay),
+        job_event_hub,
+        job_event_notifier: Arc::clone(&job_event_notifier),
+        progress_reconciler,
     });
 
-    let router = build_router(Arc::clone(&application_state));
+    let router = build_router(Arc::clone(&application_state))
+        .merge(DeadLetterController::router(
+            consumer.dead_letter_replayers()?,
+        ))
+        .layer(BODY_LIMIT);
     let http_server = HttpServer::new(HTTP_PORT, router);
 
     Ok(Application {
         consumer,
+        outbox_relay,
+        job_event_notifier,
+        callback_dispatcher,
+        job_definition_scheduler,
         http_server,
     })
 }
@@ -64,13 +146,32 @@ fn build_router(application_state: SharedApplicationState) -> Router {
         .route(
             "/api/jobs",
             get(JobController::get_jobs_endpoint_handler)
-                .post(JobController::create_job_endpoint_handler.layer(BODY_LIMIT)),
+                // The text uploads are streamed, the JSON ones bounding themselves
+                .post(
+                    JobController::create_job_endpoint_handler.layer(DefaultBodyLimit::disable()),
+                ),
         )
         .route(
             "/api/jobs/{job_id}",
             get(JobController::get_job_endpoint_handler)
                 .delete(JobController::delete_job_endpoint_handler),
         )
+        .route(
+            "/api/jobs/{job_id}/cancel",
+            post(JobController::cancel_job_endpoint_handler),
+        )
+        .route(
+            "/api/jobs/{job_id}/reconcile",
+            post(JobController::reconcile_job_endpoint_handler),
+        )
+        .route(
+            "/api/jobs/{job_id}/events",
+            get(JobController::get_job_events_endpoint_handler),
+        )
+        .route(
+            "/api/jobs/{job_id}/results",
+            get(OperationController::get_results_endpoint_handler),
+        )
         .route(
             "/api/jobs/{job_id}/operations",
             get(OperationController::get_operations_endpoint_handler),
@@ -79,6 +180,32 @@ fn build_router(application_state: SharedApplicationState) -> Router {
             "/api/jobs/{job_id}/operations/{operation_id}",
             get(OperationController::get_operation_endpoint_handler),
         )
+        .route(
+            "/api/job-definitions",
+            get(JobDefinitionController::get_definitions_endpoint_handler)
+                .post(JobDefinitionController::create_definition_endpoint_handler),
+        )
+        .route(
+            "/api/job-definitions/{definition_id}",
+            get(JobDefinitionController::get_definition_endpoint_handler)
+                .delete(JobDefinitionController::delete_definition_endpoint_handler),
+        )
+        .route(
+            "/api/job-definitions/{definition_id}/pause",
+            post(JobDefinitionController::pause_definition_endpoint_handler),
+        )
+        .route(
+            "/api/job-definitions/{definition_id}/resume",
+            post(JobDefinitionController::resume_definition_endpoint_handler),
+        )
+        .route(
+            "/api/job-definitions/{definition_id}/runs",
+            get(JobDefinitionController::get_definition_runs_endpoint_handler),
+        )
+        .merge(AdminGuard::protect(Router::new().route(
+            "/admin/jobs/reconcile",
+            post(JobController::reconcile_jobs_endpoint_handler),
+        )))
         .with_state(application_state)
 }
 
@@ -86,25 +213,38 @@ pub async fn start_application(application: Application) -> Result<()> {
     let shutdown = CancellationToken::new();
     let Application {
         consumer,
+        outbox_relay,
+        job_event_notifier,
+        callback_dispatcher,
+        job_definition_scheduler,
         http_server,
     } = application;
 
     let handles = http_server
         .start(&shutdown)
         .into_iter()
-        .chain(consumer.start(&shutdown));
+        .chain(consumer.start(&shutdown))
+        .chain(outbox_relay.start(&shutdown))
+        .chain(job_event_notifier.start(&shutdown))
+        .chain(callback_dispatcher.start(&shutdown))
+        .chain(job_definition_scheduler.start(&shutdown));
 
     let services = try_join_all(handles.map(|handle| async move { handle.await? }));
     let signal = wait_for_shutdown_signal(shutdown.clone());
     tokio::pin!(services, signal);
 
-    tokio::select! {
+    let result = tokio::select! {
         res = &mut services => {
             shutdown.cancel();
             first_error(res.map(|_| ()), signal.await)
         }
         res = &mut signal => first_error(res, services.await.map(|_| ())),
-    }
+    };
+
+    // Every service is stopped, deliver what is left in the producer queue
+    flush_producer(PRODUCER_FLUSH_TIMEOUT).await;
+
+    result
 }
 
 fn first_error(primary: Result<()>, secondary: Result<()>) -> Result<()> {
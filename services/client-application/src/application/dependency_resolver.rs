@@ -0,0 +1,210 @@
+use crate::database::database_client::DatabaseClient;
+use crate::database::model::ResultWrite;
+use crate::domain::job::Job;
+use crate::domain::operation::OperationError;
+use crate::domain::operation::OperationOutcome;
+use crate::messaging::producer::MessageProducer;
+use anyhow::Result;
+use common::counter;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+counter!(
+    RELEASED_OPERATIONS_COUNTER,
+    "dependency_resolver_released_operations",
+    "Number of operations dispatched once the results they reference were stored"
+);
+counter!(
+    FAILED_OPERATIONS_COUNTER,
+    "dependency_resolver_failed_operations",
+    "Number of operations failed because a result they reference did not succeed"
+);
+
+/// State of the inputs of an operation referencing other results.
+#[derive(Debug, PartialEq, Eq)]
+enum Resolution {
+    /// Every input succeeded, with these values by line.
+    Ready(BTreeMap<u64, String>),
+    /// The input on this line did not succeed.
+    Failed(u64),
+    /// Some inputs have no result yet.
+    Waiting,
+}
+
+/// Dispatches the operations referencing other results once those are
+/// stored.
+///
+/// It runs after every stored result: the operations waiting on it are
+/// dispatched with the values of their inputs once all of them succeeded, or
+/// failed along with the ones waiting on them as soon as one did not. A
+/// result redelivered before its dependents were marked as dispatched
+/// releases them again, the duplicated results being discarded.
+pub struct DependencyResolver {
+    database_client: Arc<DatabaseClient>,
+    message_producer: Arc<MessageProducer>,
+}
+
+impl DependencyResolver {
+    pub const fn new(
+        database_client: Arc<DatabaseClient>,
+        message_producer: Arc<MessageProducer>,
+    ) -> Self {
+        Self {
+            database_client,
+            message_producer,
+        }
+    }
+
+    /// Releases the operations of `job_id` waiting on the result of `line`,
+    /// dispatched with the `attempt` that produced it.
+    #[tracing::instrument(skip(self))]
+    pub async fn release_dependents(&self, job_id: &str, line: u64, attempt: u32) -> Result<()> {
+        let operation_repository = self.database_client.operation_repository();
+        let mut job: Option<Job> = None;
+        let mut finished_lines = vec![line];
+
+        while let Some(line) = finished_lines.pop() {
+            for dependent in operation_repository
+                .get_waiting_dependents(job_id, line)
+                .await?
+            {
+                let inputs = operation_repository
+                    .get_operations_by_line(job_id, dependent.dependencies())
+                    .await?;
+                let resolution = Self::resolve(
+                    dependent.dependencies(),
+                    inputs
+                        .iter()
+                        .filter_map(|input| Some((input.line()?, input.result()))),
+                );
+
+                match resolution {
+                    Resolution::Waiting => {}
+                    Resolution::Failed(failed_line) => {
+                        let outcome = OperationOutcome::Failed {
+                            error: OperationError::dependency(failed_line),
+                        };
+                        let write = self
+                            .database_client
+                            .store_result(job_id, &dependent.id(), &outcome, attempt)
+                            .await?;
+                        if let ResultWrite::Applied { .. } = write {
+                            FAILED_OPERATIONS_COUNTER.add(1, &[]);
+
+                            // Its own dependents cannot succeed either
+                            finished_lines.extend(dependent.line());
+                        }
+                    }
+                    Resolution::Ready(values) => {
+                        // Loaded once, for its priority, variables and kind
+                        let job = match job {
+                            Some(ref job) => job,
+                            None => job.insert(
+                                self.database_client
+                                    .job_repository()
+                                    .get_job(job_id)
+                                    .await?,
+                            ),
+                        };
+
+                        self.message_producer
+                            .send_resolved_operation_request(
+                                &dependent,
+                                values,
+                                attempt,
+                                job.priority(),
+                                job.variables(),
+                                job.kind(),
+                            )
+                            .await?;
+                        operation_repository
+                            .mark_operations_dispatched(&[dependent.id()])
+                            .await?;
+
+                        RELEASED_OPERATIONS_COUNTER.add(1, &[]);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the `dependencies` of an operation from the results of its
+    /// inputs, by line.
+    fn resolve<'a>(
+        dependencies: &[u64],
+        inputs: impl IntoIterator<Item = (u64, Option<&'a OperationOutcome>)>,
+    ) -> Resolution {
+        let results: BTreeMap<_, _> = inputs.into_iter().collect();
+
+        let mut values = BTreeMap::new();
+        let mut waiting = false;
+        for line in dependencies {
+            match results.get(line).copied().flatten() {
+                Some(OperationOutcome::Succeeded { value }) => {
+                    values.insert(*line, value.clone());
+                }
+                Some(OperationOutcome::Failed { .. } | OperationOutcome::Cancelled) => {
+                    return Resolution::Failed(*line);
+                }
+                None => waiting = true,
+            }
+        }
+
+        if waiting {
+            Resolution::Waiting
+        } else {
+            Resolution::Ready(values)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DependencyResolver;
+    use super::Resolution;
+    use crate::domain::operation::OperationError;
+    use crate::domain::operation::OperationOutcome;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn resolution_fails_on_a_failed_input_even_while_waiting() {
+        // Arrange
+        let succeeded = OperationOutcome::Succeeded {
+            value: "2".to_string(),
+        };
+        let failed = OperationOutcome::Failed {
+            error: OperationError::dependency(1),
+        };
+
+        // Act
+        let resolution = DependencyResolver::resolve(
+            &[1, 2, 3],
+            [(1, Some(&succeeded)), (2, None), (3, Some(&failed))],
+        );
+
+        // Assert
+        assert_eq!(resolution, Resolution::Failed(3));
+    }
+
+    #[test]
+    fn resolution_is_ready_once_every_input_succeeded() {
+        // Arrange
+        let two = OperationOutcome::Succeeded {
+            value: "2".to_string(),
+        };
+        let three = OperationOutcome::Succeeded {
+            value: "3".to_string(),
+        };
+
+        // Act
+        let resolution = DependencyResolver::resolve(&[1, 3], [(1, Some(&two)), (3, Some(&three))]);
+
+        // Assert
+        assert_eq!(
+            resolution,
+            Resolution::Ready(BTreeMap::from([(1, "2".to_string()), (3, "3".to_string())]))
+        );
+    }
+}
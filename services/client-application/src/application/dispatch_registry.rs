@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+/// Keeps a cancellation token for every job whose operations are being
+/// dispatched by this instance, so a cancellation can stop the dispatch.
+#[derive(Default)]
+pub struct DispatchRegistry {
+    dispatches: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl DispatchRegistry {
+    /// Registers the dispatch of `job_id`. The returned token also fires
+    /// when `parent` is cancelled.
+    pub fn register(&self, job_id: &str, parent: &CancellationToken) -> CancellationToken {
+        let token = parent.child_token();
+        self.dispatches
+            .lock()
+            .expect("Dispatch registry lock poisoned")
+            .insert(job_id.to_string(), token.clone());
+
+        token
+    }
+
+    pub fn unregister(&self, job_id: &str) {
+        self.dispatches
+            .lock()
+            .expect("Dispatch registry lock poisoned")
+            .remove(job_id);
+    }
+
+    pub fn cancel(&self, job_id: &str) {
+        let token = self
+            .dispatches
+            .lock()
+            .expect("Dispatch registry lock poisoned")
+            .remove(job_id);
+
+        if let Some(token) = token {
+            tracing::info!("Cancelling the running dispatch of job {job_id}");
+
+            token.cancel();
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use crate::database::database_client::DatabaseClient;
+use crate::database::model::OutboxRecord;
+use crate::database::outbox_relay::OutboxRelay;
+use crate::domain;
+use crate::domain::job_definition::JobDefinition;
+use anyhow::Result;
+use common::counter;
+use mongodb::bson::DateTime;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+counter!(
+    CREATED_JOBS_COUNTER,
+    "job_definition_scheduler_created_jobs",
+    "Number of jobs created from job definitions"
+);
+counter!(
+    SCHEDULER_ERROR_COUNTER,
+    "job_definition_scheduler_errors",
+    "Number of errors encountered by the job definition scheduler"
+);
+
+/// Background scheduler creating a job from each job definition whose cron
+/// expression fires.
+///
+/// Every instance runs the scheduler. A due definition is leased the same way
+/// as the outbox records, and the job is created in the same transaction that
+/// moves the definition to its next run, so a run is never created twice,
+/// even by a scheduler losing its lease halfway through.
+pub struct JobDefinitionScheduler {
+    database_client: Arc<DatabaseClient>,
+    outbox_relay: Arc<OutboxRelay>,
+}
+
+impl JobDefinitionScheduler {
+    const LEASE_DURATION: Duration = Duration::from_secs(30);
+    const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub fn new(database_client: Arc<DatabaseClient>, outbox_relay: Arc<OutboxRelay>) -> Self {
+        tracing::debug!("Initializing the job definition scheduler");
+
+        Self {
+            database_client,
+            outbox_relay,
+        }
+    }
+
+    pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
+        tracing::debug!("Start the job definition scheduler");
+
+        let database_client = Arc::clone(&self.database_client);
+        let outbox_relay = Arc::clone(&self.outbox_relay);
+        let shutdown = shutdown.clone();
+
+        vec![tokio::spawn(async move {
+            Self::worker_scheduler(database_client, outbox_relay, shutdown).await;
+            Ok(())
+        })]
+    }
+
+    async fn worker_scheduler(
+        database_client: Arc<DatabaseClient>,
+        outbox_relay: Arc<OutboxRelay>,
+        shutdown: CancellationToken,
+    ) {
+        let owner = common::application::instance_id();
+
+        loop {
+            let claimed = tokio::select! {
+                () = shutdown.cancelled() => return,
+                result = database_client
+                    .job_definition_repository()
+                    .claim_due_definition(owner, Self::LEASE_DURATION) => result,
+            };
+            match claimed {
+                Ok(Some(definition)) => {
+                    match Self::run_definition(&definition, &database_client).await {
+                        Ok(true) => outbox_relay.wake(),
+                        Ok(false) => {}
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to run job definition {}: {err}",
+                                definition.id()
+                            );
+
+                            SCHEDULER_ERROR_COUNTER.add(1, &[]);
+                        }
+                    }
+
+                    // Look for another definition right away
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::error!("Failed to claim a job definition: {err}");
+
+                    SCHEDULER_ERROR_COUNTER.add(1, &[]);
+                }
+            }
+
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Creates the job of the due run of `definition`. Returns `false` when
+    /// the run was recorded by another scheduler in the meantime.
+    #[tracing::instrument(skip_all, fields(definition_id = definition.id()))]
+    async fn run_definition(
+        definition: &JobDefinition,
+        database_client: &DatabaseClient,
+    ) -> Result<bool> {
+        let owner = common::application::instance_id();
+        let definition_id = definition.id();
+        let next_run_at = definition.next_run_after(DateTime::now())?;
+
+        let new_job = domain::job::Job::new(definition.operations().len())?
+            .with_name(Some(definition.name().to_string()))
+            .with_labels(definition.labels().clone())
+            .with_priority(definition.priority())
+            .with_variables(definition.variables().clone())
+            .with_kind(definition.kind().map(str::to_string))
+            .with_definition_id(&definition_id);
+
+        let mut session = database_client.start_transaction().await?;
+
+        let job_id = database_client
+            .job_repository()
+            .insert_job(&new_job, &mut session)
+            .await?;
+
+        let new_operations: Vec<_> = definition
+            .operations()
+            .iter()
+            .zip(1..)
+            .map(|(request, line)| domain::operation::Operation::new(&job_id, line, request))
+            .collect();
+
+        database_client
+            .operation_repository()
+            .insert_operations(&new_operations, &mut session)
+            .await?;
+
+        database_client
+            .outbox_repository()
+            .insert_record(
+                &OutboxRecord::new(&job_id, new_job.priority())
+                    .with_variables(new_job.variables().clone())
+                    .with_kind(new_job.kind().map(str::to_string)),
+                &mut session,
+            )
+            .await?;
+
+        // The session is dropped without a commit, which aborts the transaction
+        if !database_client
+            .job_definition_repository()
+            .record_run(
+                &definition_id,
+                owner,
+                definition.next_run_at(),
+                &job_id,
+                next_run_at,
+                &mut session,
+            )
+            .await?
+        {
+            tracing::warn!("Job definition {definition_id} is no longer owned");
+
+            return Ok(false);
+        }
+
+        session.commit_transaction().await?;
+
+        tracing::info!("Created job {job_id} from job definition {definition_id}");
+
+        CREATED_JOBS_COUNTER.add(1, &[]);
+
+        Ok(true)
+    }
+}
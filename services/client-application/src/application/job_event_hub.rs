@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Fans the job events received by this instance out to the local
+/// subscribers, such as the progress streams opened on the job.
+#[derive(Default)]
+pub struct JobEventHub {
+    channels: Mutex<HashMap<String, broadcast::Sender<()>>>,
+}
+
+impl JobEventHub {
+    // Events carry no data, so a lagging subscriber only needs to know it missed some
+    const CHANNEL_CAPACITY: usize = 1;
+
+    pub fn subscribe(self: &Arc<Self>, job_id: &str) -> JobEventSubscription {
+        let receiver = self
+            .channels
+            .lock()
+            .expect("Job event hub lock poisoned")
+            .entry(job_id.to_string())
+            .or_insert_with(|| broadcast::channel(Self::CHANNEL_CAPACITY).0)
+            .subscribe();
+
+        JobEventSubscription {
+            hub: Arc::clone(self),
+            job_id: job_id.to_string(),
+            receiver,
+        }
+    }
+
+    pub fn notify(&self, job_id: &str) {
+        let channels = self.channels.lock().expect("Job event hub lock poisoned");
+        if let Some(sender) = channels.get(job_id) {
+            // Sending only fails without subscribers, which is fine
+            let _ = sender.send(());
+        }
+    }
+
+    fn release(&self, job_id: &str) {
+        let mut channels = self.channels.lock().expect("Job event hub lock poisoned");
+        // The subscription being released still holds its receiver
+        if channels
+            .get(job_id)
+            .is_some_and(|sender| sender.receiver_count() <= 1)
+        {
+            channels.remove(job_id);
+        }
+    }
+}
+
+pub struct JobEventSubscription {
+    hub: Arc<JobEventHub>,
+    job_id: String,
+    receiver: broadcast::Receiver<()>,
+}
+
+impl JobEventSubscription {
+    /// Waits for the next event of the job.
+    pub async fn changed(&mut self) {
+        match self.receiver.recv().await {
+            Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            // The hub keeps the sender as long as the subscription lives
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for JobEventSubscription {
+    fn drop(&mut self) {
+        self.hub.release(&self.job_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JobEventHub;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn subscription_wakes_up_on_its_job_events() {
+        // Arrange
+        let hub = Arc::new(JobEventHub::default());
+        let mut subscription = hub.subscribe("job");
+
+        // Act
+        hub.notify("other-job");
+        hub.notify("job");
+
+        // Assert
+        tokio::time::timeout(Duration::from_secs(1), subscription.changed())
+            .await
+            .expect("The subscription should have been notified");
+    }
+
+    #[test]
+    fn last_subscription_releases_its_channel() {
+        // Arrange
+        let hub = Arc::new(JobEventHub::default());
+        let first = hub.subscribe("job");
+        let second = hub.subscribe("job");
+
+        // Act
+        drop(first);
+        let released_early = hub.channels.lock().unwrap().is_empty();
+        drop(second);
+
+        // Assert
+        assert!(!released_early);
+        assert!(hub.channels.lock().unwrap().is_empty());
+    }
+}
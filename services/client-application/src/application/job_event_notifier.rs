@@ -0,0 +1,91 @@
+use crate::messaging::producer::MessageProducer;
+use anyhow::Result;
+use common::counter;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+counter!(
+    JOB_EVENT_ERROR_COUNTER,
+    "job_event_notifier_errors",
+    "Number of errors encountered while publishing job events"
+);
+
+/// Publishes the changes of the jobs to every client instance.
+///
+/// The changes are coalesced: a job changing many times during the publish
+/// interval results in a single event, so a large job does not produce an
+/// event per operation result.
+pub struct JobEventNotifier {
+    message_producer: Arc<MessageProducer>,
+    pending: Arc<Mutex<HashSet<String>>>,
+}
+
+impl JobEventNotifier {
+    const PUBLISH_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new(message_producer: Arc<MessageProducer>) -> Self {
+        tracing::debug!("Initializing the job event notifier");
+
+        Self {
+            message_producer,
+            pending: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn notify(&self, job_id: &str) {
+        self.pending
+            .lock()
+            .expect("Job event notifier lock poisoned")
+            .insert(job_id.to_string());
+    }
+
+    pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
+        tracing::debug!("Start the job event notifier");
+
+        let message_producer = Arc::clone(&self.message_producer);
+        let pending = Arc::clone(&self.pending);
+        let shutdown = shutdown.clone();
+
+        vec![tokio::spawn(async move {
+            Self::worker_notifier(message_producer, pending, shutdown).await;
+            Ok(())
+        })]
+    }
+
+    async fn worker_notifier(
+        message_producer: Arc<MessageProducer>,
+        pending: Arc<Mutex<HashSet<String>>>,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            let stopping = tokio::select! {
+                () = shutdown.cancelled() => true,
+                () = tokio::time::sleep(Self::PUBLISH_INTERVAL) => false,
+            };
+
+            let job_ids =
+                std::mem::take(&mut *pending.lock().expect("Job event notifier lock poisoned"))
+                    .into_iter()
+                    .collect::<Vec<_>>();
+
+            if !job_ids.is_empty()
+                && let Err(err) = message_producer.send_job_events(&job_ids).await
+            {
+                tracing::error!(
+                    "Failed to publish the events of {} jobs: {err}",
+                    job_ids.len()
+                );
+
+                JOB_EVENT_ERROR_COUNTER.add(1, &[]);
+            }
+
+            if stopping {
+                return;
+            }
+        }
+    }
+}
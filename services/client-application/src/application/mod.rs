@@ -1,3 +1,10 @@
+pub mod callback_dispatcher;
 pub mod context;
+pub mod dependency_resolver;
+pub mod dispatch_registry;
+pub mod job_definition_scheduler;
+pub mod job_event_hub;
+pub mod job_event_notifier;
+pub mod progress_reconciler;
 
 pub const APPLICATION_NAME: &str = "client-application";
@@ -0,0 +1,119 @@
+use crate::database::database_client::DatabaseClient;
+use crate::database::model::Keyset;
+use anyhow::Result;
+use common::counter;
+use mongodb::bson::oid::ObjectId;
+use std::sync::Arc;
+
+counter!(
+    RECONCILED_JOBS_COUNTER,
+    "progress_reconciler_reconciled_jobs",
+    "Number of jobs whose counters were rebuilt"
+);
+
+/// Rebuilds the counters stored on the jobs from their operations.
+///
+/// The counters are updated along with each result, so they only drift for
+/// the jobs stored before they existed or edited by hand. Counting a job
+/// takes a scan of its operations, so this is a repair to run on demand
+/// rather than on every read. The counters are only overwritten while they
+/// are still the ones read before the count, and the job is counted again
+/// when a result was stored meanwhile.
+pub struct ProgressReconciler {
+    database_client: Arc<DatabaseClient>,
+}
+
+impl ProgressReconciler {
+    const PAGE_SIZE: u32 = 100;
+    // A job receiving results faster than it is counted is left to a later run
+    const MAX_ATTEMPTS: u32 = 5;
+
+    pub const fn new(database_client: Arc<DatabaseClient>) -> Self {
+        Self { database_client }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn reconcile_job(&self, job_id: &str) -> Result<()> {
+        self.reconcile(job_id).await
+    }
+
+    /// Reconciles every job, page by page, and returns how many there were.
+    #[tracing::instrument(skip(self))]
+    pub async fn reconcile_jobs(&self) -> Result<usize> {
+        tracing::info!("Reconciling the counters of every job");
+
+        let mut reconciled_jobs = 0;
+        // Every job comes after the smallest id
+        let mut keyset = Keyset::After(ObjectId::from_bytes([0; 12]).to_hex());
+
+        loop {
+            let jobs = self
+                .database_client
+                .job_repository()
+                .get_jobs_page(&keyset, Self::PAGE_SIZE)
+                .await?;
+
+            for job in jobs.items() {
+                self.reconcile(&job.id()).await?;
+            }
+            reconciled_jobs += jobs.items().len();
+
+            match jobs.items().last() {
+                Some(last) if jobs.has_next() => keyset = Keyset::After(last.id()),
+                _ => break,
+            }
+        }
+
+        tracing::info!("Reconciled the counters of {reconciled_jobs} jobs");
+
+        Ok(reconciled_jobs)
+    }
+
+    async fn reconcile(&self, job_id: &str) -> Result<()> {
+        for attempt in 1..=Self::MAX_ATTEMPTS {
+            let job = self
+                .database_client
+                .job_repository()
+                .get_job(job_id)
+                .await?;
+
+            let completed_operations = self
+                .database_client
+                .operation_repository()
+                .get_total_completed_operations(job_id)
+                .await?;
+            let failed_operations = self
+                .database_client
+                .operation_repository()
+                .get_total_failed_operations(job_id)
+                .await?;
+
+            if completed_operations != job.completed_operations()
+                || failed_operations != job.failed_operations()
+            {
+                tracing::warn!(
+                    "Counters of job {job_id} drifted: {} completed and {} failed stored, {completed_operations} and {failed_operations} counted",
+                    job.completed_operations(),
+                    job.failed_operations()
+                );
+            }
+
+            if self
+                .database_client
+                .job_repository()
+                .reset_progress(&job, completed_operations, failed_operations)
+                .await?
+            {
+                RECONCILED_JOBS_COUNTER.add(1, &[]);
+
+                return Ok(());
+            }
+
+            tracing::debug!("Job {job_id} received results while counted, attempt {attempt}");
+        }
+
+        anyhow::bail!(
+            "Failed to reconcile job {job_id}: results kept coming in while it was counted"
+        )
+    }
+}
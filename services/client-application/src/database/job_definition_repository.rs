@@ -0,0 +1,306 @@
+use crate::database;
+use crate::domain;
+use anyhow::Result;
+use common::counter;
+use futures::TryStreamExt as _;
+use mongodb::ClientSession;
+use mongodb::Collection;
+use mongodb::IndexModel;
+use mongodb::bson::DateTime;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use mongodb::options::ReturnDocument;
+use std::time::Duration;
+
+counter!(
+    INSERT_JOB_DEFINITION_COUNTER,
+    "database_insert_job_definition_requests",
+    "Number of insert job definition requests"
+);
+counter!(
+    GET_JOB_DEFINITION_COUNTER,
+    "database_get_job_definition_requests",
+    "Number of get job definition requests"
+);
+counter!(
+    GET_JOB_DEFINITIONS_COUNTER,
+    "database_get_job_definitions_requests",
+    "Number of get job definitions requests"
+);
+counter!(
+    PAUSE_JOB_DEFINITION_COUNTER,
+    "database_pause_job_definition_requests",
+    "Number of pause job definition requests"
+);
+counter!(
+    RESUME_JOB_DEFINITION_COUNTER,
+    "database_resume_job_definition_requests",
+    "Number of resume job definition requests"
+);
+counter!(
+    DELETE_JOB_DEFINITION_COUNTER,
+    "database_delete_job_definition_requests",
+    "Number of delete job definition requests"
+);
+counter!(
+    CLAIM_JOB_DEFINITION_COUNTER,
+    "database_claim_job_definition_requests",
+    "Number of claim job definition requests"
+);
+counter!(
+    RECORD_JOB_DEFINITION_RUN_COUNTER,
+    "database_record_job_definition_run_requests",
+    "Number of record job definition run requests"
+);
+
+pub struct JobDefinitionRepository {
+    collection: Collection<domain::job_definition::JobDefinition>,
+}
+
+impl JobDefinitionRepository {
+    pub const COLLECTION_NAME: &'static str = "job_definition";
+
+    const ID_FIELD: &'static str = "_id";
+    const PAUSED_FIELD: &'static str = "paused";
+    const NEXT_RUN_AT_FIELD: &'static str = "next_run_at";
+    const LAST_RUN_AT_FIELD: &'static str = "last_run_at";
+    const LAST_JOB_ID_FIELD: &'static str = "last_job_id";
+    const RUNS_FIELD: &'static str = "runs";
+    const LEASE_OWNER_FIELD: &'static str = "lease_owner";
+    const LEASE_EXPIRES_AT_FIELD: &'static str = "lease_expires_at";
+
+    pub async fn new(
+        collection: Collection<domain::job_definition::JobDefinition>,
+    ) -> Result<Self> {
+        tracing::debug!("Initializing the MongoDB job definition repository");
+
+        let next_run_index = IndexModel::builder()
+            .keys(doc! { Self::PAUSED_FIELD: 1, Self::NEXT_RUN_AT_FIELD: 1 })
+            .build();
+        collection.create_index(next_run_index).await?;
+
+        Ok(Self { collection })
+    }
+
+    #[tracing::instrument(skip(self, definition))]
+    pub async fn insert_definition(
+        &self,
+        definition: &domain::job_definition::JobDefinition,
+    ) -> Result<String> {
+        tracing::debug!("Inserting job definition {}", definition.name());
+
+        INSERT_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let result = self.collection.insert_one(definition).await?;
+
+        Ok(result
+            .inserted_id
+            .as_object_id()
+            .expect("No ObjectId returned")
+            .to_string())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_definition(
+        &self,
+        definition_id: &str,
+    ) -> Result<domain::job_definition::JobDefinition> {
+        tracing::debug!("Getting job definition with id: {definition_id}");
+
+        GET_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .find_one(doc! {Self::ID_FIELD: ObjectId::parse_str(definition_id)?})
+            .await?;
+
+        if let Some(result) = result {
+            Ok(result)
+        } else {
+            anyhow::bail!("Document not found");
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_definitions(
+        &self,
+        page: u32,
+        page_size: u32,
+    ) -> Result<database::model::PageSubset<domain::job_definition::JobDefinition>> {
+        tracing::debug!("Getting job definitions");
+
+        GET_JOB_DEFINITIONS_COUNTER.add(1, &[]);
+
+        let skip = u64::from(page - 1) * u64::from(page_size);
+
+        let mut cursor = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { Self::ID_FIELD: 1 })
+            .limit(i64::from(page_size))
+            .skip(skip)
+            .await?;
+
+        let mut definitions = Vec::new();
+        while let Some(doc) = cursor.try_next().await? {
+            definitions.push(doc);
+        }
+
+        let total = self
+            .collection
+            .count_documents(doc! {})
+            .await
+            .map(usize::try_from)??;
+
+        Ok(database::model::PageSubset::new(total, definitions))
+    }
+
+    /// Stops the definition from firing until it is resumed. Returns `false`
+    /// when the definition does not exist.
+    #[tracing::instrument(skip(self))]
+    pub async fn pause_definition(&self, definition_id: &str) -> Result<bool> {
+        tracing::debug!("Pausing job definition {definition_id}");
+
+        PAUSE_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! { Self::ID_FIELD: ObjectId::parse_str(definition_id)? },
+                doc! { "$set": doc! { Self::PAUSED_FIELD: true } },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    /// Lets the definition fire again from `next_run_at`, the runs missed
+    /// while paused being skipped. Returns `false` when the definition does
+    /// not exist.
+    #[tracing::instrument(skip(self))]
+    pub async fn resume_definition(
+        &self,
+        definition_id: &str,
+        next_run_at: DateTime,
+    ) -> Result<bool> {
+        tracing::debug!("Resuming job definition {definition_id}");
+
+        RESUME_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! { Self::ID_FIELD: ObjectId::parse_str(definition_id)? },
+                doc! {
+                    "$set": doc! {
+                        Self::PAUSED_FIELD: false,
+                        Self::NEXT_RUN_AT_FIELD: next_run_at,
+                    }
+                },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    /// Deletes the definition, the jobs it created being kept.
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_definition(&self, definition_id: &str) -> Result<()> {
+        tracing::debug!("Deleting job definition with id {definition_id}");
+
+        DELETE_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .delete_one(doc! {Self::ID_FIELD: ObjectId::parse_str(definition_id)?})
+            .await?;
+
+        if result.deleted_count == 0 {
+            anyhow::bail!("Document not found");
+        }
+
+        Ok(())
+    }
+
+    /// Takes a lease on the most overdue definition that is not paused, when
+    /// no live scheduler owns it. The lease is released by
+    /// [`Self::record_run`], or left to expire on failure so that another
+    /// scheduler retries the run.
+    #[tracing::instrument(skip(self))]
+    pub async fn claim_due_definition(
+        &self,
+        owner: &str,
+        lease: Duration,
+    ) -> Result<Option<domain::job_definition::JobDefinition>> {
+        tracing::trace!("Claiming a due job definition");
+
+        CLAIM_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let now = DateTime::now();
+        let result = self
+            .collection
+            .find_one_and_update(
+                doc! {
+                    Self::PAUSED_FIELD: { "$ne": true },
+                    Self::NEXT_RUN_AT_FIELD: { "$lte": now },
+                    "$or": [
+                        { Self::LEASE_EXPIRES_AT_FIELD: { "$exists": false } },
+                        { Self::LEASE_EXPIRES_AT_FIELD: { "$lt": now } },
+                    ]
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::LEASE_OWNER_FIELD: owner,
+                        Self::LEASE_EXPIRES_AT_FIELD: now.saturating_add_duration(lease),
+                    }
+                },
+            )
+            .sort(doc! { Self::NEXT_RUN_AT_FIELD: 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Records the job created for the run due at `scheduled_at`, moves the
+    /// definition to its next run and releases the lease. Returns `false`
+    /// when the lease was lost or the run already recorded, in which case
+    /// the transaction must not be committed.
+    #[tracing::instrument(skip(self, session))]
+    pub async fn record_run(
+        &self,
+        definition_id: &str,
+        owner: &str,
+        scheduled_at: DateTime,
+        job_id: &str,
+        next_run_at: DateTime,
+        session: &mut ClientSession,
+    ) -> Result<bool> {
+        tracing::debug!("Recording the run of job definition {definition_id}");
+
+        RECORD_JOB_DEFINITION_RUN_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(definition_id)?,
+                    Self::LEASE_OWNER_FIELD: owner,
+                    Self::NEXT_RUN_AT_FIELD: scheduled_at,
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::NEXT_RUN_AT_FIELD: next_run_at,
+                        Self::LAST_RUN_AT_FIELD: DateTime::now(),
+                        Self::LAST_JOB_ID_FIELD: job_id,
+                    },
+                    "$inc": doc! { Self::RUNS_FIELD: 1 },
+                    "$unset": doc! { Self::LEASE_OWNER_FIELD: "", Self::LEASE_EXPIRES_AT_FIELD: "" },
+                },
+            )
+            .session(session)
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+}
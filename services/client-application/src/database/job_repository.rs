@@ -3,20 +3,63 @@ use crate::domain;
 use anyhow::Result;
 use common::counter;
 use futures::TryStreamExt;
+use mongodb::ClientSession;
 use mongodb::Collection;
+use mongodb::IndexModel;
+use mongodb::bson::Bson;
+use mongodb::bson::DateTime;
+use mongodb::bson::Document;
 use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
+use mongodb::bson::to_bson;
+use mongodb::options::ReturnDocument;
+use std::time::Duration;
 
 counter!(
     INSERT_JOB_COUNTER,
     "database_insert_job_requests",
     "Number of insert job requests"
 );
+counter!(
+    START_JOB_UPLOAD_COUNTER,
+    "database_start_job_upload_requests",
+    "Number of start job upload requests"
+);
+counter!(
+    FINISH_JOB_UPLOAD_COUNTER,
+    "database_finish_job_upload_requests",
+    "Number of finish job upload requests"
+);
+counter!(
+    START_SCHEDULED_JOB_COUNTER,
+    "database_start_scheduled_job_requests",
+    "Number of start scheduled job requests"
+);
 counter!(
     DELETE_JOB_COUNTER,
     "database_delete_job_requests",
     "Number of delete job requests"
 );
+counter!(
+    CANCEL_JOB_COUNTER,
+    "database_cancel_job_requests",
+    "Number of cancel job requests"
+);
+counter!(
+    FAIL_JOB_COUNTER,
+    "database_fail_job_requests",
+    "Number of fail job requests"
+);
+counter!(
+    RECORD_JOB_PROGRESS_COUNTER,
+    "database_record_job_progress_requests",
+    "Number of record job progress requests"
+);
+counter!(
+    RESET_JOB_PROGRESS_COUNTER,
+    "database_reset_job_progress_requests",
+    "Number of reset job progress requests"
+);
 counter!(
     GET_JOB_COUNTER,
     "database_get_job_requests",
@@ -27,6 +70,31 @@ counter!(
     "database_get_jobs_requests",
     "Number of get jobs requests"
 );
+counter!(
+    GET_JOBS_PAGE_COUNTER,
+    "database_get_jobs_page_requests",
+    "Number of get jobs page requests"
+);
+counter!(
+    ESTIMATE_TOTAL_JOBS_COUNTER,
+    "database_estimate_total_jobs_requests",
+    "Number of estimate total jobs requests"
+);
+counter!(
+    CLAIM_CALLBACK_COUNTER,
+    "database_claim_callback_requests",
+    "Number of claim callback requests"
+);
+counter!(
+    DEFER_CALLBACK_COUNTER,
+    "database_defer_callback_requests",
+    "Number of defer callback requests"
+);
+counter!(
+    RECORD_CALLBACK_DELIVERY_COUNTER,
+    "database_record_callback_delivery_requests",
+    "Number of record callback delivery requests"
+);
 
 pub struct JobRepository {
     collection: Collection<domain::job::Job>,
@@ -36,19 +104,109 @@ impl JobRepository {
     pub const COLLECTION_NAME: &'static str = "job";
 
     const ID_FIELD: &'static str = "_id";
+    const NAME_FIELD: &'static str = "name";
+    const LABELS_FIELD: &'static str = "labels";
+    const CREATED_AT_FIELD: &'static str = "created_at";
+    const COMPLETED_OPERATIONS_FIELD: &'static str = "completed_operations";
+    const FAILED_OPERATIONS_FIELD: &'static str = "failed_operations";
+    const OPERATIONS_FIELD: &'static str = "operations";
+    const UPLOADING_FIELD: &'static str = "uploading";
+    const SCHEDULED_FIELD: &'static str = "scheduled";
+    const CANCELLED_FIELD: &'static str = "cancelled";
+    const STATUS_FIELD: &'static str = "status";
+    const ERROR_FIELD: &'static str = "error";
+    const STARTED_AT_FIELD: &'static str = "started_at";
+    const FINISHED_AT_FIELD: &'static str = "finished_at";
+    const DEFINITION_ID_FIELD: &'static str = "definition_id";
+    const CALLBACK_FIELD: &'static str = "callback";
+    const CALLBACK_STATE_FIELD: &'static str = "callback.state";
+    const CALLBACK_ATTEMPTS_FIELD: &'static str = "callback.attempts";
+    const CALLBACK_NEXT_ATTEMPT_AT_FIELD: &'static str = "callback.next_attempt_at";
+    const CALLBACK_LEASE_OWNER_FIELD: &'static str = "callback.lease_owner";
+    const CALLBACK_LEASE_EXPIRES_AT_FIELD: &'static str = "callback.lease_expires_at";
+    const CALLBACK_DELIVERIES_FIELD: &'static str = "callback.deliveries";
 
-    pub fn new(collection: Collection<domain::job::Job>) -> Self {
+    pub async fn new(collection: Collection<domain::job::Job>) -> Result<Self> {
         tracing::debug!("Initializing the MongoDB job repository");
 
-        Self { collection }
+        // Sparse, since most jobs come without a callback
+        let callback_index = IndexModel::builder()
+            .keys(doc! { Self::CALLBACK_STATE_FIELD: 1, Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD: 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .sparse(true)
+                    .build(),
+            )
+            .build();
+        collection.create_index(callback_index).await?;
+
+        let created_at_index = IndexModel::builder()
+            .keys(doc! { Self::CREATED_AT_FIELD: 1, Self::ID_FIELD: 1 })
+            .build();
+        collection.create_index(created_at_index).await?;
+
+        let operations_index = IndexModel::builder()
+            .keys(doc! { Self::OPERATIONS_FIELD: 1, Self::ID_FIELD: 1 })
+            .build();
+        collection.create_index(operations_index).await?;
+
+        let status_index = IndexModel::builder()
+            .keys(doc! { Self::STATUS_FIELD: 1, Self::ID_FIELD: 1 })
+            .build();
+        collection.create_index(status_index).await?;
+
+        let name_index = IndexModel::builder()
+            .keys(doc! { Self::NAME_FIELD: 1 })
+            .build();
+        collection.create_index(name_index).await?;
+
+        // Sparse, since most jobs are not created from a definition
+        let definition_index = IndexModel::builder()
+            .keys(doc! { Self::DEFINITION_ID_FIELD: 1, Self::CREATED_AT_FIELD: 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .sparse(true)
+                    .build(),
+            )
+            .build();
+        collection.create_index(definition_index).await?;
+
+        // Labels are free-form, hence a wildcard index
+        let labels_index = IndexModel::builder()
+            .keys(doc! { format!("{}.$**", Self::LABELS_FIELD): 1 })
+            .build();
+        collection.create_index(labels_index).await?;
+
+        Ok(Self { collection })
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn insert_job(&self, job: &domain::job::Job) -> Result<String> {
+    #[tracing::instrument(skip(self, session))]
+    pub async fn insert_job(
+        &self,
+        job: &domain::job::Job,
+        session: &mut ClientSession,
+    ) -> Result<String> {
         tracing::debug!("Inserting a job");
 
         INSERT_JOB_COUNTER.add(1, &[]);
 
+        let result = self.collection.insert_one(job).session(session).await?;
+
+        Ok(result
+            .inserted_id
+            .as_object_id()
+            .expect("No ObjectId returned")
+            .to_string())
+    }
+
+    /// Inserts a job whose operations are about to be uploaded, outside of
+    /// any transaction since the upload can be arbitrarily long.
+    #[tracing::instrument(skip(self))]
+    pub async fn start_upload(&self, job: &domain::job::Job) -> Result<String> {
+        tracing::debug!("Inserting an uploading job");
+
+        START_JOB_UPLOAD_COUNTER.add(1, &[]);
+
         let result = self.collection.insert_one(job).await?;
 
         Ok(result
@@ -58,6 +216,72 @@ impl JobRepository {
             .to_string())
     }
 
+    /// Records the number of operations uploaded for the job, along with the
+    /// status it moves to. Returns `false` when the job is no longer
+    /// uploading, because it was cancelled or deleted in the meantime.
+    #[tracing::instrument(skip(self, session))]
+    pub async fn finish_upload(
+        &self,
+        job_id: &str,
+        operations: usize,
+        status: domain::job::JobStatus,
+        session: &mut ClientSession,
+    ) -> Result<bool> {
+        tracing::debug!("Finishing the upload of job {job_id} with {operations} operations");
+
+        FINISH_JOB_UPLOAD_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job_id)?,
+                    Self::UPLOADING_FIELD: true,
+                    Self::CANCELLED_FIELD: { "$ne": true },
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::OPERATIONS_FIELD: i64::try_from(operations)?,
+                        Self::STATUS_FIELD: to_bson(&status)?,
+                    },
+                    "$unset": doc! { Self::UPLOADING_FIELD: "" },
+                },
+            )
+            .session(session)
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    /// Moves a scheduled job that is due to in progress. Returns `false` when
+    /// the job is not scheduled, because it was already started, cancelled or
+    /// deleted.
+    #[tracing::instrument(skip(self))]
+    pub async fn start_scheduled_job(&self, job_id: &str) -> Result<bool> {
+        tracing::debug!("Starting scheduled job {job_id}");
+
+        START_SCHEDULED_JOB_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job_id)?,
+                    Self::SCHEDULED_FIELD: true,
+                    Self::CANCELLED_FIELD: { "$ne": true },
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::STATUS_FIELD: to_bson(&domain::job::JobStatus::InProgress)?,
+                    },
+                    "$unset": doc! { Self::SCHEDULED_FIELD: "" },
+                },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn delete_job(&self, job_id: &str) -> Result<()> {
         tracing::debug!("Deleting job with id {job_id}");
@@ -76,6 +300,181 @@ impl JobRepository {
         Ok(())
     }
 
+    /// Marks the job as cancelled and finished, and schedules its callback. Returns
+    /// `false` when the job already reached a terminal status, which is then
+    /// kept.
+    #[tracing::instrument(skip(self))]
+    pub async fn cancel_job(&self, job_id: &str) -> Result<bool> {
+        tracing::debug!("Cancelling job with id {job_id}");
+
+        CANCEL_JOB_COUNTER.add(1, &[]);
+
+        let job_id = ObjectId::parse_str(job_id)?;
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: job_id,
+                    Self::STATUS_FIELD: Self::running_status_filter()?,
+                },
+                vec![doc! {
+                    "$set": doc! {
+                        Self::CANCELLED_FIELD: true,
+                        Self::STATUS_FIELD: to_bson(&domain::job::JobStatus::Cancelled)?,
+                        Self::FINISHED_AT_FIELD: DateTime::now(),
+                        Self::CALLBACK_FIELD: Self::scheduled_callback(),
+                    }
+                }],
+            )
+            .await?;
+
+        if result.matched_count > 0 {
+            return Ok(true);
+        }
+
+        if self
+            .collection
+            .count_documents(doc! { Self::ID_FIELD: job_id })
+            .await?
+            == 0
+        {
+            anyhow::bail!("Document not found");
+        }
+
+        Ok(false)
+    }
+
+    /// Fails a job that can no longer run to its end, keeping `error` on it,
+    /// finishes it and schedules its callback. A job that already reached a
+    /// terminal status is left untouched.
+    #[tracing::instrument(skip(self, session))]
+    pub async fn fail_job(
+        &self,
+        job_id: &str,
+        error: &str,
+        session: &mut ClientSession,
+    ) -> Result<()> {
+        tracing::debug!("Failing job with id {job_id}");
+
+        FAIL_JOB_COUNTER.add(1, &[]);
+
+        self.collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job_id)?,
+                    Self::STATUS_FIELD: Self::running_status_filter()?,
+                },
+                vec![doc! {
+                    "$set": doc! {
+                        Self::ERROR_FIELD: { "$literal": error },
+                        Self::STATUS_FIELD: to_bson(&domain::job::JobStatus::Failed)?,
+                        Self::FINISHED_AT_FIELD: DateTime::now(),
+                        Self::CALLBACK_FIELD: Self::scheduled_callback(),
+                    }
+                }],
+            )
+            .session(session)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Applies `delta` to the counters of the job, starting it with its first
+    /// result rather than a cancellation, then stores the status they lead to. The status is only written
+    /// when the counters are still the ones it was derived from, a concurrent
+    /// update storing its own. A job reaching a terminal status is finished
+    /// along, and its callback scheduled.
+    #[tracing::instrument(skip(self, session))]
+    pub async fn record_progress(
+        &self,
+        job_id: &str,
+        delta: domain::job::ProgressDelta,
+        session: &mut ClientSession,
+    ) -> Result<()> {
+        tracing::debug!("Recording the progress of job {job_id}");
+
+        RECORD_JOB_PROGRESS_COUNTER.add(1, &[]);
+
+        let job_id = ObjectId::parse_str(job_id)?;
+        let mut update = doc! {
+            "$inc": doc! {
+                Self::COMPLETED_OPERATIONS_FIELD: delta.completed(),
+                Self::FAILED_OPERATIONS_FIELD: delta.failed(),
+            },
+        };
+        if delta.starts_job() {
+            update.insert("$min", doc! { Self::STARTED_AT_FIELD: DateTime::now() });
+        }
+
+        let Some(job) = self
+            .collection
+            .find_one_and_update(doc! { Self::ID_FIELD: job_id }, update)
+            .return_document(ReturnDocument::After)
+            .session(&mut *session)
+            .await?
+        else {
+            anyhow::bail!("Document not found");
+        };
+
+        let status = job.derive_status(job.completed_operations(), job.failed_operations());
+        if Some(status) != job.stored_status() {
+            let mut set = doc! { Self::STATUS_FIELD: to_bson(&status)? };
+            Self::finish(&mut set, &job, status);
+
+            self.collection
+                .update_one(
+                    doc! {
+                        Self::ID_FIELD: job_id,
+                        Self::COMPLETED_OPERATIONS_FIELD: i64::try_from(job.completed_operations())?,
+                        Self::FAILED_OPERATIONS_FIELD: i64::try_from(job.failed_operations())?,
+                    },
+                    doc! { "$set": set },
+                )
+                .session(session)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Overwrites the counters of the job, along with the status they lead
+    /// to, finishing the job it leads to a terminal status and scheduling its
+    /// callback. Returns `false` when the stored counters are no longer the
+    /// ones of `job`, a result having been counted since it was read.
+    #[tracing::instrument(skip(self, job))]
+    pub async fn reset_progress(
+        &self,
+        job: &domain::job::Job,
+        completed_operations: usize,
+        failed_operations: usize,
+    ) -> Result<bool> {
+        tracing::debug!("Resetting the progress of job {}", job.id());
+
+        RESET_JOB_PROGRESS_COUNTER.add(1, &[]);
+
+        let status = job.derive_status(completed_operations, failed_operations);
+        let mut set = doc! {
+            Self::COMPLETED_OPERATIONS_FIELD: i64::try_from(completed_operations)?,
+            Self::FAILED_OPERATIONS_FIELD: i64::try_from(failed_operations)?,
+            Self::STATUS_FIELD: to_bson(&status)?,
+        };
+        Self::finish(&mut set, job, status);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job.id())?,
+                    Self::COMPLETED_OPERATIONS_FIELD: Self::counter_filter(job.completed_operations())?,
+                    Self::FAILED_OPERATIONS_FIELD: Self::counter_filter(job.failed_operations())?,
+                },
+                doc! { "$set": set },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get_job(&self, job_id: &str) -> Result<domain::job::Job> {
         tracing::debug!("Getting job with id: {job_id}");
@@ -97,6 +496,9 @@ impl JobRepository {
     #[tracing::instrument(skip(self))]
     pub async fn get_jobs(
         &self,
+        filter: &database::model::JobFilter,
+        sort: Option<database::model::JobSort>,
+        order: database::model::SortOrder,
         page: u32,
         page_size: u32,
     ) -> Result<database::model::PageSubset<domain::job::Job>> {
@@ -105,11 +507,12 @@ impl JobRepository {
         GET_JOBS_COUNTER.add(1, &[]);
 
         let skip = u64::from(page - 1) * u64::from(page_size);
-        let filter = doc! {};
+        let filter = Self::job_query(filter)?;
 
         let mut cursor = self
             .collection
             .find(filter.clone())
+            .sort(Self::job_sort(sort, order))
             .limit(i64::from(page_size))
             .skip(skip)
             .await?;
@@ -127,4 +530,347 @@ impl JobRepository {
 
         Ok(database::model::PageSubset::new(total, jobs))
     }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_jobs_page(
+        &self,
+        keyset: &database::model::Keyset,
+        page_size: u32,
+    ) -> Result<database::model::KeysetPage<domain::job::Job>> {
+        tracing::debug!("Getting a page of jobs");
+
+        GET_JOBS_PAGE_COUNTER.add(1, &[]);
+
+        database::pagination::find_keyset_page(&self.collection, doc! {}, keyset, page_size).await
+    }
+
+    /// Total number of jobs taken from the collection metadata, which is
+    /// cheap but may be slightly off.
+    #[tracing::instrument(skip(self))]
+    pub async fn estimate_total_jobs(&self) -> Result<usize> {
+        tracing::debug!("Estimating the total number of jobs");
+
+        ESTIMATE_TOTAL_JOBS_COUNTER.add(1, &[]);
+
+        let result = self.collection.estimated_document_count().await?;
+
+        usize::try_from(result).map_err(|err| anyhow::anyhow!(err))
+    }
+
+    /// Takes a lease on the job whose pending callback is the most overdue,
+    /// when no live dispatcher owns it. Only the callbacks scheduled by the
+    /// transition of their job to a terminal status are due. The lease is
+    /// released by [`Self::defer_callback`] or
+    /// [`Self::record_callback_delivery`].
+    #[tracing::instrument(skip(self))]
+    pub async fn claim_callback(
+        &self,
+        owner: &str,
+        lease: Duration,
+    ) -> Result<Option<domain::job::Job>> {
+        tracing::trace!("Claiming a job callback");
+
+        CLAIM_CALLBACK_COUNTER.add(1, &[]);
+
+        let now = DateTime::now();
+        let result = self
+            .collection
+            .find_one_and_update(
+                doc! {
+                    Self::CALLBACK_STATE_FIELD: to_bson(&domain::job::CallbackState::Pending)?,
+                    Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD: { "$lte": now },
+                    "$or": [
+                        { Self::CALLBACK_LEASE_EXPIRES_AT_FIELD: { "$exists": false } },
+                        { Self::CALLBACK_LEASE_EXPIRES_AT_FIELD: { "$lt": now } },
+                    ]
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::CALLBACK_LEASE_OWNER_FIELD: owner,
+                        Self::CALLBACK_LEASE_EXPIRES_AT_FIELD: now.saturating_add_duration(lease),
+                    }
+                },
+            )
+            .sort(doc! { Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD: 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Releases the callback lease of a job that is not finished without
+    /// attempting a delivery, and unschedules its callback until the job
+    /// reaches a terminal status. A job finishing in the meantime keeps the
+    /// schedule of its transition.
+    #[tracing::instrument(skip(self))]
+    pub async fn defer_callback(&self, job_id: &str, owner: &str) -> Result<()> {
+        tracing::trace!("Deferring the callback of job {job_id}");
+
+        DEFER_CALLBACK_COUNTER.add(1, &[]);
+
+        self.collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job_id)?,
+                    Self::CALLBACK_LEASE_OWNER_FIELD: owner,
+                },
+                vec![
+                    doc! {
+                        "$set": doc! {
+                            Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD: {
+                                "$cond": [
+                                    { "$in": [format!("${}", Self::STATUS_FIELD), Self::terminal_statuses()?] },
+                                    format!("${}", Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD),
+                                    "$$REMOVE",
+                                ]
+                            },
+                        }
+                    },
+                    doc! {
+                        "$unset": [
+                            Self::CALLBACK_LEASE_OWNER_FIELD,
+                            Self::CALLBACK_LEASE_EXPIRES_AT_FIELD,
+                        ]
+                    },
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Appends `delivery` to the callback log and releases the lease. A
+    /// callback left in the pending state is attempted again after `delay`.
+    #[tracing::instrument(skip(self, delivery))]
+    pub async fn record_callback_delivery(
+        &self,
+        job_id: &str,
+        owner: &str,
+        delivery: &domain::job::CallbackDelivery,
+        state: domain::job::CallbackState,
+        delay: Duration,
+    ) -> Result<()> {
+        tracing::debug!("Recording a callback delivery of job {job_id}");
+
+        RECORD_CALLBACK_DELIVERY_COUNTER.add(1, &[]);
+
+        self.collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(job_id)?,
+                    Self::CALLBACK_LEASE_OWNER_FIELD: owner,
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::CALLBACK_STATE_FIELD: to_bson(&state)?,
+                        Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD: DateTime::now().saturating_add_duration(delay),
+                    },
+                    "$inc": doc! { Self::CALLBACK_ATTEMPTS_FIELD: 1 },
+                    "$push": doc! { Self::CALLBACK_DELIVERIES_FIELD: to_bson(delivery)? },
+                    "$unset": doc! {
+                        Self::CALLBACK_LEASE_OWNER_FIELD: "",
+                        Self::CALLBACK_LEASE_EXPIRES_AT_FIELD: "",
+                    },
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    fn terminal_statuses() -> Result<Bson> {
+        Ok(to_bson(&[
+            domain::job::JobStatus::Completed,
+            domain::job::JobStatus::CompletedWithErrors,
+            domain::job::JobStatus::Failed,
+            domain::job::JobStatus::Cancelled,
+        ])?)
+    }
+
+    /// Matches the status of a job that is not finished yet, including the
+    /// missing one of the jobs stored before it was.
+    fn running_status_filter() -> Result<Document> {
+        Ok(doc! { "$nin": Self::terminal_statuses()? })
+    }
+
+    /// Adds to `set` the finish time of `job` when moving it to `status`
+    /// finishes it, along with the schedule of its callback.
+    fn finish(set: &mut Document, job: &domain::job::Job, status: domain::job::JobStatus) {
+        let finishes = status.is_terminal()
+            && !job
+                .stored_status()
+                .is_some_and(domain::job::JobStatus::is_terminal);
+        if !finishes {
+            return;
+        }
+
+        set.insert(Self::FINISHED_AT_FIELD, DateTime::now());
+        if job.callback().is_some() {
+            set.insert(Self::CALLBACK_NEXT_ATTEMPT_AT_FIELD, DateTime::now());
+        }
+    }
+
+    /// Update pipeline expression scheduling the callback of the job right
+    /// away, leaving the jobs without one as they are.
+    fn scheduled_callback() -> Document {
+        let callback = format!("${}", Self::CALLBACK_FIELD);
+
+        doc! {
+            "$cond": [
+                { "$eq": [{ "$type": &callback }, "object"] },
+                { "$mergeObjects": [&callback, { "next_attempt_at": DateTime::now() }] },
+                "$$REMOVE",
+            ]
+        }
+    }
+
+    /// Matches a counter holding `value`, a zero also matching the counters
+    /// missing from the jobs stored before they existed.
+    fn counter_filter(value: usize) -> Result<Bson> {
+        let value = i64::try_from(value)?;
+        if value == 0 {
+            Ok(Bson::Document(doc! { "$in": [value, Bson::Null] }))
+        } else {
+            Ok(Bson::Int64(value))
+        }
+    }
+
+    fn job_query(filter: &database::model::JobFilter) -> Result<Document> {
+        let mut query = doc! {};
+
+        if filter.created_after().is_some() || filter.created_before().is_some() {
+            let mut range = doc! {};
+            if let Some(created_after) = filter.created_after() {
+                range.insert("$gte", created_after);
+            }
+            if let Some(created_before) = filter.created_before() {
+                range.insert("$lt", created_before);
+            }
+            query.insert(Self::CREATED_AT_FIELD, range);
+        }
+
+        if let Some((key, value)) = filter.label() {
+            query.insert(format!("{}.{key}", Self::LABELS_FIELD), value);
+        }
+
+        if let Some(name_prefix) = filter.name_prefix() {
+            query.insert(
+                Self::NAME_FIELD,
+                doc! { "$regex": format!("^{}", escape_regex(name_prefix)) },
+            );
+        }
+
+        if let Some(status) = filter.status() {
+            query.insert(Self::STATUS_FIELD, to_bson(&status)?);
+        }
+
+        if let Some(definition_id) = filter.definition_id() {
+            query.insert(Self::DEFINITION_ID_FIELD, definition_id);
+        }
+
+        Ok(query)
+    }
+
+    fn job_sort(
+        sort: Option<database::model::JobSort>,
+        order: database::model::SortOrder,
+    ) -> Document {
+        let direction = match order {
+            database::model::SortOrder::Asc => 1,
+            database::model::SortOrder::Desc => -1,
+        };
+
+        // The id breaks ties, so the pages do not overlap
+        match sort {
+            None => doc! { Self::ID_FIELD: direction },
+            Some(database::model::JobSort::CreatedAt) => {
+                doc! { Self::CREATED_AT_FIELD: direction, Self::ID_FIELD: direction }
+            }
+            Some(database::model::JobSort::Operations) => {
+                doc! { Self::OPERATIONS_FIELD: direction, Self::ID_FIELD: direction }
+            }
+        }
+    }
+}
+
+/// Escapes the characters standing for something in a regular expression.
+fn escape_regex(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, character| {
+        if "\\.^$|?*+()[]{}".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+        escaped
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JobRepository;
+    use super::escape_regex;
+    use crate::domain::job::Job;
+    use crate::domain::job::JobCallback;
+    use crate::domain::job::JobStatus;
+    use mongodb::bson::Bson;
+    use mongodb::bson::doc;
+    use mongodb::bson::to_document;
+
+    #[test]
+    fn escape_regex_matches_names_literally() {
+        // Arrange
+        let name = "nightly (v1.2)*";
+
+        // Act
+        let escaped = escape_regex(name);
+
+        // Assert
+        assert_eq!(escaped, r"nightly \(v1\.2\)\*");
+    }
+
+    #[test]
+    fn finishing_a_job_with_a_callback_schedules_it_once() {
+        // Arrange
+        let job = Job::new(2)
+            .unwrap()
+            .with_callback(JobCallback::new("https://example.com/jobs", None).unwrap());
+        let mut document = to_document(&job).unwrap();
+        document.insert("status", "Completed");
+        let finished: Job = mongodb::bson::from_document(document).unwrap();
+        let mut running_set = doc! {};
+        let mut finished_set = doc! {};
+
+        // Act
+        JobRepository::finish(&mut running_set, &job, JobStatus::Completed);
+        JobRepository::finish(&mut finished_set, &finished, JobStatus::Completed);
+
+        // Assert
+        assert!(running_set.contains_key("finished_at"));
+        assert!(running_set.contains_key("callback.next_attempt_at"));
+        assert!(finished_set.is_empty());
+    }
+
+    #[test]
+    fn finishing_a_job_without_a_callback_only_stores_its_finish_time() {
+        // Arrange
+        let job = Job::new(2).unwrap();
+        let mut set = doc! {};
+
+        // Act
+        JobRepository::finish(&mut set, &job, JobStatus::Failed);
+
+        // Assert
+        assert!(set.contains_key("finished_at"));
+        assert!(!set.contains_key("callback.next_attempt_at"));
+    }
+
+    #[test]
+    fn counter_filter_matches_missing_counters_only_for_zero() {
+        // Act
+        let zero = JobRepository::counter_filter(0).unwrap();
+        let three = JobRepository::counter_filter(3).unwrap();
+
+        // Assert
+        assert_eq!(zero, Bson::Document(doc! { "$in": [0_i64, Bson::Null] }));
+        assert_eq!(three, Bson::Int64(3));
+    }
 }
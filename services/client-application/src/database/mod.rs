@@ -1,4 +1,8 @@
 pub mod database_client;
+pub mod job_definition_repository;
 pub mod job_repository;
 pub mod model;
 pub mod operation_repository;
+pub mod outbox_relay;
+pub mod outbox_repository;
+pub mod pagination;
@@ -1,3 +1,11 @@
+use crate::domain::job::JobPriority;
+use crate::domain::job::JobStatus;
+use crate::domain::job::JobVariables;
+use crate::domain::operation::OperationStatus;
+use mongodb::bson::DateTime;
+use mongodb::bson::oid::ObjectId;
+use std::collections::BTreeMap;
+
 // Misc models
 
 pub struct PageSubset<T> {
@@ -21,3 +29,295 @@ impl<T> PageSubset<T> {
         self.items_subset.as_slice()
     }
 }
+
+/// Position of a keyset page, relative to the `_id` of the last item of the
+/// previous page or of the first item of the next one.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Keyset {
+    After(String),
+    Before(String),
+}
+
+/// Page of items in `_id` order, telling whether more items lie on each side.
+pub struct KeysetPage<T> {
+    items: Vec<T>,
+    has_previous: bool,
+    has_next: bool,
+}
+
+impl<T> KeysetPage<T> {
+    pub const fn new(items: Vec<T>, has_previous: bool, has_next: bool) -> Self {
+        Self {
+            items,
+            has_previous,
+            has_next,
+        }
+    }
+
+    pub const fn items(&self) -> &[T] {
+        self.items.as_slice()
+    }
+
+    pub const fn has_previous(&self) -> bool {
+        self.has_previous
+    }
+
+    pub const fn has_next(&self) -> bool {
+        self.has_next
+    }
+}
+
+// Job models
+
+/// Criteria of a job listing, every criterion left to `None` matching any job.
+#[derive(Clone, Debug, Default)]
+pub struct JobFilter {
+    status: Option<JobStatus>,
+    created_after: Option<DateTime>,
+    created_before: Option<DateTime>,
+    label: Option<(String, String)>,
+    name_prefix: Option<String>,
+    definition_id: Option<String>,
+}
+
+impl JobFilter {
+    #[must_use]
+    pub const fn with_status(mut self, status: Option<JobStatus>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Keeps the jobs created from `created_after`, included, up to
+    /// `created_before`, excluded.
+    #[must_use]
+    pub const fn with_created_range(
+        mut self,
+        created_after: Option<DateTime>,
+        created_before: Option<DateTime>,
+    ) -> Self {
+        self.created_after = created_after;
+        self.created_before = created_before;
+        self
+    }
+
+    #[must_use]
+    pub fn with_label(mut self, label: Option<(String, String)>) -> Self {
+        self.label = label;
+        self
+    }
+
+    #[must_use]
+    pub fn with_name_prefix(mut self, name_prefix: Option<String>) -> Self {
+        self.name_prefix = name_prefix;
+        self
+    }
+
+    /// Keeps the jobs created from the given definition.
+    #[must_use]
+    pub fn with_definition_id(mut self, definition_id: Option<String>) -> Self {
+        self.definition_id = definition_id;
+        self
+    }
+
+    pub const fn status(&self) -> Option<JobStatus> {
+        self.status
+    }
+
+    pub const fn created_after(&self) -> Option<DateTime> {
+        self.created_after
+    }
+
+    pub const fn created_before(&self) -> Option<DateTime> {
+        self.created_before
+    }
+
+    pub fn label(&self) -> Option<(&str, &str)> {
+        self.label
+            .as_ref()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn name_prefix(&self) -> Option<&str> {
+        self.name_prefix.as_deref()
+    }
+
+    pub fn definition_id(&self) -> Option<&str> {
+        self.definition_id.as_deref()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.status.is_none()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+            && self.label.is_none()
+            && self.name_prefix.is_none()
+            && self.definition_id.is_none()
+    }
+}
+
+/// Order of a job listing, jobs being listed in insertion order otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobSort {
+    CreatedAt,
+    Operations,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// Outbox models
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxState {
+    Pending,
+    Sent,
+    Cancelled,
+    /// Given up after failing too many times in a row, left for an operator.
+    Failed,
+}
+
+/// Pending dispatch of the operations of a job. `cursor` holds the id of the
+/// last operation published, so a relay resuming the record after a crash
+/// continues from there instead of starting over.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct OutboxRecord {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    job_id: String,
+    #[serde(default)]
+    priority: JobPriority,
+    /// Variables of the job, sent along with each operation.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: JobVariables,
+    /// Kind of the operations, sent along with each of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    /// Time before which the record cannot be claimed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_at: Option<DateTime>,
+    state: OutboxState,
+    #[serde(default)]
+    attempt: u32,
+    /// Claims in a row that failed without any progress.
+    #[serde(default)]
+    failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_expires_at: Option<DateTime>,
+    created_at: DateTime,
+}
+
+impl OutboxRecord {
+    pub fn new(job_id: impl Into<String>, priority: JobPriority) -> Self {
+        Self {
+            id: None,
+            job_id: job_id.into(),
+            priority,
+            variables: JobVariables::new(),
+            kind: None,
+            run_at: None,
+            state: OutboxState::Pending,
+            attempt: 0,
+            failures: 0,
+            last_error: None,
+            cursor: None,
+            lease_owner: None,
+            lease_expires_at: None,
+            created_at: DateTime::now(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_run_at(mut self, run_at: Option<DateTime>) -> Self {
+        self.run_at = run_at;
+        self
+    }
+
+    #[must_use]
+    pub fn with_variables(mut self, variables: JobVariables) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    #[must_use]
+    pub fn with_kind(mut self, kind: Option<String>) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn id(&self) -> String {
+        self.id.map(ObjectId::to_hex).unwrap_or_default()
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub const fn run_at(&self) -> Option<DateTime> {
+        self.run_at
+    }
+
+    /// Lane the operations are dispatched on.
+    pub const fn priority(&self) -> JobPriority {
+        self.priority
+    }
+
+    pub const fn variables(&self) -> &JobVariables {
+        &self.variables
+    }
+
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    /// Number of times the record was claimed by a relay, the current claim
+    /// included.
+    pub const fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Number of claims in a row that failed without any progress.
+    pub const fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+}
+
+/// Effect of writing the result of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultWrite {
+    /// The result was stored, `created_at` being the creation time of the
+    /// operation, `line` its position in the job and `replaced` its status
+    /// before the write.
+    Applied {
+        created_at: DateTime,
+        line: Option<u64>,
+        replaced: OperationStatus,
+    },
+
+    /// The same result was already stored for the operation on `line`.
+    Duplicate { line: Option<u64> },
+
+    /// A different result was already stored by the same or a newer attempt,
+    /// the received one is recorded aside.
+    Conflict,
+
+    /// The operation was cancelled, the received result is dropped.
+    Discarded,
+}
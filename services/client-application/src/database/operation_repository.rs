@@ -3,12 +3,18 @@ use crate::domain;
 use anyhow::Result;
 use common::counter;
 use futures::Future;
-use futures::StreamExt as _;
 use futures::TryStreamExt;
+use mongodb::ClientSession;
 use mongodb::Collection;
 use mongodb::IndexModel;
+use mongodb::bson::Bson;
+use mongodb::bson::DateTime;
+use mongodb::bson::Document;
 use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
+use mongodb::bson::to_bson;
+use mongodb::options::ReturnDocument;
+use tokio_util::sync::CancellationToken;
 
 counter!(
     INSERT_OPERATION_COUNTER,
@@ -20,6 +26,11 @@ counter!(
     "database_insert_operations_requests",
     "Number of insert operations requests"
 );
+counter!(
+    APPEND_OPERATIONS_COUNTER,
+    "database_append_operations_requests",
+    "Number of append operations requests"
+);
 counter!(
     DELETE_OPERATIONS_COUNTER,
     "database_delete_operations_requests",
@@ -35,21 +46,56 @@ counter!(
     "database_get_total_completed_operations_requests",
     "Number of get total completed operations requests"
 );
+counter!(
+    GET_TOTAL_FAILED_OPERATIONS_COUNTER,
+    "database_get_total_failed_operations_requests",
+    "Number of get total failed operations requests"
+);
 counter!(
     GET_OPERATIONS_COUNTER,
     "database_get_operations_requests",
     "Number of get operations requests"
 );
+counter!(
+    GET_OPERATIONS_PAGE_COUNTER,
+    "database_get_operations_page_requests",
+    "Number of get operations page requests"
+);
 counter!(
     GET_BATCH_OPERATIONS_COUNTER,
     "database_get_batch_operations_requests",
     "Number of get batch operations requests"
 );
+counter!(
+    CANCEL_PENDING_OPERATIONS_COUNTER,
+    "database_cancel_pending_operations_requests",
+    "Number of cancel pending operations requests"
+);
+counter!(
+    COUNT_OPERATIONS_COUNTER,
+    "database_count_operations_requests",
+    "Number of count operations requests"
+);
 counter!(
     UPDATE_OPERATION_COUNTER,
     "database_update_operation_requests",
     "Number of update operation requests"
 );
+counter!(
+    MARK_OPERATIONS_DISPATCHED_COUNTER,
+    "database_mark_operations_dispatched_requests",
+    "Number of mark operations dispatched requests"
+);
+counter!(
+    GET_WAITING_DEPENDENTS_COUNTER,
+    "database_get_waiting_dependents_requests",
+    "Number of get waiting dependents requests"
+);
+counter!(
+    GET_OPERATIONS_BY_LINE_COUNTER,
+    "database_get_operations_by_line_requests",
+    "Number of get operations by line requests"
+);
 
 pub struct OperationRepository {
     collection: Collection<domain::operation::Operation>,
@@ -60,7 +106,15 @@ impl OperationRepository {
 
     const ID_FIELD: &'static str = "_id";
     const JOB_ID_FIELD: &'static str = "job_id";
+    const LINE_FIELD: &'static str = "line";
+    const DEPENDENCIES_FIELD: &'static str = "dependencies";
     const RESULT_FIELD: &'static str = "result";
+    const RESULT_STATUS_FIELD: &'static str = "result.status";
+    const RESULT_ATTEMPT_FIELD: &'static str = "result_attempt";
+    const CONFLICTING_RESULTS_FIELD: &'static str = "conflicting_results";
+    const DISPATCHED_AT_FIELD: &'static str = "dispatched_at";
+    const COMPLETED_AT_FIELD: &'static str = "completed_at";
+    const FAILED_AT_FIELD: &'static str = "failed_at";
 
     pub async fn new(collection: Collection<domain::operation::Operation>) -> Result<Self> {
         tracing::debug!("Initializing the MongoDB operation repository");
@@ -70,11 +124,33 @@ impl OperationRepository {
             .build();
         collection.create_index(job_id_index).await?;
 
+        let job_id_order_index = IndexModel::builder()
+            .keys(doc! { Self::JOB_ID_FIELD: 1, Self::ID_FIELD: 1 })
+            .build();
+        collection.create_index(job_id_order_index).await?;
+
         let result_index = IndexModel::builder()
             .keys(doc! { Self::RESULT_FIELD: 1 })
             .build();
         collection.create_index(result_index).await?;
 
+        // Also reads the operations of a job in a given state in order, pending ones included
+        // since a missing result status is indexed as null
+        let result_status_index = IndexModel::builder()
+            .keys(doc! { Self::JOB_ID_FIELD: 1, Self::RESULT_STATUS_FIELD: 1, Self::ID_FIELD: 1 })
+            .build();
+        collection.create_index(result_status_index).await?;
+
+        let job_id_line_index = IndexModel::builder()
+            .keys(doc! { Self::JOB_ID_FIELD: 1, Self::LINE_FIELD: 1 })
+            .build();
+        collection.create_index(job_id_line_index).await?;
+
+        let dependencies_index = IndexModel::builder()
+            .keys(doc! { Self::JOB_ID_FIELD: 1, Self::DEPENDENCIES_FIELD: 1 })
+            .build();
+        collection.create_index(dependencies_index).await?;
+
         Ok(Self { collection })
     }
 
@@ -97,15 +173,35 @@ impl OperationRepository {
             .to_string())
     }
 
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self, session))]
     pub async fn insert_operations(
         &self,
         new_operations: &[domain::operation::Operation],
+        session: &mut ClientSession,
     ) -> Result<()> {
         tracing::debug!("Inserting operations");
 
         INSERT_OPERATIONS_COUNTER.add(1, &[]);
 
+        self.collection
+            .insert_many(new_operations)
+            .session(session)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Inserts a chunk of the operations of a job being uploaded, outside of
+    /// any transaction.
+    #[tracing::instrument(skip_all)]
+    pub async fn append_operations(
+        &self,
+        new_operations: &[domain::operation::Operation],
+    ) -> Result<()> {
+        tracing::trace!("Appending {} operations", new_operations.len());
+
+        APPEND_OPERATIONS_COUNTER.add(1, &[]);
+
         self.collection.insert_many(new_operations).await?;
 
         Ok(())
@@ -166,10 +262,28 @@ impl OperationRepository {
         usize::try_from(result).map_err(|err| anyhow::anyhow!(err))
     }
 
+    #[tracing::instrument(skip(self))]
+    pub async fn get_total_failed_operations(&self, job_id: &str) -> Result<usize> {
+        tracing::debug!("Getting total failed operations for job {job_id}");
+
+        GET_TOTAL_FAILED_OPERATIONS_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .count_documents(doc! {
+                Self::JOB_ID_FIELD: job_id,
+                Self::RESULT_STATUS_FIELD: to_bson(&domain::operation::OperationStatus::Failed)?
+            })
+            .await?;
+
+        usize::try_from(result).map_err(|err| anyhow::anyhow!(err))
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get_operations(
         &self,
         job_id: &str,
+        state: Option<domain::operation::OperationStatus>,
         page: u32,
         page_size: u32,
     ) -> Result<database::model::PageSubset<domain::operation::Operation>> {
@@ -178,11 +292,12 @@ impl OperationRepository {
         GET_OPERATIONS_COUNTER.add(1, &[]);
 
         let skip = u64::from(page - 1) * u64::from(page_size);
-        let filter = doc! {Self::JOB_ID_FIELD: job_id};
+        let filter = Self::operations_query(job_id, state)?;
 
         let mut cursor = self
             .collection
             .find(filter.clone())
+            .sort(doc! { Self::ID_FIELD: 1 })
             .limit(i64::from(page_size))
             .skip(skip)
             .await?;
@@ -201,68 +316,326 @@ impl OperationRepository {
         Ok(database::model::PageSubset::new(total, operations))
     }
 
-    #[tracing::instrument(skip(self, handler))]
+    #[tracing::instrument(skip(self))]
+    pub async fn get_operations_page(
+        &self,
+        job_id: &str,
+        state: Option<domain::operation::OperationStatus>,
+        keyset: &database::model::Keyset,
+        page_size: u32,
+    ) -> Result<database::model::KeysetPage<domain::operation::Operation>> {
+        tracing::debug!("Getting a page of operations for job {job_id}");
+
+        GET_OPERATIONS_PAGE_COUNTER.add(1, &[]);
+
+        database::pagination::find_keyset_page(
+            &self.collection,
+            Self::operations_query(job_id, state)?,
+            keyset,
+            page_size,
+        )
+        .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn count_operations(
+        &self,
+        job_id: &str,
+        state: domain::operation::OperationStatus,
+    ) -> Result<usize> {
+        tracing::debug!("Counting the {} operations of job {job_id}", state.as_str());
+
+        COUNT_OPERATIONS_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .count_documents(Self::operations_query(job_id, Some(state))?)
+            .await?;
+
+        usize::try_from(result).map_err(|err| anyhow::anyhow!(err))
+    }
+
+    /// Operations of a job, restricted to the ones in `state` if any.
+    fn operations_query(
+        job_id: &str,
+        state: Option<domain::operation::OperationStatus>,
+    ) -> Result<Document> {
+        let mut query = doc! { Self::JOB_ID_FIELD: job_id };
+
+        match state {
+            None => {}
+            // Matches the missing results through the result status index, leaving out the
+            // plain results stored before the outcome was structured
+            Some(domain::operation::OperationStatus::Pending) => {
+                query.insert(Self::RESULT_STATUS_FIELD, Bson::Null);
+                query.insert(Self::RESULT_FIELD, doc! { "$exists": false });
+            }
+            Some(state) => {
+                query.insert(Self::RESULT_STATUS_FIELD, to_bson(&state)?);
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// Streams the operations of `job_id` in `_id` order, starting right
+    /// after the operation `after` when given, and runs `handler` on chunks
+    /// of `batch_size` operations. Once `cancellation` fires, the chunk in
+    /// flight is completed and the remaining ones are skipped.
+    #[tracing::instrument(skip(self, cancellation, handler))]
     pub async fn get_batch_operations<F, Fut>(
         &self,
         job_id: &str,
+        after: Option<&str>,
         batch_size: u32,
+        cancellation: &CancellationToken,
         mut handler: F,
     ) -> Result<()>
     where
-        F: FnMut(domain::operation::Operation) -> Fut + Send,
-        Fut: Future<Output = ()> + Send,
+        F: FnMut(Vec<domain::operation::Operation>) -> Fut + Send,
+        Fut: Future<Output = Result<()>> + Send,
     {
         tracing::debug!("Getting operations for job {job_id}");
 
         GET_BATCH_OPERATIONS_COUNTER.add(1, &[]);
 
+        let mut filter = doc! { Self::JOB_ID_FIELD: job_id };
+        if let Some(after) = after {
+            filter.insert(Self::ID_FIELD, doc! { "$gt": ObjectId::parse_str(after)? });
+        }
+
         let cursor = self
             .collection
-            .find(doc! { Self::JOB_ID_FIELD: job_id })
+            .find(filter)
+            .sort(doc! { Self::ID_FIELD: 1 })
             .batch_size(batch_size)
             .await?;
 
         let mut chunked = cursor.try_chunks(batch_size as usize);
 
         while let Some(batch) = chunked.try_next().await? {
+            if cancellation.is_cancelled() {
+                tracing::info!("Stopping the processing of the operations for job {job_id}");
+                break;
+            }
+
             tracing::trace!("Processing a chunk of {} operations", batch.len());
 
-            futures::stream::iter(batch)
-                .for_each_concurrent(None, &mut handler)
-                .await;
+            handler(batch).await?;
         }
 
         Ok(())
     }
 
+    #[tracing::instrument(skip(self, session))]
+    pub async fn cancel_pending_operations(
+        &self,
+        job_id: &str,
+        session: &mut ClientSession,
+    ) -> Result<u64> {
+        tracing::debug!("Cancelling pending operations of job {job_id}");
+
+        CANCEL_PENDING_OPERATIONS_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_many(
+                doc! {
+                    Self::JOB_ID_FIELD: job_id,
+                    Self::RESULT_FIELD: { "$exists": false }
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::RESULT_FIELD: to_bson(&domain::operation::OperationOutcome::Cancelled)?
+                    }
+                },
+            )
+            .session(session)
+            .await?;
+
+        Ok(result.modified_count)
+    }
+
+    /// Records the dispatch time of the operations published for the first
+    /// time, a redispatch keeping the original one.
+    #[tracing::instrument(skip(self, operation_ids))]
+    pub async fn mark_operations_dispatched(&self, operation_ids: &[String]) -> Result<()> {
+        tracing::debug!("Marking {} operations as dispatched", operation_ids.len());
+
+        MARK_OPERATIONS_DISPATCHED_COUNTER.add(1, &[]);
+
+        let operation_ids = operation_ids
+            .iter()
+            .map(ObjectId::parse_str)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.collection
+            .update_many(
+                doc! {
+                    Self::ID_FIELD: { "$in": operation_ids },
+                    Self::DISPATCHED_AT_FIELD: { "$exists": false },
+                },
+                doc! {
+                    "$set": doc! { Self::DISPATCHED_AT_FIELD: DateTime::now() }
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Operations of a job referencing the result on `line`, which have
+    /// neither been dispatched nor given a result yet.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_waiting_dependents(
+        &self,
+        job_id: &str,
+        line: u64,
+    ) -> Result<Vec<domain::operation::Operation>> {
+        tracing::debug!("Getting the operations of job {job_id} waiting on line {line}");
+
+        GET_WAITING_DEPENDENTS_COUNTER.add(1, &[]);
+
+        let cursor = self
+            .collection
+            .find(doc! {
+                Self::JOB_ID_FIELD: job_id,
+                Self::DEPENDENCIES_FIELD: to_bson(&line)?,
+                Self::RESULT_FIELD: { "$exists": false },
+                Self::DISPATCHED_AT_FIELD: { "$exists": false },
+            })
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
     #[tracing::instrument(skip(self))]
+    pub async fn get_operations_by_line(
+        &self,
+        job_id: &str,
+        lines: &[u64],
+    ) -> Result<Vec<domain::operation::Operation>> {
+        tracing::debug!("Getting {} operations of job {job_id} by line", lines.len());
+
+        GET_OPERATIONS_BY_LINE_COUNTER.add(1, &[]);
+
+        let cursor = self
+            .collection
+            .find(doc! {
+                Self::JOB_ID_FIELD: job_id,
+                Self::LINE_FIELD: { "$in": to_bson(lines)? },
+            })
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Stores the result of an operation produced by the dispatch `attempt`.
+    ///
+    /// The result is applied when the operation has none yet, or one from an
+    /// older attempt. A cancellation is final, so a result received after it
+    /// is discarded. Otherwise, a redelivered result is reported as a
+    /// duplicate, and a different one is recorded aside as a conflict.
+    #[tracing::instrument(skip(self, session))]
     pub async fn update_operation(
         &self,
         job_id: &str,
         operation_id: &str,
-        result: &str,
-    ) -> Result<()> {
+        outcome: &domain::operation::OperationOutcome,
+        attempt: u32,
+        session: &mut ClientSession,
+    ) -> Result<database::model::ResultWrite> {
         tracing::debug!("Updating operation {operation_id} for job {job_id}");
 
         UPDATE_OPERATION_COUNTER.add(1, &[]);
 
+        let operation_id = ObjectId::parse_str(operation_id)?;
+
+        // A result from a newer attempt replaces the timestamp of the previous one
+        let mut set = doc! {
+            Self::RESULT_FIELD: to_bson(outcome)?,
+            Self::RESULT_ATTEMPT_FIELD: attempt,
+        };
+        let mut unset = doc! {};
+        match outcome.status() {
+            domain::operation::OperationStatus::Succeeded => {
+                set.insert(Self::COMPLETED_AT_FIELD, DateTime::now());
+                unset.insert(Self::FAILED_AT_FIELD, "");
+            }
+            domain::operation::OperationStatus::Failed => {
+                set.insert(Self::FAILED_AT_FIELD, DateTime::now());
+                unset.insert(Self::COMPLETED_AT_FIELD, "");
+            }
+            domain::operation::OperationStatus::Pending
+            | domain::operation::OperationStatus::Cancelled => {
+                unset.insert(Self::COMPLETED_AT_FIELD, "");
+                unset.insert(Self::FAILED_AT_FIELD, "");
+            }
+        }
+
         let result = self
             .collection
-            .update_one(
+            .find_one_and_update(
                 doc! {
-                    Self::ID_FIELD: ObjectId::parse_str(operation_id)?,
-                    Self::JOB_ID_FIELD: job_id
-                },
-                doc! {
-                    "$set": doc! {Self::RESULT_FIELD: Some(result)}
+                    Self::ID_FIELD: operation_id,
+                    Self::JOB_ID_FIELD: job_id,
+                    "$or": [
+                        { Self::RESULT_FIELD: { "$exists": false } },
+                        {
+                            Self::RESULT_ATTEMPT_FIELD: { "$lt": attempt },
+                            Self::RESULT_STATUS_FIELD: {
+                                "$ne": to_bson(&domain::operation::OperationStatus::Cancelled)?
+                            },
+                        },
+                    ]
                 },
+                doc! { "$set": set, "$unset": unset },
             )
+            .return_document(ReturnDocument::Before)
+            .session(&mut *session)
             .await?;
 
-        if result.matched_count == 0 {
+        if let Some(operation) = result {
+            return Ok(database::model::ResultWrite::Applied {
+                created_at: operation.created_at(),
+                line: operation.line(),
+                replaced: operation.status(),
+            });
+        }
+
+        let Some(operation) = self
+            .collection
+            .find_one(doc! { Self::ID_FIELD: operation_id, Self::JOB_ID_FIELD: job_id })
+            .session(&mut *session)
+            .await?
+        else {
             anyhow::bail!("Document not found");
+        };
+
+        if operation.status() == domain::operation::OperationStatus::Cancelled {
+            return Ok(database::model::ResultWrite::Discarded);
         }
 
-        Ok(())
+        if operation.result() == Some(outcome) {
+            return Ok(database::model::ResultWrite::Duplicate {
+                line: operation.line(),
+            });
+        }
+
+        self.collection
+            .update_one(
+                doc! { Self::ID_FIELD: operation_id },
+                doc! {
+                    "$push": doc! {
+                        Self::CONFLICTING_RESULTS_FIELD: to_bson(
+                            &domain::operation::ConflictingResult::new(attempt, outcome.clone())
+                        )?
+                    }
+                },
+            )
+            .session(session)
+            .await?;
+
+        Ok(database::model::ResultWrite::Conflict)
     }
 }
@@ -0,0 +1,341 @@
+use crate::application::dispatch_registry::DispatchRegistry;
+use crate::database::database_client::DatabaseClient;
+use crate::database::model::OutboxRecord;
+use crate::domain;
+use crate::messaging::producer::MessageProducer;
+use anyhow::Result;
+use common::counter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+counter!(
+    DISPATCHED_OPERATIONS_COUNTER,
+    "outbox_relay_dispatched_operations",
+    "Number of operations dispatched by the outbox relay"
+);
+counter!(
+    STARTED_SCHEDULED_JOBS_COUNTER,
+    "outbox_relay_started_scheduled_jobs",
+    "Number of scheduled jobs started by the outbox relay once due"
+);
+counter!(
+    FAILED_RECORDS_COUNTER,
+    "outbox_relay_failed_records",
+    "Number of outbox records given up by the outbox relay after failing too many times"
+);
+counter!(
+    RELAY_ERROR_COUNTER,
+    "outbox_relay_errors",
+    "Number of errors encountered by the outbox relay"
+);
+
+/// Background relay publishing the operations referenced by the pending
+/// outbox records.
+///
+/// Every instance runs the relay. A record is only processed by the relay
+/// holding its lease, and its cursor is advanced after each chunk, so a
+/// record left behind by a crashed instance is resumed where it stopped once
+/// the lease expires.
+///
+/// The operations referencing the result of others are skipped, the
+/// [`DependencyResolver`](crate::application::dependency_resolver::DependencyResolver)
+/// dispatching them once their inputs are stored.
+///
+/// A record whose claims keep failing without any progress is marked as
+/// failed after a few of them, its last error kept on it, instead of being
+/// claimed forever. Its job is failed in the same transaction.
+///
+/// The relay also schedules the delayed jobs: their record cannot be claimed
+/// before its `run_at` time, so the poll picks them up once due, and the
+/// lease keeps a job from being dispatched by two instances.
+pub struct OutboxRelay {
+    database_client: Arc<DatabaseClient>,
+    message_producer: Arc<MessageProducer>,
+    dispatch_registry: Arc<DispatchRegistry>,
+    wakeup: Arc<Notify>,
+}
+
+impl OutboxRelay {
+    const CONCURRENCY: usize = 4;
+    const CHUNK_SIZE: u32 = 128;
+    const LEASE_DURATION: Duration = Duration::from_secs(30);
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    // A failed record waits for its lease to expire, so this spans a few minutes
+    const MAX_FAILURES: u32 = 10;
+
+    pub fn new(
+        database_client: Arc<DatabaseClient>,
+        message_producer: Arc<MessageProducer>,
+        dispatch_registry: Arc<DispatchRegistry>,
+    ) -> Self {
+        tracing::debug!("Initializing the outbox relay");
+
+        Self {
+            database_client,
+            message_producer,
+            dispatch_registry,
+            wakeup: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Wakes up an idle relay worker, so a freshly inserted record does not
+    /// wait for the next poll.
+    pub fn wake(&self) {
+        self.wakeup.notify_one();
+    }
+
+    pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
+        tracing::debug!("Start the outbox relay");
+
+        (0..Self::CONCURRENCY)
+            .map(|_| {
+                let database_client = Arc::clone(&self.database_client);
+                let message_producer = Arc::clone(&self.message_producer);
+                let dispatch_registry = Arc::clone(&self.dispatch_registry);
+                let wakeup = Arc::clone(&self.wakeup);
+                let shutdown = shutdown.clone();
+
+                tokio::spawn(async move {
+                    Self::worker_relay(
+                        database_client,
+                        message_producer,
+                        dispatch_registry,
+                        wakeup,
+                        shutdown,
+                    )
+                    .await;
+                    Ok(())
+                })
+            })
+            .collect()
+    }
+
+    async fn worker_relay(
+        database_client: Arc<DatabaseClient>,
+        message_producer: Arc<MessageProducer>,
+        dispatch_registry: Arc<DispatchRegistry>,
+        wakeup: Arc<Notify>,
+        shutdown: CancellationToken,
+    ) {
+        let owner = common::application::instance_id();
+
+        loop {
+            let claimed = tokio::select! {
+                () = shutdown.cancelled() => return,
+                result = database_client
+                    .outbox_repository()
+                    .claim_record(owner, Self::LEASE_DURATION) => result,
+            };
+            match claimed {
+                Ok(Some(record)) => {
+                    if let Err(err) = Self::relay_record(
+                        &record,
+                        &database_client,
+                        &message_producer,
+                        &dispatch_registry,
+                        &shutdown,
+                    )
+                    .await
+                    {
+                        tracing::error!(
+                            "Failed to dispatch the operations of job {}: {err}",
+                            record.job_id()
+                        );
+
+                        RELAY_ERROR_COUNTER.add(1, &[]);
+
+                        Self::record_failure(&record, &database_client, &err).await;
+                    }
+
+                    // Look for another record right away
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::error!("Failed to claim an outbox record: {err}");
+
+                    RELAY_ERROR_COUNTER.add(1, &[]);
+                }
+            }
+
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = wakeup.notified() => {}
+                () = tokio::time::sleep(Self::POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Counts the failed claim of `record`, giving it up after too many of
+    /// them in a row rather than claiming it forever. The job of a record
+    /// given up on can never finish, so it is failed along with it.
+    async fn record_failure(
+        record: &OutboxRecord,
+        database_client: &DatabaseClient,
+        err: &anyhow::Error,
+    ) {
+        match Self::fail_record(record, database_client, err).await {
+            Ok(true) => {
+                tracing::error!(
+                    "Gave up dispatching the operations of job {} after {} failures: {err}",
+                    record.job_id(),
+                    Self::MAX_FAILURES
+                );
+
+                FAILED_RECORDS_COUNTER.add(1, &[]);
+            }
+            Ok(false) => {}
+            Err(err) => {
+                tracing::error!(
+                    "Failed to record the failure of the outbox record of job {}: {err}",
+                    record.job_id()
+                );
+
+                RELAY_ERROR_COUNTER.add(1, &[]);
+            }
+        }
+    }
+
+    async fn fail_record(
+        record: &OutboxRecord,
+        database_client: &DatabaseClient,
+        err: &anyhow::Error,
+    ) -> Result<bool> {
+        let owner = common::application::instance_id();
+        let mut session = database_client.start_transaction().await?;
+
+        let given_up = database_client
+            .outbox_repository()
+            .fail_record(
+                record,
+                owner,
+                &err.to_string(),
+                Self::MAX_FAILURES,
+                &mut session,
+            )
+            .await?;
+        if given_up {
+            database_client
+                .job_repository()
+                .fail_job(
+                    record.job_id(),
+                    &format!("Failed to dispatch the operations: {err}"),
+                    &mut session,
+                )
+                .await?;
+        }
+
+        session.commit_transaction().await?;
+
+        Ok(given_up)
+    }
+
+    #[tracing::instrument(skip_all, fields(job_id = record.job_id()))]
+    async fn relay_record(
+        record: &OutboxRecord,
+        database_client: &DatabaseClient,
+        message_producer: &MessageProducer,
+        dispatch_registry: &DispatchRegistry,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let owner = common::application::instance_id();
+        let record_id = record.id();
+        let job_id = record.job_id();
+        let outbox_repository = database_client.outbox_repository();
+
+        if record.run_at().is_some()
+            && database_client
+                .job_repository()
+                .start_scheduled_job(job_id)
+                .await?
+        {
+            tracing::info!("Scheduled job {job_id} is due");
+
+            STARTED_SCHEDULED_JOBS_COUNTER.add(1, &[]);
+        }
+
+        tracing::info!("Dispatching the operations of job {job_id}");
+
+        let cancellation = dispatch_registry.register(job_id, shutdown);
+        let result = database_client
+            .operation_repository()
+            .get_batch_operations(
+                job_id,
+                record.cursor(),
+                Self::CHUNK_SIZE,
+                &cancellation,
+                |operations: Vec<domain::operation::Operation>| {
+                    let record_id = &record_id;
+                    let cancellation = &cancellation;
+                    async move {
+                        let Some(cursor) = operations.last().map(domain::operation::Operation::id)
+                        else {
+                            return Ok(());
+                        };
+
+                        // Operations referencing other results are released once those are stored
+                        let operations: Vec<_> = operations
+                            .into_iter()
+                            .filter(|operation| operation.dependencies().is_empty())
+                            .collect();
+
+                        // The cursor only moves past operations acknowledged by Kafka
+                        let deliveries = message_producer
+                            .send_operation_requests(
+                                &operations,
+                                record.attempt(),
+                                record.priority(),
+                                record.variables(),
+                                record.kind(),
+                            )
+                            .await?;
+
+                        DISPATCHED_OPERATIONS_COUNTER.add(
+                            deliveries.len() as u64,
+                            &[opentelemetry::KeyValue::new(
+                                "priority",
+                                record.priority().as_str(),
+                            )],
+                        );
+
+                        let operation_ids: Vec<_> = operations
+                            .iter()
+                            .map(domain::operation::Operation::id)
+                            .collect();
+                        database_client
+                            .operation_repository()
+                            .mark_operations_dispatched(&operation_ids)
+                            .await?;
+
+                        if !outbox_repository
+                            .advance_record(record_id, owner, &cursor, Self::LEASE_DURATION)
+                            .await?
+                        {
+                            tracing::warn!("Outbox record of job {job_id} is no longer owned");
+
+                            cancellation.cancel();
+                        }
+
+                        Ok(())
+                    }
+                },
+            )
+            .await;
+        dispatch_registry.unregister(job_id);
+        result?;
+
+        // Cancelled, taken over or shutting down: the record is left to its new state
+        if cancellation.is_cancelled() {
+            return Ok(());
+        }
+
+        if outbox_repository.complete_record(&record_id, owner).await? {
+            tracing::info!("All the operations of job {job_id} have been dispatched");
+        }
+
+        Ok(())
+    }
+}
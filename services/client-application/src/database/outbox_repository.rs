@@ -0,0 +1,276 @@
+use crate::database::model::OutboxRecord;
+use crate::database::model::OutboxState;
+use anyhow::Result;
+use common::counter;
+use mongodb::ClientSession;
+use mongodb::Collection;
+use mongodb::IndexModel;
+use mongodb::bson::DateTime;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::to_bson;
+use mongodb::options::ReturnDocument;
+use std::time::Duration;
+
+counter!(
+    INSERT_OUTBOX_RECORD_COUNTER,
+    "database_insert_outbox_record_requests",
+    "Number of insert outbox record requests"
+);
+counter!(
+    CLAIM_OUTBOX_RECORD_COUNTER,
+    "database_claim_outbox_record_requests",
+    "Number of claim outbox record requests"
+);
+counter!(
+    ADVANCE_OUTBOX_RECORD_COUNTER,
+    "database_advance_outbox_record_requests",
+    "Number of advance outbox record requests"
+);
+counter!(
+    COMPLETE_OUTBOX_RECORD_COUNTER,
+    "database_complete_outbox_record_requests",
+    "Number of complete outbox record requests"
+);
+counter!(
+    FAIL_OUTBOX_RECORD_COUNTER,
+    "database_fail_outbox_record_requests",
+    "Number of fail outbox record requests"
+);
+counter!(
+    CANCEL_OUTBOX_RECORDS_COUNTER,
+    "database_cancel_outbox_records_requests",
+    "Number of cancel outbox records requests"
+);
+counter!(
+    DELETE_OUTBOX_RECORDS_COUNTER,
+    "database_delete_outbox_records_requests",
+    "Number of delete outbox records requests"
+);
+
+pub struct OutboxRepository {
+    collection: Collection<OutboxRecord>,
+}
+
+impl OutboxRepository {
+    pub const COLLECTION_NAME: &'static str = "outbox";
+
+    const ID_FIELD: &'static str = "_id";
+    const JOB_ID_FIELD: &'static str = "job_id";
+    const STATE_FIELD: &'static str = "state";
+    const RUN_AT_FIELD: &'static str = "run_at";
+    const ATTEMPT_FIELD: &'static str = "attempt";
+    const FAILURES_FIELD: &'static str = "failures";
+    const LAST_ERROR_FIELD: &'static str = "last_error";
+    const CURSOR_FIELD: &'static str = "cursor";
+    const LEASE_OWNER_FIELD: &'static str = "lease_owner";
+    const LEASE_EXPIRES_AT_FIELD: &'static str = "lease_expires_at";
+
+    pub async fn new(collection: Collection<OutboxRecord>) -> Result<Self> {
+        tracing::debug!("Initializing the MongoDB outbox repository");
+
+        let job_id_index = IndexModel::builder()
+            .keys(doc! { Self::JOB_ID_FIELD: 1 })
+            .build();
+        collection.create_index(job_id_index).await?;
+
+        let state_index = IndexModel::builder()
+            .keys(doc! { Self::STATE_FIELD: 1, Self::LEASE_EXPIRES_AT_FIELD: 1 })
+            .build();
+        collection.create_index(state_index).await?;
+
+        Ok(Self { collection })
+    }
+
+    #[tracing::instrument(skip(self, session))]
+    pub async fn insert_record(
+        &self,
+        record: &OutboxRecord,
+        session: &mut ClientSession,
+    ) -> Result<()> {
+        tracing::debug!("Inserting an outbox record for job {}", record.job_id());
+
+        INSERT_OUTBOX_RECORD_COUNTER.add(1, &[]);
+
+        self.collection.insert_one(record).session(session).await?;
+
+        Ok(())
+    }
+
+    /// Takes a lease on the oldest pending record that is due and that no
+    /// live relay owns, and counts a new dispatch attempt for it. The lease
+    /// must be renewed through [`Self::advance_record`] before it expires,
+    /// otherwise another relay is free to take the record over.
+    #[tracing::instrument(skip(self))]
+    pub async fn claim_record(&self, owner: &str, lease: Duration) -> Result<Option<OutboxRecord>> {
+        tracing::trace!("Claiming an outbox record");
+
+        CLAIM_OUTBOX_RECORD_COUNTER.add(1, &[]);
+
+        let now = DateTime::now();
+        let result = self
+            .collection
+            .find_one_and_update(
+                doc! {
+                    Self::STATE_FIELD: to_bson(&OutboxState::Pending)?,
+                    // Also matches the records dispatched right away, which have no run_at
+                    Self::RUN_AT_FIELD: { "$not": { "$gt": now } },
+                    "$or": [
+                        { Self::LEASE_EXPIRES_AT_FIELD: { "$exists": false } },
+                        { Self::LEASE_EXPIRES_AT_FIELD: { "$lt": now } },
+                    ]
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::LEASE_OWNER_FIELD: owner,
+                        Self::LEASE_EXPIRES_AT_FIELD: now.saturating_add_duration(lease),
+                    },
+                    "$inc": doc! { Self::ATTEMPT_FIELD: 1 },
+                },
+            )
+            .sort(doc! { Self::ID_FIELD: 1 })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Records the progress of the relay and renews its lease, which clears
+    /// the failures of the previous claims. Returns `false` when the relay no
+    /// longer owns the record, because the lease was taken over or the record
+    /// was cancelled.
+    #[tracing::instrument(skip(self))]
+    pub async fn advance_record(
+        &self,
+        record_id: &str,
+        owner: &str,
+        cursor: &str,
+        lease: Duration,
+    ) -> Result<bool> {
+        tracing::trace!("Advancing outbox record {record_id} to {cursor}");
+
+        ADVANCE_OUTBOX_RECORD_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(record_id)?,
+                    Self::STATE_FIELD: to_bson(&OutboxState::Pending)?,
+                    Self::LEASE_OWNER_FIELD: owner,
+                },
+                doc! {
+                    "$set": doc! {
+                        Self::CURSOR_FIELD: cursor,
+                        Self::LEASE_EXPIRES_AT_FIELD: DateTime::now().saturating_add_duration(lease),
+                        Self::FAILURES_FIELD: 0,
+                    },
+                    "$unset": doc! { Self::LAST_ERROR_FIELD: "" },
+                },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn complete_record(&self, record_id: &str, owner: &str) -> Result<bool> {
+        tracing::debug!("Completing outbox record {record_id}");
+
+        COMPLETE_OUTBOX_RECORD_COUNTER.add(1, &[]);
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(record_id)?,
+                    Self::STATE_FIELD: to_bson(&OutboxState::Pending)?,
+                    Self::LEASE_OWNER_FIELD: owner,
+                },
+                doc! {
+                    "$set": doc! { Self::STATE_FIELD: to_bson(&OutboxState::Sent)? },
+                    "$unset": doc! { Self::LEASE_OWNER_FIELD: "", Self::LEASE_EXPIRES_AT_FIELD: "" },
+                },
+            )
+            .await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    /// Records a failed claim of the record, whose lease is left to expire
+    /// before it is claimed again. Once `max_failures` claims in a row failed
+    /// the record is marked as failed and no longer claimed. Returns `true`
+    /// when the record was given up on.
+    #[tracing::instrument(skip(self, record, session))]
+    pub async fn fail_record(
+        &self,
+        record: &OutboxRecord,
+        owner: &str,
+        error: &str,
+        max_failures: u32,
+        session: &mut ClientSession,
+    ) -> Result<bool> {
+        tracing::debug!("Recording a failure of outbox record {}", record.id());
+
+        FAIL_OUTBOX_RECORD_COUNTER.add(1, &[]);
+
+        let failures = record.failures() + 1;
+        let mut set = doc! {
+            Self::FAILURES_FIELD: failures,
+            Self::LAST_ERROR_FIELD: error,
+        };
+        let given_up = failures >= max_failures;
+        if given_up {
+            set.insert(Self::STATE_FIELD, to_bson(&OutboxState::Failed)?);
+        }
+
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    Self::ID_FIELD: ObjectId::parse_str(record.id())?,
+                    Self::STATE_FIELD: to_bson(&OutboxState::Pending)?,
+                    Self::LEASE_OWNER_FIELD: owner,
+                },
+                doc! { "$set": set },
+            )
+            .session(session)
+            .await?;
+
+        Ok(given_up && result.matched_count > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn cancel_records(&self, job_id: &str) -> Result<()> {
+        tracing::debug!("Cancelling outbox records of job {job_id}");
+
+        CANCEL_OUTBOX_RECORDS_COUNTER.add(1, &[]);
+
+        self.collection
+            .update_many(
+                doc! {
+                    Self::JOB_ID_FIELD: job_id,
+                    Self::STATE_FIELD: to_bson(&OutboxState::Pending)?,
+                },
+                doc! {
+                    "$set": doc! { Self::STATE_FIELD: to_bson(&OutboxState::Cancelled)? }
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_records(&self, job_id: &str) -> Result<()> {
+        tracing::debug!("Deleting outbox records of job {job_id}");
+
+        DELETE_OUTBOX_RECORDS_COUNTER.add(1, &[]);
+
+        self.collection
+            .delete_many(doc! {Self::JOB_ID_FIELD: job_id})
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::database::model::Keyset;
+use crate::database::model::KeysetPage;
+use anyhow::Result;
+use futures::TryStreamExt;
+use mongodb::Collection;
+use mongodb::bson::Document;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+
+const ID_FIELD: &str = "_id";
+
+/// Reads the page of `size` documents matching `filter` on the side of
+/// `keyset`. The position is resolved through the `_id` index, so the cost
+/// of a page does not depend on how deep it is.
+pub async fn find_keyset_page<T>(
+    collection: &Collection<T>,
+    mut filter: Document,
+    keyset: &Keyset,
+    size: u32,
+) -> Result<KeysetPage<T>>
+where
+    T: serde::de::DeserializeOwned + Send + Sync,
+{
+    let (operator, order, id) = match keyset {
+        Keyset::After(id) => ("$gt", 1, id),
+        Keyset::Before(id) => ("$lt", -1, id),
+    };
+    filter.insert(ID_FIELD, doc! { operator: ObjectId::parse_str(id)? });
+
+    // One more document tells whether another page follows
+    let mut items: Vec<T> = collection
+        .find(filter)
+        .sort(doc! { ID_FIELD: order })
+        .limit(i64::from(size) + 1)
+        .await?
+        .try_collect()
+        .await?;
+
+    let has_more = items.len() > size as usize;
+    items.truncate(size as usize);
+
+    Ok(match keyset {
+        Keyset::After(_) => KeysetPage::new(items, true, has_more),
+        Keyset::Before(_) => {
+            items.reverse();
+            KeysetPage::new(items, has_more, true)
+        }
+    })
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use mongodb::bson::DateTime;
+
+/// Five-field cron expression: minute, hour, day of month, month and day of
+/// week, evaluated in UTC.
+///
+/// Each field takes `*`, a value, a range `a-b` or a comma-separated list of
+/// those, each optionally stepped with `/n`. Sunday is either 0 or 7. As in
+/// the classic cron, a day matching either the day of month or the day of
+/// week fires when both fields are restricted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    const MINUTES_PER_DAY: i64 = 24 * 60;
+    const MILLIS_PER_MINUTE: i64 = 60 * 1000;
+    // Past that, the expression is taken as never firing, such as on February 30
+    const MAX_SEARCHED_MINUTES: i64 = 5 * 366 * Self::MINUTES_PER_DAY;
+
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            anyhow::bail!(
+                "Invalid cron expression {expression}: expected 5 fields, got {}",
+                fields.len()
+            );
+        };
+
+        let mut weekdays_mask = Self::parse_field("day of week", weekdays, 0, 7)?;
+        // Sunday is both 0 and 7
+        if weekdays_mask & (1 << 7) != 0 {
+            weekdays_mask = (weekdays_mask | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: Self::parse_field("minute", minutes, 0, 59)?,
+            hours: Self::parse_field("hour", hours, 0, 23)?,
+            days: Self::parse_field("day of month", days, 1, 31)?,
+            months: Self::parse_field("month", months, 1, 12)?,
+            weekdays: weekdays_mask,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+
+    /// First time strictly after `after` matching the expression, to the
+    /// minute, or `None` when it never fires.
+    pub const fn next_after(&self, after: DateTime) -> Option<DateTime> {
+        let first_minute = after.timestamp_millis().div_euclid(Self::MILLIS_PER_MINUTE) + 1;
+        let mut minute = first_minute;
+
+        while minute - first_minute < Self::MAX_SEARCHED_MINUTES {
+            let days = minute.div_euclid(Self::MINUTES_PER_DAY);
+            let (year, month, day) = civil_from_days(days);
+
+            if !Self::contains(self.months, month) {
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                minute = days_from_civil(year, month, 1) * Self::MINUTES_PER_DAY;
+                continue;
+            }
+
+            // 1970-01-01 was a Thursday
+            let weekday = (days + 4).rem_euclid(7);
+            if !self.matches_day(day, weekday) {
+                minute = (days + 1) * Self::MINUTES_PER_DAY;
+                continue;
+            }
+
+            let minute_of_day = minute.rem_euclid(Self::MINUTES_PER_DAY);
+            if !Self::contains(self.hours, minute_of_day / 60) {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+
+            if !Self::contains(self.minutes, minute_of_day % 60) {
+                minute += 1;
+                continue;
+            }
+
+            return Some(DateTime::from_millis(minute * Self::MILLIS_PER_MINUTE));
+        }
+
+        None
+    }
+
+    const fn matches_day(&self, day: i64, weekday: i64) -> bool {
+        let day_matches = Self::contains(self.days, day);
+        let weekday_matches = Self::contains(self.weekdays, weekday);
+
+        if self.days_restricted && self.weekdays_restricted {
+            day_matches || weekday_matches
+        } else {
+            day_matches && weekday_matches
+        }
+    }
+
+    const fn contains(mask: u64, value: i64) -> bool {
+        mask & (1 << value) != 0
+    }
+
+    fn parse_field(name: &str, field: &str, min: i64, max: i64) -> Result<u64> {
+        let parse_value = |value: &str| {
+            value
+                .parse::<i64>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid {name} {value}, expected a value in {min}-{max}")
+                })
+        };
+
+        let mut mask = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<i64>()
+                        .ok()
+                        .filter(|step| *step > 0)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid {name} step {step}"))?,
+                ),
+                None => (part, 1),
+            };
+
+            let (start, end) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((start, end)) => (parse_value(start)?, parse_value(end)?),
+                    // A stepped value runs up to the maximum, as in 5/15
+                    None if step > 1 => (parse_value(range)?, max),
+                    None => (parse_value(range)?, parse_value(range)?),
+                },
+            };
+            if start > end {
+                anyhow::bail!("Invalid {name} range {range}");
+            }
+
+            let mut value = start;
+            while value <= end {
+                mask |= 1 << value;
+                value += step;
+            }
+        }
+
+        Ok(mask)
+    }
+}
+
+/// Year, month and day of the given number of days since 1970-01-01.
+const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that February comes last
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Number of days since 1970-01-01 of the given date.
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CronSchedule;
+    use mongodb::bson::DateTime;
+
+    #[test]
+    fn cron_rejects_out_of_range_values() {
+        // Arrange
+        let expression = "0 24 * * *";
+
+        // Act
+        let schedule = CronSchedule::parse(expression);
+
+        // Assert
+        assert!(schedule.is_err());
+    }
+
+    #[test]
+    fn cron_fires_on_the_next_matching_weekday() {
+        // Arrange
+        let schedule = CronSchedule::parse("30 9 * * 1").unwrap();
+        // A Saturday
+        let after = DateTime::parse_rfc3339_str("2026-10-17T12:00:00Z").unwrap();
+
+        // Act
+        let next = schedule.next_after(after);
+
+        // Assert
+        assert_eq!(
+            next,
+            Some(DateTime::parse_rfc3339_str("2026-10-19T09:30:00Z").unwrap())
+        );
+    }
+
+    #[test]
+    fn cron_rolls_over_to_the_next_year() {
+        // Arrange
+        let schedule = CronSchedule::parse("0 */6 * * *").unwrap();
+        let after = DateTime::parse_rfc3339_str("2026-12-31T18:00:00Z").unwrap();
+
+        // Act
+        let next = schedule.next_after(after);
+
+        // Assert
+        assert_eq!(
+            next,
+            Some(DateTime::parse_rfc3339_str("2027-01-01T00:00:00Z").unwrap())
+        );
+    }
+}
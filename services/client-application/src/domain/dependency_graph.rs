@@ -0,0 +1,168 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Lines whose result an operation references, written `$N` for the
+/// operation on line `N`. The references within string literals are left
+/// alone.
+pub fn references(request: &str) -> Vec<u64> {
+    let mut references = Vec::new();
+    let mut chars = request.char_indices().peekable();
+    let mut in_string = false;
+
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            '$' if !in_string => {
+                let start = index + 1;
+                let mut end = start;
+                while let Some((index, char)) = chars.peek().copied() {
+                    if !char.is_ascii_alphanumeric() && char != '_' {
+                        break;
+                    }
+                    chars.next();
+                    end = index + char.len_utf8();
+                }
+
+                // Anything else than digits is left to the evaluation, as an unknown identifier
+                if let Ok(line) = request[start..end].parse::<u64>() {
+                    references.push(line);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    references.sort_unstable();
+    references.dedup();
+    references
+}
+
+/// Dependencies between the operations of a job, checked before the job is
+/// accepted so that every operation is eventually dispatched.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    dependencies: BTreeMap<u64, Vec<u64>>,
+}
+
+impl DependencyGraph {
+    /// Checks the dependencies between the operations of a job, given in
+    /// order.
+    pub fn validate_requests(requests: &[String]) -> Result<()> {
+        let mut graph = Self::default();
+        for (request, line) in requests.iter().zip(1..) {
+            graph.add(line, &references(request));
+        }
+
+        graph.validate(requests.len() as u64)
+    }
+
+    /// Records the lines the operation on `line` depends on.
+    pub fn add(&mut self, line: u64, dependencies: &[u64]) {
+        if !dependencies.is_empty() {
+            self.dependencies.insert(line, dependencies.to_vec());
+        }
+    }
+
+    /// Number of operations depending on others.
+    pub fn dependent_operations(&self) -> usize {
+        self.dependencies.len()
+    }
+
+    /// Rejects the references to lines missing from a job of `total_lines`
+    /// operations, and the cycles, an operation waiting on itself included.
+    pub fn validate(&self, total_lines: u64) -> Result<()> {
+        for (line, dependencies) in &self.dependencies {
+            if let Some(missing) = dependencies
+                .iter()
+                .find(|dependency| **dependency == 0 || **dependency > total_lines)
+            {
+                anyhow::bail!("Operation on line {line} references the missing line {missing}");
+            }
+        }
+
+        // Depth-first search, a line met again while still on the path closing a cycle
+        let mut visited = BTreeMap::new();
+        for start in self.dependencies.keys() {
+            if visited.contains_key(start) {
+                continue;
+            }
+
+            visited.insert(*start, true);
+            let mut path = vec![(*start, 0)];
+            while let Some((line, next)) = path.last_mut() {
+                let dependencies = self.dependencies.get(line).map_or(&[][..], Vec::as_slice);
+                let Some(dependency) = dependencies.get(*next).copied() else {
+                    visited.insert(*line, false);
+                    path.pop();
+                    continue;
+                };
+                *next += 1;
+
+                match visited.get(&dependency) {
+                    Some(true) => {
+                        anyhow::bail!("Operation on line {dependency} depends on its own result")
+                    }
+                    Some(false) => {}
+                    None => {
+                        visited.insert(dependency, true);
+                        path.push((dependency, 0));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DependencyGraph;
+    use super::references;
+
+    #[test]
+    fn references_skip_string_literals() {
+        // Arrange
+        let request = r#"$1 * 2 + $3 + str::len("$4") + $1"#;
+
+        // Act
+        let lines = references(request);
+
+        // Assert
+        assert_eq!(lines, vec![1, 3]);
+    }
+
+    #[test]
+    fn graph_rejects_cycles() {
+        // Arrange
+        let mut graph = DependencyGraph::default();
+        graph.add(1, &[3]);
+        graph.add(2, &[1]);
+        graph.add(3, &[2]);
+        graph.add(4, &[1, 2]);
+
+        // Act
+        let result = graph.validate(4);
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn graph_accepts_shared_dependencies() {
+        // Arrange
+        let mut graph = DependencyGraph::default();
+        graph.add(2, &[1]);
+        graph.add(3, &[1, 2]);
+        graph.add(4, &[2, 3]);
+
+        // Act
+        let result = graph.validate(4);
+
+        // Assert
+        assert!(result.is_ok());
+    }
+}
@@ -1,17 +1,240 @@
+use crate::domain::operation::OperationStatus;
 use anyhow::Result;
+use mongodb::bson::DateTime;
 use mongodb::bson::oid::ObjectId;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 
-#[derive(Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub enum JobStatus {
+    Uploading,
+    /// Waiting for its `run_at` time before being dispatched.
+    Scheduled,
     InProgress,
     Completed,
+    CompletedWithErrors,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub const fn is_terminal(self) -> bool {
+        !matches!(self, Self::Uploading | Self::Scheduled | Self::InProgress)
+    }
+}
+
+/// Values bound to their names in every expression of a job.
+pub type JobVariables = BTreeMap<String, serde_json::Value>;
+
+/// Rejects the variables whose name is not an identifier, or whose value is
+/// neither a number, a string nor a boolean.
+pub fn validate_variables(variables: &JobVariables) -> Result<()> {
+    for (name, value) in variables {
+        let mut chars = name.chars();
+        let is_identifier = chars
+            .next()
+            .is_some_and(|char| char.is_ascii_alphabetic() || char == '_')
+            && chars.all(|char| char.is_ascii_alphanumeric() || char == '_');
+        if !is_identifier || name == "true" || name == "false" {
+            anyhow::bail!("Invalid variable name {name}");
+        }
+
+        if !(value.is_number() || value.is_string() || value.is_boolean()) {
+            anyhow::bail!("Variable {name} must be a number, a string or a boolean");
+        }
+    }
+
+    Ok(())
+}
+
+/// Lane the operations of a job are dispatched on, the servers preferring the
+/// higher ones without starving the lower ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    High,
+    #[default]
+    Normal,
+    Bulk,
+}
+
+impl JobPriority {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Bulk => "bulk",
+        }
+    }
+}
+
+impl std::str::FromStr for JobPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "high" => Ok(Self::High),
+            "normal" => Ok(Self::Normal),
+            "bulk" => Ok(Self::Bulk),
+            _ => anyhow::bail!("Unknown priority {value}, expected high, normal or bulk"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackState {
+    /// Waiting for the job to finish, or for the next delivery attempt.
+    Pending,
+    Delivered,
+    /// Every delivery attempt failed.
+    Failed,
+}
+
+/// Outcome of one attempt to deliver the callback of a job.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct CallbackDelivery {
+    attempt: u32,
+    attempted_at: DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl CallbackDelivery {
+    pub fn new(attempt: u32, status_code: Option<u16>, error: Option<String>) -> Self {
+        Self {
+            attempt,
+            attempted_at: DateTime::now(),
+            status_code,
+            error,
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// URL notified once the job is finished, along with the log of the
+/// deliveries attempted so far.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct JobCallback {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    state: CallbackState,
+    attempts: u32,
+    /// Unset until the job reaches a terminal status.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_attempt_at: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_expires_at: Option<DateTime>,
+    #[serde(default)]
+    deliveries: Vec<CallbackDelivery>,
+}
+
+impl JobCallback {
+    pub fn new(url: impl Into<String>, secret: Option<String>) -> Result<Self> {
+        let url = url.into();
+        let parsed = match reqwest::Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+            Ok(_) => anyhow::bail!("The callback URL must use http or https"),
+            Err(err) => anyhow::bail!("Invalid callback URL: {err}"),
+        };
+        if parsed.host_str().is_none_or(is_internal_host) {
+            anyhow::bail!("The callback URL must target a public host");
+        }
+
+        Ok(Self {
+            url,
+            secret: secret.filter(|secret| !secret.is_empty()),
+            state: CallbackState::Pending,
+            attempts: 0,
+            next_attempt_at: None,
+            lease_owner: None,
+            lease_expires_at: None,
+            deliveries: Vec::new(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn secret(&self) -> Option<&str> {
+        self.secret.as_deref()
+    }
+
+    pub const fn state(&self) -> CallbackState {
+        self.state
+    }
+
+    /// Number of deliveries attempted so far.
+    pub const fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn deliveries(&self) -> &[CallbackDelivery] {
+        &self.deliveries
+    }
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Job {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
     operations: usize,
+    /// Operations holding a result, successful or not.
+    #[serde(default)]
+    completed_operations: usize,
+    #[serde(default)]
+    failed_operations: usize,
+    /// Missing from the jobs stored before it was, derived from their
+    /// counters then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<JobStatus>,
+    #[serde(default)]
+    priority: JobPriority,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: JobVariables,
+    /// Executor of the operations, the servers' default one when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    /// Missing from the jobs stored before it was, taken from their id then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime>,
+    /// Time the dispatch of the operations is held until.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_at: Option<DateTime>,
+    /// Definition the job was created from, on its schedule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    definition_id: Option<String>,
+    /// First result received, missing from the jobs stored before it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started_at: Option<DateTime>,
+    /// Time the job reached a terminal status, missing from the jobs stored
+    /// before it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finished_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    uploading: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    scheduled: bool,
+    #[serde(default)]
+    cancelled: bool,
+    /// Why the job could not run to its end, which fails it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback: Option<JobCallback>,
 }
 
 impl Job {
@@ -22,23 +245,480 @@ impl Job {
 
         Ok(Self {
             id: None,
+            name: None,
+            labels: BTreeMap::new(),
             operations,
+            completed_operations: 0,
+            failed_operations: 0,
+            status: Some(JobStatus::InProgress),
+            priority: JobPriority::Normal,
+            variables: JobVariables::new(),
+            kind: None,
+            created_at: Some(DateTime::now()),
+            run_at: None,
+            definition_id: None,
+            started_at: None,
+            finished_at: None,
+            uploading: false,
+            scheduled: false,
+            cancelled: false,
+            error: None,
+            callback: None,
         })
     }
 
+    /// Job whose operations are streamed in, counted once the upload is
+    /// finished.
+    pub fn uploading() -> Self {
+        Self {
+            id: None,
+            name: None,
+            labels: BTreeMap::new(),
+            operations: 0,
+            completed_operations: 0,
+            failed_operations: 0,
+            status: Some(JobStatus::Uploading),
+            priority: JobPriority::Normal,
+            variables: JobVariables::new(),
+            kind: None,
+            created_at: Some(DateTime::now()),
+            run_at: None,
+            definition_id: None,
+            started_at: None,
+            finished_at: None,
+            uploading: true,
+            scheduled: false,
+            cancelled: false,
+            error: None,
+            callback: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    #[must_use]
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_priority(mut self, priority: JobPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    #[must_use]
+    pub fn with_variables(mut self, variables: JobVariables) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    #[must_use]
+    pub fn with_kind(mut self, kind: Option<String>) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Holds the dispatch until `run_at`, a time already past leaving the job
+    /// to be dispatched right away.
+    #[must_use]
+    pub fn with_run_at(mut self, run_at: DateTime) -> Self {
+        self.run_at = Some(run_at);
+        self.scheduled = run_at > DateTime::now();
+        if !self.uploading {
+            self.status = Some(self.ready_status());
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn with_definition_id(mut self, definition_id: impl Into<String>) -> Self {
+        self.definition_id = Some(definition_id.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_callback(mut self, callback: JobCallback) -> Self {
+        self.callback = Some(callback);
+        self
+    }
+
     pub fn id(&self) -> String {
         self.id.map(ObjectId::to_hex).unwrap_or_default()
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub const fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
     pub const fn operations(&self) -> usize {
         self.operations
     }
 
-    pub const fn status(&self, total_finished: usize) -> JobStatus {
-        if total_finished == self.operations {
-            JobStatus::Completed
+    pub const fn priority(&self) -> JobPriority {
+        self.priority
+    }
+
+    pub const fn variables(&self) -> &JobVariables {
+        &self.variables
+    }
+
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    pub const fn run_at(&self) -> Option<DateTime> {
+        self.run_at
+    }
+
+    pub fn definition_id(&self) -> Option<&str> {
+        self.definition_id.as_deref()
+    }
+
+    /// Status of the job once all its operations are stored.
+    pub const fn ready_status(&self) -> JobStatus {
+        if self.scheduled {
+            JobStatus::Scheduled
         } else {
             JobStatus::InProgress
         }
     }
+
+    pub fn created_at(&self) -> DateTime {
+        self.created_at
+            .unwrap_or_else(|| self.id.map_or(DateTime::MIN, |id| id.timestamp()))
+    }
+
+    pub const fn started_at(&self) -> Option<DateTime> {
+        self.started_at
+    }
+
+    pub const fn finished_at(&self) -> Option<DateTime> {
+        self.finished_at
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub const fn callback(&self) -> Option<&JobCallback> {
+        self.callback.as_ref()
+    }
+
+    pub const fn completed_operations(&self) -> usize {
+        self.completed_operations
+    }
+
+    pub const fn failed_operations(&self) -> usize {
+        self.failed_operations
+    }
+
+    /// Status stored along with the counters, kept up to date as the results
+    /// come in.
+    pub const fn status(&self) -> JobStatus {
+        match self.status {
+            Some(status) => status,
+            None => self.derive_status(self.completed_operations, self.failed_operations),
+        }
+    }
+
+    /// Status as stored, missing from the jobs stored before it was.
+    pub const fn stored_status(&self) -> Option<JobStatus> {
+        self.status
+    }
+
+    /// Status matching the given counters. `total_finished` counts every
+    /// operation holding a result, successful or not, while `total_failed`
+    /// only counts the failed ones. A cancelled job stays cancelled, and a
+    /// job holding an error stays failed, whatever its counters say.
+    pub const fn derive_status(&self, total_finished: usize, total_failed: usize) -> JobStatus {
+        if self.cancelled {
+            JobStatus::Cancelled
+        } else if self.error.is_some() {
+            JobStatus::Failed
+        } else if self.uploading {
+            JobStatus::Uploading
+        } else if self.scheduled {
+            JobStatus::Scheduled
+        } else if total_finished < self.operations {
+            JobStatus::InProgress
+        } else if total_failed == 0 {
+            JobStatus::Completed
+        } else if total_failed < self.operations {
+            JobStatus::CompletedWithErrors
+        } else {
+            JobStatus::Failed
+        }
+    }
+}
+
+/// Change of the counters of a job when one of its operations moves from a
+/// status to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgressDelta {
+    completed: i64,
+    failed: i64,
+    /// Whether the change comes from results, which start the job, rather
+    /// than from a cancellation.
+    starts_job: bool,
+}
+
+impl ProgressDelta {
+    /// Change of the counters when `cancelled` pending operations are
+    /// cancelled, the job being finished without having run them.
+    pub const fn of_cancellation(cancelled: i64) -> Self {
+        Self {
+            completed: cancelled,
+            failed: 0,
+            starts_job: false,
+        }
+    }
+
+    pub fn of_transition(previous: OperationStatus, current: OperationStatus) -> Self {
+        let finished = |status| i64::from(status != OperationStatus::Pending);
+        let failed = |status| i64::from(status == OperationStatus::Failed);
+
+        Self {
+            completed: finished(current) - finished(previous),
+            failed: failed(current) - failed(previous),
+            starts_job: true,
+        }
+    }
+
+    pub const fn completed(self) -> i64 {
+        self.completed
+    }
+
+    pub const fn failed(self) -> i64 {
+        self.failed
+    }
+
+    pub const fn starts_job(self) -> bool {
+        self.starts_job
+    }
+}
+
+/// Tells whether `host` is the machine itself or its local network link,
+/// which the callbacks must not reach.
+fn is_internal_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_link_local() || ip.is_unspecified(),
+        Ok(IpAddr::V6(ip)) => {
+            ip.is_loopback()
+                || ip.is_unicast_link_local()
+                || ip.is_unspecified()
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_internal_host(&ip.to_string()))
+        }
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Job;
+    use super::JobCallback;
+    use super::JobPriority;
+    use super::JobStatus;
+    use super::JobVariables;
+    use super::ProgressDelta;
+    use super::validate_variables;
+    use crate::domain::operation::OperationStatus;
+    use mongodb::bson::DateTime;
+    use mongodb::bson::doc;
+    use mongodb::bson::oid::ObjectId;
+    use std::time::Duration;
+
+    #[test]
+    fn status_is_in_progress_while_results_are_missing() {
+        // Arrange
+        let job = Job::new(3).unwrap();
+
+        // Act
+        let status = job.derive_status(2, 1);
+
+        // Assert
+        assert_eq!(status, JobStatus::InProgress);
+    }
+
+    #[test]
+    fn status_is_completed_with_errors_on_partial_failure() {
+        // Arrange
+        let job = Job::new(3).unwrap();
+
+        // Act
+        let status = job.derive_status(3, 1);
+
+        // Assert
+        assert_eq!(status, JobStatus::CompletedWithErrors);
+    }
+
+    #[test]
+    fn status_is_failed_when_every_operation_failed() {
+        // Arrange
+        let job = Job::new(3).unwrap();
+
+        // Act
+        let status = job.derive_status(3, 3);
+
+        // Assert
+        assert_eq!(status, JobStatus::Failed);
+    }
+
+    #[test]
+    fn status_is_uploading_until_the_upload_is_finished() {
+        // Arrange
+        let job = Job::uploading();
+
+        // Act
+        let status = job.derive_status(0, 0);
+
+        // Assert
+        assert_eq!(status, JobStatus::Uploading);
+    }
+
+    #[test]
+    fn status_is_scheduled_until_the_job_is_due() {
+        // Arrange
+        let run_at = DateTime::now().saturating_add_duration(Duration::from_mins(1));
+
+        // Act
+        let job = Job::new(3).unwrap().with_run_at(run_at);
+
+        // Assert
+        assert_eq!(job.status(), JobStatus::Scheduled);
+    }
+
+    #[test]
+    fn job_stored_before_its_creation_time_falls_back_on_its_id() {
+        // Arrange
+        let id = ObjectId::new();
+        let document = doc! { "_id": id, "operations": 2_i64, "status": "InProgress" };
+
+        // Act
+        let job: Job = mongodb::bson::from_document(document).unwrap();
+
+        // Assert
+        assert_eq!(job.created_at(), id.timestamp());
+    }
+
+    #[test]
+    fn job_stored_before_its_status_derives_it_from_its_counters() {
+        // Arrange
+        let document = doc! { "operations": 2_i64, "completed_operations": 2_i64 };
+
+        // Act
+        let job: Job = mongodb::bson::from_document(document).unwrap();
+
+        // Assert
+        assert_eq!(job.status(), JobStatus::Completed);
+    }
+
+    #[test]
+    fn status_stays_failed_once_the_job_holds_an_error() {
+        // Arrange
+        let document = doc! { "operations": 2_i64, "error": "Failed to dispatch the operations" };
+
+        // Act
+        let job: Job = mongodb::bson::from_document(document).unwrap();
+
+        // Assert
+        assert_eq!(job.derive_status(1, 0), JobStatus::Failed);
+    }
+
+    #[test]
+    fn variables_reject_names_that_are_not_identifiers() {
+        // Arrange
+        let variables = JobVariables::from([
+            ("rate".to_string(), serde_json::json!(0.07)),
+            ("1n".to_string(), serde_json::json!(12)),
+        ]);
+
+        // Act
+        let result = validate_variables(&variables);
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn progress_delta_moves_a_retried_failure_to_success() {
+        // Arrange
+        let previous = OperationStatus::Failed;
+
+        // Act
+        let delta = ProgressDelta::of_transition(previous, OperationStatus::Succeeded);
+
+        // Assert
+        assert_eq!(delta.completed(), 0);
+        assert_eq!(delta.failed(), -1);
+        assert!(delta.starts_job());
+    }
+
+    #[test]
+    fn progress_delta_of_a_cancellation_leaves_the_job_unstarted() {
+        // Act
+        let delta = ProgressDelta::of_cancellation(3);
+
+        // Assert
+        assert_eq!(delta.completed(), 3);
+        assert_eq!(delta.failed(), 0);
+        assert!(!delta.starts_job());
+    }
+
+    #[test]
+    fn priority_rejects_unknown_lanes() {
+        // Arrange
+        let value = "urgent";
+
+        // Act
+        let priority = value.parse::<JobPriority>();
+
+        // Assert
+        assert!(priority.is_err());
+    }
+
+    #[test]
+    fn callback_rejects_non_http_urls() {
+        // Arrange
+        let url = "ftp://example.com/jobs";
+
+        // Act
+        let callback = JobCallback::new(url, None);
+
+        // Assert
+        assert!(callback.is_err());
+    }
+
+    #[test]
+    fn callback_rejects_internal_hosts() {
+        // Arrange
+        let urls = [
+            "http://localhost:8080/jobs",
+            "http://127.0.0.1/jobs",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/jobs",
+            "http://[::ffff:127.0.0.1]/jobs",
+        ];
+
+        // Act
+        let callbacks = urls.map(|url| JobCallback::new(url, None));
+
+        // Assert
+        assert!(callbacks.iter().all(Result::is_err));
+    }
 }
@@ -0,0 +1,169 @@
+use crate::domain::cron::CronSchedule;
+use crate::domain::dependency_graph::DependencyGraph;
+use crate::domain::job::JobPriority;
+use crate::domain::job::JobVariables;
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use mongodb::bson::oid::ObjectId;
+use std::collections::BTreeMap;
+
+/// Recurring job: its operations are submitted as a new job every time its
+/// cron expression fires, the jobs created being its run history.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct JobDefinition {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    cron: String,
+    operations: Vec<String>,
+    #[serde(default)]
+    priority: JobPriority,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: JobVariables,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(default)]
+    paused: bool,
+    next_run_at: DateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_run_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_job_id: Option<String>,
+    #[serde(default)]
+    runs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease_expires_at: Option<DateTime>,
+    created_at: DateTime,
+}
+
+impl JobDefinition {
+    pub fn new(
+        name: impl Into<String>,
+        cron: impl Into<String>,
+        operations: Vec<String>,
+    ) -> Result<Self> {
+        if operations.is_empty() {
+            anyhow::bail!("A job definition must contain at least one operation");
+        }
+        DependencyGraph::validate_requests(&operations)?;
+
+        let cron = cron.into();
+        let now = DateTime::now();
+        let Some(next_run_at) = CronSchedule::parse(&cron)?.next_after(now) else {
+            anyhow::bail!("The cron expression {cron} never fires");
+        };
+
+        Ok(Self {
+            id: None,
+            name: name.into(),
+            labels: BTreeMap::new(),
+            cron,
+            operations,
+            priority: JobPriority::Normal,
+            variables: JobVariables::new(),
+            kind: None,
+            paused: false,
+            next_run_at,
+            last_run_at: None,
+            last_job_id: None,
+            runs: 0,
+            lease_owner: None,
+            lease_expires_at: None,
+            created_at: now,
+        })
+    }
+
+    #[must_use]
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_priority(mut self, priority: JobPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    #[must_use]
+    pub fn with_variables(mut self, variables: JobVariables) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    #[must_use]
+    pub fn with_kind(mut self, kind: Option<String>) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn id(&self) -> String {
+        self.id.map(ObjectId::to_hex).unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub const fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    pub fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    pub fn operations(&self) -> &[String] {
+        &self.operations
+    }
+
+    pub const fn priority(&self) -> JobPriority {
+        self.priority
+    }
+
+    pub const fn variables(&self) -> &JobVariables {
+        &self.variables
+    }
+
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    pub const fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub const fn next_run_at(&self) -> DateTime {
+        self.next_run_at
+    }
+
+    pub const fn last_run_at(&self) -> Option<DateTime> {
+        self.last_run_at
+    }
+
+    pub fn last_job_id(&self) -> Option<&str> {
+        self.last_job_id.as_deref()
+    }
+
+    /// Number of jobs created from the definition.
+    pub const fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    pub const fn created_at(&self) -> DateTime {
+        self.created_at
+    }
+
+    /// Next time the definition fires after `after`. The runs missed while
+    /// no instance was up are not caught up, the definition firing once for
+    /// all of them.
+    pub fn next_run_after(&self, after: DateTime) -> Result<DateTime> {
+        CronSchedule::parse(&self.cron)?
+            .next_after(after)
+            .ok_or_else(|| anyhow::anyhow!("The cron expression {} never fires", self.cron))
+    }
+}
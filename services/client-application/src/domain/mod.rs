@@ -1,2 +1,5 @@
+pub mod cron;
+pub mod dependency_graph;
 pub mod job;
+pub mod job_definition;
 pub mod operation;
@@ -1,22 +1,188 @@
+use crate::domain::dependency_graph;
+use mongodb::bson::DateTime;
 use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl OperationStatus {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationErrorKind {
+    Syntax,
+    UnknownIdentifier,
+    Type,
+    Arithmetic,
+    Evaluation,
+    /// An operation referenced by this one did not succeed.
+    Dependency,
+    /// No executor handles the kind of the operation.
+    UnknownKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct OperationError {
+    kind: OperationErrorKind,
+    message: String,
+}
+
+impl OperationError {
+    /// Failure of an operation that could not be evaluated because the one
+    /// on `line` did not succeed.
+    pub fn dependency(line: u64) -> Self {
+        Self {
+            kind: OperationErrorKind::Dependency,
+            message: format!("Operation on line {line} did not succeed"),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OperationOutcome {
+    Succeeded { value: String },
+    Failed { error: OperationError },
+    Cancelled,
+}
+
+impl OperationOutcome {
+    pub const fn status(&self) -> OperationStatus {
+        match self {
+            Self::Succeeded { .. } => OperationStatus::Succeeded,
+            Self::Failed { .. } => OperationStatus::Failed,
+            Self::Cancelled => OperationStatus::Cancelled,
+        }
+    }
+}
+
+/// Result as stored or received, the operations stored before the outcome was
+/// structured, and the servers not upgraded since, only holding the value they
+/// succeeded with.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StoredOutcome {
+    Outcome(OperationOutcome),
+    Legacy(String),
+}
+
+impl From<StoredOutcome> for OperationOutcome {
+    fn from(stored: StoredOutcome) -> Self {
+        match stored {
+            StoredOutcome::Outcome(outcome) => outcome,
+            StoredOutcome::Legacy(value) => Self::Succeeded { value },
+        }
+    }
+}
+
+fn deserialize_outcome<'de, D>(deserializer: D) -> Result<Option<OperationOutcome>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<StoredOutcome>::deserialize(deserializer)?.map(OperationOutcome::from))
+}
+
+/// Reads an outcome, or the plain value of a legacy successful one.
+pub fn deserialize_any_outcome<'de, D>(deserializer: D) -> Result<OperationOutcome, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(StoredOutcome::deserialize(deserializer)?.into())
+}
+
+/// Result received for an operation that already had a different one, kept
+/// for investigation rather than overwriting the stored result.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ConflictingResult {
+    attempt: u32,
+    outcome: OperationOutcome,
+    received_at: DateTime,
+}
+
+impl ConflictingResult {
+    pub fn new(attempt: u32, outcome: OperationOutcome) -> Self {
+        Self {
+            attempt,
+            outcome,
+            received_at: DateTime::now(),
+        }
+    }
+}
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Operation {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     id: Option<ObjectId>,
     job_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
     request: String,
+    /// Lines whose result the request references, the operation being
+    /// dispatched once all of them succeeded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dependencies: Vec<u64>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_outcome",
+        skip_serializing_if = "Option::is_none"
+    )]
+    result: Option<OperationOutcome>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    result: Option<String>,
+    result_attempt: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conflicting_results: Vec<ConflictingResult>,
+    /// Missing from the operations stored before it was, taken from their id
+    /// then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime>,
+    /// Set once the request was accepted by Kafka, for the first time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dispatched_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failed_at: Option<DateTime>,
 }
 
 impl Operation {
-    pub fn new(job_id: impl Into<String>, request: impl Into<String>) -> Self {
+    /// `line` is the position of the operation in the submitted job, the
+    /// first one being 1.
+    pub fn new(job_id: impl Into<String>, line: u64, request: impl Into<String>) -> Self {
+        let request = request.into();
+
         Self {
             id: None,
             job_id: job_id.into(),
-            request: request.into(),
+            line: Some(line),
+            dependencies: dependency_graph::references(&request),
+            request,
             result: None,
+            result_attempt: None,
+            conflicting_results: Vec::new(),
+            created_at: Some(DateTime::now()),
+            dispatched_at: None,
+            completed_at: None,
+            failed_at: None,
         }
     }
 
@@ -29,11 +195,87 @@ impl Operation {
         &self.job_id
     }
 
+    /// Position of the operation in the submitted job, unknown for the jobs
+    /// submitted before it was recorded.
+    pub const fn line(&self) -> Option<u64> {
+        self.line
+    }
+
     pub fn request(&self) -> &str {
         &self.request
     }
 
-    pub fn result(&self) -> Option<&str> {
-        self.result.as_deref()
+    pub fn dependencies(&self) -> &[u64] {
+        &self.dependencies
+    }
+
+    pub const fn result(&self) -> Option<&OperationOutcome> {
+        self.result.as_ref()
+    }
+
+    pub const fn conflicting_results(&self) -> usize {
+        self.conflicting_results.len()
+    }
+
+    pub fn created_at(&self) -> DateTime {
+        self.created_at
+            .unwrap_or_else(|| self.id.map_or(DateTime::MIN, |id| id.timestamp()))
+    }
+
+    pub const fn dispatched_at(&self) -> Option<DateTime> {
+        self.dispatched_at
+    }
+
+    /// When the operation succeeded.
+    pub const fn completed_at(&self) -> Option<DateTime> {
+        self.completed_at
+    }
+
+    pub const fn failed_at(&self) -> Option<DateTime> {
+        self.failed_at
+    }
+
+    pub fn status(&self) -> OperationStatus {
+        self.result
+            .as_ref()
+            .map_or(OperationStatus::Pending, OperationOutcome::status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Operation;
+    use super::OperationOutcome;
+    use super::OperationStatus;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn operation_stored_with_a_plain_result_succeeded_with_it() {
+        // Arrange
+        let document = doc! { "job_id": "job", "request": "1 + 1", "result": "2" };
+
+        // Act
+        let operation: Operation = mongodb::bson::from_document(document).unwrap();
+
+        // Assert
+        assert_eq!(
+            operation.result(),
+            Some(&OperationOutcome::Succeeded {
+                value: "2".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn operation_stored_with_an_outcome_reads_it_back() {
+        // Arrange
+        let document =
+            doc! { "job_id": "job", "request": "1 / 0", "result": { "status": "cancelled" } };
+
+        // Act
+        let operation: Operation = mongodb::bson::from_document(document).unwrap();
+
+        // Assert
+        assert_eq!(operation.status(), OperationStatus::Cancelled);
     }
 }
@@ -1,6 +1,9 @@
 use crate::application::context::SharedApplicationState;
+use crate::application::job_event_hub::JobEventSubscription;
+use crate::database::model::OutboxRecord;
 use crate::domain;
 use crate::http;
+use crate::http::model::JobListParams;
 use crate::http::model::PageParams;
 use crate::http::utils::ErrorResponse;
 use anyhow::Result;
@@ -9,8 +12,23 @@ use axum::body::Body;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::header;
 use axum::response::IntoResponse;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
 use common::counter;
+use futures::StreamExt as _;
+use futures::TryStreamExt as _;
+use mongodb::bson::DateTime;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio_util::codec::FramedRead;
+use tokio_util::codec::LinesCodec;
+use tokio_util::io::StreamReader;
 use tracing::Instrument as _;
 
 counter!(
@@ -18,6 +36,11 @@ counter!(
     "http_server_create_job_requests",
     "Number of create job requests"
 );
+counter!(
+    CANCEL_JOB_COUNTER,
+    "http_server_cancel_job_requests",
+    "Number of cancel job requests"
+);
 counter!(
     DELETE_JOB_COUNTER,
     "http_server_delete_job_requests",
@@ -28,97 +51,383 @@ counter!(
     "http_server_get_job_requests",
     "Number of get job requests"
 );
+counter!(
+    GET_JOB_EVENTS_COUNTER,
+    "http_server_get_job_events_requests",
+    "Number of get job events requests"
+);
+counter!(
+    RECONCILE_JOB_COUNTER,
+    "http_server_reconcile_job_requests",
+    "Number of reconcile job requests"
+);
+counter!(
+    RECONCILE_JOBS_COUNTER,
+    "http_server_reconcile_jobs_requests",
+    "Number of reconcile jobs requests"
+);
 counter!(
     GET_JOBS_COUNTER,
     "http_server_get_jobs_requests",
     "Number of get jobs requests"
 );
 
+const CALLBACK_URL_HEADER: &str = "X-Callback-Url";
+const CALLBACK_SECRET_HEADER: &str = "X-Callback-Secret";
+const PRIORITY_HEADER: &str = "X-Priority";
+const RUN_AT_HEADER: &str = "X-Run-At";
+const DELAY_SECONDS_HEADER: &str = "X-Delay-Seconds";
+const VARIABLES_HEADER: &str = "X-Variables";
+const OPERATION_KIND_HEADER: &str = "X-Operation-Kind";
+
+const MAX_DEPENDENT_OPERATIONS_ENV_VAR: &str = "MAX_DEPENDENT_OPERATIONS";
+const DEFAULT_MAX_DEPENDENT_OPERATIONS: usize = 1_000_000;
+
+// The dependencies of an uploaded job are checked once it is complete, so they are held in memory
+static MAX_DEPENDENT_OPERATIONS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var(MAX_DEPENDENT_OPERATIONS_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_DEPENDENT_OPERATIONS)
+});
+
 pub struct JobController;
 
 impl JobController {
-    #[tracing::instrument(skip(body, state))]
+    // Reload the job now and then, in case an event was lost
+    const JOB_EVENTS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+    const JSON_BODY_LIMIT: usize = 10 * 1024 * 1024; // 10MB
+    const MAX_OPERATION_LENGTH: usize = 64 * 1024; // 64KB
+    const UPLOAD_CHUNK_SIZE: usize = 1024;
+
+    #[tracing::instrument(skip(headers, body, state))]
     pub async fn create_job_endpoint_handler(
         State(state): State<SharedApplicationState>,
-        body: String,
+        headers: HeaderMap,
+        body: Body,
     ) -> Result<impl IntoResponse, ErrorResponse> {
         tracing::info!("Creating a new job");
 
         CREATE_JOB_COUNTER.add(1, &[]);
 
-        let lines = body.lines().count();
+        // JSON bodies carry the job metadata, any other body is streamed as one operation per line
+        let new_job_response = if Self::is_json_content(&headers) {
+            Self::create_json_job(&state, &headers, body).await?
+        } else {
+            let callback = Self::parse_callback(None, &headers)?;
+            let run_at = Self::parse_run_at(None, &headers)?;
+            let new_job = domain::job::Job::uploading()
+                .with_priority(Self::parse_priority(None, &headers)?)
+                .with_variables(Self::parse_variables(None, &headers)?)
+                .with_kind(Self::parse_kind(None, &headers)?);
+            Self::upload_job(&state, new_job, callback, run_at, body).await?
+        };
+
+        state.outbox_relay().wake();
+
+        Ok(Json(new_job_response))
+    }
 
-        let new_job = domain::job::Job::new(lines)
+    /// Creates a job submitted as JSON. The body is bounded, so the job, its
+    /// operations and the outbox record are committed together, the outbox
+    /// relay then taking care of dispatching the operations.
+    async fn create_json_job(
+        state: &SharedApplicationState,
+        headers: &HeaderMap,
+        body: Body,
+    ) -> Result<http::model::NewJobResponse, ErrorResponse> {
+        let body = axum::body::to_bytes(body, Self::JSON_BODY_LIMIT)
+            .await
+            .map_err(|err| ErrorResponse::bad_request(format!("Invalid job: {err}")))?;
+        let json_request = serde_json::from_slice::<http::model::NewJobRequest>(&body)
+            .map_err(|err| ErrorResponse::bad_request(format!("Invalid job: {err}")))?;
+
+        domain::dependency_graph::DependencyGraph::validate_requests(json_request.operations())
             .map_err(|err| ErrorResponse::bad_request(err.to_string()))?;
 
+        let mut new_job = domain::job::Job::new(json_request.operations().len())
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()))?
+            .with_name(json_request.name().map(str::to_string))
+            .with_labels(json_request.labels().clone())
+            .with_priority(Self::parse_priority(Some(&json_request), headers)?)
+            .with_variables(Self::parse_variables(Some(&json_request), headers)?)
+            .with_kind(Self::parse_kind(Some(&json_request), headers)?);
+        if let Some(run_at) = Self::parse_run_at(Some(&json_request), headers)? {
+            new_job = new_job.with_run_at(run_at);
+        }
+        if let Some(callback) = Self::parse_callback(Some(&json_request), headers)? {
+            new_job = new_job.with_callback(callback);
+        }
+
+        let mut session = state.database_client().start_transaction().await?;
+
         let job_id = state
             .database_client()
             .job_repository()
-            .insert_job(&new_job)
+            .insert_job(&new_job, &mut session)
             .await?;
 
-        let new_operations: Vec<_> = body
-            .lines()
-            .map(|request| domain::operation::Operation::new(&job_id, request))
+        let new_operations: Vec<_> = json_request
+            .operations()
+            .iter()
+            .zip(1..)
+            .map(|(request, line)| domain::operation::Operation::new(&job_id, line, request))
             .collect();
 
-        // Add the operations to the database
-        if let Err(err) = state
+        state
             .database_client()
             .operation_repository()
-            .insert_operations(&new_operations)
-            .await
-        {
-            tracing::error!("Failed to insert operations: {err}");
+            .insert_operations(&new_operations, &mut session)
+            .await?;
 
-            if let Err(rollback_err) = state
-                .database_client()
-                .job_repository()
-                .delete_job(&job_id)
-                .await
-            {
-                tracing::error!(
-                    "Failed to roll back job {job_id} after insert_operations error: {rollback_err}"
-                );
-            }
+        state
+            .database_client()
+            .outbox_repository()
+            .insert_record(
+                &OutboxRecord::new(&job_id, new_job.priority())
+                    .with_run_at(new_job.run_at())
+                    .with_variables(new_job.variables().clone())
+                    .with_kind(new_job.kind().map(str::to_string)),
+                &mut session,
+            )
+            .await?;
+
+        session.commit_transaction().await?;
+
+        Ok(http::model::NewJobResponse::new(
+            job_id,
+            new_job.operations(),
+            new_job.status(),
+        ))
+    }
 
-            return Err(err.into());
+    /// Creates a job from a text body of any size. The lines are streamed into
+    /// the database by chunks, while the dependencies of up to
+    /// `MAX_DEPENDENT_OPERATIONS` of them are kept in memory to be checked at
+    /// the end, then the operation count and the outbox record are committed
+    /// together. An upload failing or aborted halfway through is removed.
+    async fn upload_job(
+        state: &SharedApplicationState,
+        mut new_job: domain::job::Job,
+        callback: Option<domain::job::JobCallback>,
+        run_at: Option<DateTime>,
+        body: Body,
+    ) -> Result<http::model::NewJobResponse, ErrorResponse> {
+        if let Some(run_at) = run_at {
+            new_job = new_job.with_run_at(run_at);
+        }
+        if let Some(callback) = callback {
+            new_job = new_job.with_callback(callback);
         }
 
-        // Send operation request message asynchronously
-        let state_cloned = state.clone();
-        let job_id_cloned = job_id.clone();
-        let parent_span = tracing::Span::current();
-        tokio::spawn(
-            async move {
-                const CHUNK_SIZE: u32 = 128;
+        let job_id = state
+            .database_client()
+            .job_repository()
+            .start_upload(&new_job)
+            .await?;
+        let cleanup = UploadCleanup::new(state, &job_id);
 
-                let message_producer = state_cloned.message_producer();
+        let mut lines = FramedRead::new(
+            StreamReader::new(body.into_data_stream().map_err(std::io::Error::other)),
+            LinesCodec::new_with_max_length(Self::MAX_OPERATION_LENGTH),
+        );
 
-                if let Err(err) = state_cloned
+        let mut chunk = Vec::with_capacity(Self::UPLOAD_CHUNK_SIZE);
+        let mut dependency_graph = domain::dependency_graph::DependencyGraph::default();
+        let mut total_operations = 0;
+        while let Some(line) = lines.next().await {
+            let request = line.map_err(|err| {
+                ErrorResponse::bad_request(format!(
+                    "Invalid operation on line {}: {err}",
+                    total_operations + 1
+                ))
+            })?;
+
+            total_operations += 1;
+            let operation =
+                domain::operation::Operation::new(&job_id, total_operations as u64, request);
+            if !operation.dependencies().is_empty()
+                && dependency_graph.dependent_operations() == *MAX_DEPENDENT_OPERATIONS
+            {
+                return Err(ErrorResponse::bad_request(format!(
+                    "A job must contain at most {} operations referencing other results",
+                    *MAX_DEPENDENT_OPERATIONS
+                )));
+            }
+            dependency_graph.add(total_operations as u64, operation.dependencies());
+            chunk.push(operation);
+
+            if chunk.len() == Self::UPLOAD_CHUNK_SIZE {
+                state
                     .database_client()
                     .operation_repository()
-                    .get_batch_operations(
-                        &job_id_cloned,
-                        CHUNK_SIZE,
-                        move |operation: domain::operation::Operation| async move {
-                            message_producer.send_operation_request(operation);
-                        },
-                    )
-                    .await
-                {
-                    tracing::error!(
-                        "Failed to get batch operations for job {job_id_cloned}: {err}",
-                    );
-                }
+                    .append_operations(&chunk)
+                    .await?;
+                chunk.clear();
             }
-            .instrument(parent_span),
-        );
+        }
 
-        Ok(Json(http::model::NewJobResponse::new(
+        if !chunk.is_empty() {
+            state
+                .database_client()
+                .operation_repository()
+                .append_operations(&chunk)
+                .await?;
+        }
+
+        if total_operations == 0 {
+            return Err(ErrorResponse::bad_request(
+                "A job must contain at least one operation",
+            ));
+        }
+        dependency_graph
+            .validate(total_operations as u64)
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()))?;
+
+        let mut session = state.database_client().start_transaction().await?;
+
+        if !state
+            .database_client()
+            .job_repository()
+            .finish_upload(
+                &job_id,
+                total_operations,
+                new_job.ready_status(),
+                &mut session,
+            )
+            .await?
+        {
+            return Err(ErrorResponse::conflict(format!(
+                "Job {job_id} was cancelled during its upload"
+            )));
+        }
+
+        state
+            .database_client()
+            .outbox_repository()
+            .insert_record(
+                &OutboxRecord::new(&job_id, new_job.priority())
+                    .with_run_at(new_job.run_at())
+                    .with_variables(new_job.variables().clone())
+                    .with_kind(new_job.kind().map(str::to_string)),
+                &mut session,
+            )
+            .await?;
+
+        session.commit_transaction().await?;
+        cleanup.disarm();
+
+        tracing::info!("Uploaded {total_operations} operations for job {job_id}");
+
+        Ok(http::model::NewJobResponse::new(
             job_id,
-            new_job.operations(),
-        )))
+            total_operations,
+            new_job.ready_status(),
+        ))
+    }
+
+    #[tracing::instrument(skip(state))]
+    pub async fn cancel_job_endpoint_handler(
+        Path(job_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Cancelling job {}", job_id);
+
+        CANCEL_JOB_COUNTER.add(1, &[]);
+
+        let job_response = Self::load_job_response(&state, &job_id).await?;
+        if job_response.status().is_terminal() {
+            return Err(ErrorResponse::conflict(format!(
+                "Job {job_id} is already {:?}",
+                job_response.status()
+            )));
+        }
+
+        // The job may have finished since it was read
+        if !state
+            .database_client()
+            .job_repository()
+            .cancel_job(&job_id)
+            .await?
+        {
+            return Err(ErrorResponse::conflict(format!(
+                "Job {job_id} finished before it could be cancelled"
+            )));
+        }
+
+        state
+            .database_client()
+            .outbox_repository()
+            .cancel_records(&job_id)
+            .await?;
+
+        // Stop the local dispatch right away, and let the other instances and the workers know
+        state.dispatch_registry().cancel(&job_id);
+        state.job_event_notifier().notify(&job_id);
+        if let Err(err) = state
+            .message_producer()
+            .send_job_cancellation(&job_id)
+            .await
+        {
+            // The cancellation is persisted, the workers only miss the chance to skip early
+            tracing::warn!("Failed to broadcast the cancellation of job {job_id}: {err}");
+        }
+
+        // Counted within the same transaction, so no cancelled operation is left out
+        let mut session = state.database_client().start_transaction().await?;
+
+        let cancelled_operations = state
+            .database_client()
+            .operation_repository()
+            .cancel_pending_operations(&job_id, &mut session)
+            .await?;
+
+        state
+            .database_client()
+            .job_repository()
+            .record_progress(
+                &job_id,
+                domain::job::ProgressDelta::of_cancellation(i64::try_from(cancelled_operations)?),
+                &mut session,
+            )
+            .await?;
+
+        session.commit_transaction().await?;
+
+        tracing::info!("Cancelled {cancelled_operations} pending operations of job {job_id}");
+
+        Ok(Json(Self::load_job_response(&state, &job_id).await?))
+    }
+
+    /// Rebuilds the counters of a job from its operations.
+    #[tracing::instrument(skip(state))]
+    pub async fn reconcile_job_endpoint_handler(
+        Path(job_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Reconciling job {}", job_id);
+
+        RECONCILE_JOB_COUNTER.add(1, &[]);
+
+        state.progress_reconciler().reconcile_job(&job_id).await?;
+        state.job_event_notifier().notify(&job_id);
+
+        Ok(Json(Self::load_job_response(&state, &job_id).await?))
+    }
+
+    /// Rebuilds the counters of every job from their operations.
+    #[tracing::instrument(skip(state))]
+    pub async fn reconcile_jobs_endpoint_handler(
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Reconciling all the jobs");
+
+        RECONCILE_JOBS_COUNTER.add(1, &[]);
+
+        let reconciled_jobs = state.progress_reconciler().reconcile_jobs().await?;
+
+        Ok(Json(http::model::ReconcileResponse::new(reconciled_jobs)))
     }
 
     #[tracing::instrument(skip(state))]
@@ -141,6 +450,18 @@ impl JobController {
         let parent_span = tracing::Span::current();
         tokio::spawn(
             async move {
+                state_cloned.dispatch_registry().cancel(&job_id);
+                state_cloned.job_event_notifier().notify(&job_id);
+
+                if let Err(err) = state_cloned
+                    .database_client()
+                    .outbox_repository()
+                    .delete_records(&job_id)
+                    .await
+                {
+                    tracing::error!("Failed to delete outbox records for job {job_id}: {err}");
+                }
+
                 if let Err(err) = state_cloned
                     .database_client()
                     .operation_repository()
@@ -165,50 +486,396 @@ impl JobController {
 
         GET_JOB_COUNTER.add(1, &[]);
 
-        let job = state
-            .database_client()
-            .job_repository()
-            .get_job(&job_id)
-            .await?;
+        Ok(Json(Self::load_job_response(&state, &job_id).await?))
+    }
 
-        let total_completed_operations = state
-            .database_client()
-            .operation_repository()
-            .get_total_completed_operations(&job_id)
-            .await?;
+    /// Streams the progress of a job as server-sent events: a `progress`
+    /// event whenever its counters change, then a single `status` event once
+    /// the job is finished, which ends the stream.
+    #[tracing::instrument(skip(state))]
+    pub async fn get_job_events_endpoint_handler(
+        Path(job_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Streaming the events of job {}", job_id);
+
+        GET_JOB_EVENTS_COUNTER.add(1, &[]);
 
-        Ok(Json(http::model::JobResponse::new(
-            &job,
-            total_completed_operations,
-        )))
+        // Subscribe before loading the job, so no change falls in between
+        let subscription = state.job_event_hub().subscribe(&job_id);
+        let job_response = Self::load_job_response(&state, &job_id).await?;
+
+        let events = futures::stream::unfold(
+            JobEventStream {
+                state,
+                job_id,
+                subscription,
+                last_progress: None,
+                next_progress: Some(job_response),
+                finished: false,
+            },
+            Self::next_job_event,
+        );
+
+        Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+    }
+
+    async fn next_job_event(
+        mut stream: JobEventStream,
+    ) -> Option<(Result<Event, Infallible>, JobEventStream)> {
+        if stream.finished {
+            return None;
+        }
+
+        loop {
+            let job_response = if let Some(job_response) = stream.next_progress.take() {
+                job_response
+            } else {
+                tokio::select! {
+                    () = stream.subscription.changed() => {}
+                    () = tokio::time::sleep(Self::JOB_EVENTS_REFRESH_INTERVAL) => {}
+                }
+
+                match Self::load_job_response(&stream.state, &stream.job_id).await {
+                    Ok(job_response) => job_response,
+                    Err(err) => {
+                        // Most likely the job was deleted, the client may reconnect to find out
+                        tracing::warn!(
+                            "Failed to load job {} for its events: {err}",
+                            stream.job_id
+                        );
+                        stream.finished = true;
+                        let event = Event::default().event("error").data(err.to_string());
+                        return Some((Ok(event), stream));
+                    }
+                }
+            };
+
+            if job_response.status().is_terminal() {
+                stream.finished = true;
+                return Some((Ok(Self::job_event("status", &job_response)), stream));
+            }
+
+            if stream.last_progress.as_ref() != Some(&job_response) {
+                let event = Self::job_event("progress", &job_response);
+                stream.last_progress = Some(job_response);
+                return Some((Ok(event), stream));
+            }
+        }
+    }
+
+    fn job_event(name: &str, job_response: &http::model::JobResponse) -> Event {
+        Event::default()
+            .event(name)
+            .json_data(job_response)
+            .unwrap_or_else(|err| Event::default().event("error").data(err.to_string()))
     }
 
     #[tracing::instrument(skip(state))]
     pub async fn get_jobs_endpoint_handler(
         Query(params): Query<PageParams>,
+        Query(list_params): Query<JobListParams>,
         State(state): State<SharedApplicationState>,
     ) -> Result<impl IntoResponse, ErrorResponse> {
         tracing::info!("Getting all the jobs");
 
         GET_JOBS_COUNTER.add(1, &[]);
 
-        let page = params.page();
         let page_size = params.size();
+        let filter = list_params.filter()?;
+        let unfiltered = filter.is_empty() && list_params.is_insertion_order();
+
+        if let Some(keyset) = params.keyset()? {
+            if !unfiltered {
+                return Err(ErrorResponse::bad_request(
+                    "Cursors cannot be combined with filters or sorting",
+                ));
+            }
+
+            let jobs = state
+                .database_client()
+                .job_repository()
+                .get_jobs_page(&keyset, page_size)
+                .await?;
+
+            let estimated_total = if params.estimate_total() {
+                Some(
+                    state
+                        .database_client()
+                        .job_repository()
+                        .estimate_total_jobs()
+                        .await?,
+                )
+            } else {
+                None
+            };
+
+            return Ok(Json(
+                http::model::PageResponse::<http::model::MinimalJobResponse>::from_keyset(
+                    page_size,
+                    &jobs,
+                    domain::job::Job::id,
+                )
+                .with_estimated_total(estimated_total),
+            ));
+        }
 
+        let page = params.page();
         let jobs = state
             .database_client()
             .job_repository()
-            .get_jobs(page, page_size)
+            .get_jobs(
+                &filter,
+                list_params.sort(),
+                list_params.order(),
+                page,
+                page_size,
+            )
             .await?;
 
-        Ok(Json(http::model::PageResponse::new(
+        let page_response = http::model::PageResponse::<http::model::MinimalJobResponse>::new(
             page,
             page_size,
-            jobs.total(),
-            jobs.items_subset()
-                .iter()
-                .map(http::model::MinimalJobResponse::from)
-                .collect(),
-        )))
+            &jobs,
+            domain::job::Job::id,
+        );
+
+        Ok(Json(if unfiltered {
+            page_response
+        } else {
+            page_response.without_cursors()
+        }))
+    }
+
+    fn is_json_content(headers: &HeaderMap) -> bool {
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    }
+
+    /// Reads the optional callback of a new job from the options of a JSON
+    /// job, or else from the request headers.
+    fn parse_callback(
+        json_request: Option<&http::model::NewJobRequest>,
+        headers: &HeaderMap,
+    ) -> Result<Option<domain::job::JobCallback>, ErrorResponse> {
+        if let Some(options) = json_request.map(http::model::NewJobRequest::options)
+            && let Some(url) = options.callback_url()
+        {
+            return domain::job::JobCallback::new(
+                url,
+                options.callback_secret().map(str::to_string),
+            )
+            .map(Some)
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()));
+        }
+
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .map(str::to_string)
+                        .map_err(|_| ErrorResponse::bad_request(format!("Invalid {name} header")))
+                })
+                .transpose()
+        };
+
+        let Some(url) = header_value(CALLBACK_URL_HEADER)? else {
+            return Ok(None);
+        };
+
+        domain::job::JobCallback::new(url, header_value(CALLBACK_SECRET_HEADER)?)
+            .map(Some)
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()))
+    }
+
+    /// Reads the priority of a new job from the options of a JSON job, or
+    /// else from the request headers, defaulting to the normal lane.
+    fn parse_priority(
+        json_request: Option<&http::model::NewJobRequest>,
+        headers: &HeaderMap,
+    ) -> Result<domain::job::JobPriority, ErrorResponse> {
+        if let Some(priority) = json_request.and_then(|request| request.options().priority()) {
+            return Ok(priority);
+        }
+
+        let Some(value) = headers.get(PRIORITY_HEADER) else {
+            return Ok(domain::job::JobPriority::default());
+        };
+
+        value
+            .to_str()
+            .map_err(|_| ErrorResponse::bad_request(format!("Invalid {PRIORITY_HEADER} header")))?
+            .parse()
+            .map_err(|err: anyhow::Error| ErrorResponse::bad_request(err.to_string()))
+    }
+
+    /// Reads the variables of a new job from a JSON job, or else from the
+    /// request headers as a JSON object.
+    fn parse_variables(
+        json_request: Option<&http::model::NewJobRequest>,
+        headers: &HeaderMap,
+    ) -> Result<domain::job::JobVariables, ErrorResponse> {
+        let variables = match json_request.map(http::model::NewJobRequest::variables) {
+            Some(variables) if !variables.is_empty() => variables.clone(),
+            _ => match headers.get(VARIABLES_HEADER) {
+                Some(value) => value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| serde_json::from_str(value).ok())
+                    .ok_or_else(|| {
+                        ErrorResponse::bad_request(format!("Invalid {VARIABLES_HEADER} header"))
+                    })?,
+                None => domain::job::JobVariables::new(),
+            },
+        };
+
+        domain::job::validate_variables(&variables)
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()))?;
+
+        Ok(variables)
+    }
+
+    /// Reads the optional kind of the operations of a new job from the
+    /// options of a JSON job, or else from the request headers.
+    fn parse_kind(
+        json_request: Option<&http::model::NewJobRequest>,
+        headers: &HeaderMap,
+    ) -> Result<Option<String>, ErrorResponse> {
+        let kind = match json_request.and_then(|request| request.options().kind()) {
+            Some(kind) => Some(kind.to_string()),
+            None => headers
+                .get(OPERATION_KIND_HEADER)
+                .map(|value| {
+                    value.to_str().map(str::to_string).map_err(|_| {
+                        ErrorResponse::bad_request(format!(
+                            "Invalid {OPERATION_KIND_HEADER} header"
+                        ))
+                    })
+                })
+                .transpose()?,
+        };
+
+        if kind.as_deref().is_some_and(|kind| kind.trim().is_empty()) {
+            return Err(ErrorResponse::bad_request(
+                "The operation kind cannot be empty",
+            ));
+        }
+
+        Ok(kind)
+    }
+
+    /// Reads the optional time a new job is held until from the options of a
+    /// JSON job, or else from the request headers.
+    fn parse_run_at(
+        json_request: Option<&http::model::NewJobRequest>,
+        headers: &HeaderMap,
+    ) -> Result<Option<DateTime>, ErrorResponse> {
+        if let Some(options) = json_request.map(http::model::NewJobRequest::options)
+            && (options.run_at().is_some() || options.delay_seconds().is_some())
+        {
+            return http::model::parse_run_at(options.run_at(), options.delay_seconds());
+        }
+
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .map_err(|_| ErrorResponse::bad_request(format!("Invalid {name} header")))
+                })
+                .transpose()
+        };
+
+        let delay_seconds = header_value(DELAY_SECONDS_HEADER)?
+            .map(|value| {
+                value.parse::<u64>().map_err(|_| {
+                    ErrorResponse::bad_request(format!("Invalid {DELAY_SECONDS_HEADER} header"))
+                })
+            })
+            .transpose()?;
+
+        http::model::parse_run_at(header_value(RUN_AT_HEADER)?, delay_seconds)
+    }
+
+    async fn load_job_response(
+        state: &SharedApplicationState,
+        job_id: &str,
+    ) -> Result<http::model::JobResponse> {
+        let job = state
+            .database_client()
+            .job_repository()
+            .get_job(job_id)
+            .await?;
+
+        Ok(http::model::JobResponse::new(&job))
+    }
+}
+
+struct JobEventStream {
+    state: SharedApplicationState,
+    job_id: String,
+    subscription: JobEventSubscription,
+    last_progress: Option<http::model::JobResponse>,
+    next_progress: Option<http::model::JobResponse>,
+    finished: bool,
+}
+
+/// Removes a job whose upload did not finish, including when the request is
+/// dropped because the client went away.
+struct UploadCleanup {
+    state: Option<SharedApplicationState>,
+    job_id: String,
+}
+
+impl UploadCleanup {
+    fn new(state: &SharedApplicationState, job_id: &str) -> Self {
+        Self {
+            state: Some(Arc::clone(state)),
+            job_id: job_id.to_string(),
+        }
+    }
+
+    fn disarm(mut self) {
+        self.state = None;
+    }
+}
+
+impl Drop for UploadCleanup {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let job_id = std::mem::take(&mut self.job_id);
+
+        tracing::warn!("Upload of job {job_id} aborted, removing it");
+
+        tokio::spawn(
+            async move {
+                if let Err(err) = state
+                    .database_client()
+                    .operation_repository()
+                    .delete_operations(&job_id)
+                    .await
+                {
+                    tracing::error!("Failed to delete operations for job {job_id}: {err}");
+                }
+
+                if let Err(err) = state
+                    .database_client()
+                    .job_repository()
+                    .delete_job(&job_id)
+                    .await
+                {
+                    tracing::error!("Failed to delete uploading job {job_id}: {err}");
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
     }
 }
@@ -0,0 +1,256 @@
+use crate::application::context::SharedApplicationState;
+use crate::database::model::JobFilter;
+use crate::database::model::JobSort;
+use crate::database::model::SortOrder;
+use crate::domain;
+use crate::http;
+use crate::http::model::PageParams;
+use crate::http::utils::ErrorResponse;
+use axum::Json;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use common::counter;
+use mongodb::bson::DateTime;
+
+counter!(
+    CREATE_JOB_DEFINITION_COUNTER,
+    "http_server_create_job_definition_requests",
+    "Number of create job definition requests"
+);
+counter!(
+    GET_JOB_DEFINITION_COUNTER,
+    "http_server_get_job_definition_requests",
+    "Number of get job definition requests"
+);
+counter!(
+    GET_JOB_DEFINITIONS_COUNTER,
+    "http_server_get_job_definitions_requests",
+    "Number of get job definitions requests"
+);
+counter!(
+    PAUSE_JOB_DEFINITION_COUNTER,
+    "http_server_pause_job_definition_requests",
+    "Number of pause job definition requests"
+);
+counter!(
+    RESUME_JOB_DEFINITION_COUNTER,
+    "http_server_resume_job_definition_requests",
+    "Number of resume job definition requests"
+);
+counter!(
+    DELETE_JOB_DEFINITION_COUNTER,
+    "http_server_delete_job_definition_requests",
+    "Number of delete job definition requests"
+);
+counter!(
+    GET_JOB_DEFINITION_RUNS_COUNTER,
+    "http_server_get_job_definition_runs_requests",
+    "Number of get job definition runs requests"
+);
+
+pub struct JobDefinitionController;
+
+impl JobDefinitionController {
+    #[tracing::instrument(skip(state, request))]
+    pub async fn create_definition_endpoint_handler(
+        State(state): State<SharedApplicationState>,
+        Json(request): Json<http::model::NewJobDefinitionRequest>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Creating a new job definition");
+
+        CREATE_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let definition = request.into_definition()?;
+
+        let definition_id = state
+            .database_client()
+            .job_definition_repository()
+            .insert_definition(&definition)
+            .await?;
+
+        Ok(Json(
+            Self::load_definition_response(&state, &definition_id).await?,
+        ))
+    }
+
+    #[tracing::instrument(skip(state))]
+    pub async fn get_definitions_endpoint_handler(
+        Query(params): Query<PageParams>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Getting all the job definitions");
+
+        GET_JOB_DEFINITIONS_COUNTER.add(1, &[]);
+
+        if params.keyset()?.is_some() {
+            return Err(ErrorResponse::bad_request(
+                "Job definitions are paged by number only",
+            ));
+        }
+
+        let page = params.page();
+        let page_size = params.size();
+        let definitions = state
+            .database_client()
+            .job_definition_repository()
+            .get_definitions(page, page_size)
+            .await?;
+
+        Ok(Json(
+            http::model::PageResponse::<http::model::JobDefinitionResponse>::new(
+                page,
+                page_size,
+                &definitions,
+                domain::job_definition::JobDefinition::id,
+            )
+            .without_cursors(),
+        ))
+    }
+
+    #[tracing::instrument(skip(state))]
+    pub async fn get_definition_endpoint_handler(
+        Path(definition_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Getting job definition {}", definition_id);
+
+        GET_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        Ok(Json(
+            Self::load_definition_response(&state, &definition_id).await?,
+        ))
+    }
+
+    #[tracing::instrument(skip(state))]
+    pub async fn pause_definition_endpoint_handler(
+        Path(definition_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Pausing job definition {}", definition_id);
+
+        PAUSE_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        if !state
+            .database_client()
+            .job_definition_repository()
+            .pause_definition(&definition_id)
+            .await?
+        {
+            return Err(anyhow::anyhow!("Document not found").into());
+        }
+
+        Ok(Json(
+            Self::load_definition_response(&state, &definition_id).await?,
+        ))
+    }
+
+    /// Resumes a paused definition from its next run after now, the runs
+    /// missed while paused being skipped.
+    #[tracing::instrument(skip(state))]
+    pub async fn resume_definition_endpoint_handler(
+        Path(definition_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Resuming job definition {}", definition_id);
+
+        RESUME_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        let definition = state
+            .database_client()
+            .job_definition_repository()
+            .get_definition(&definition_id)
+            .await?;
+        if !definition.paused() {
+            return Err(ErrorResponse::conflict(format!(
+                "Job definition {definition_id} is not paused"
+            )));
+        }
+
+        state
+            .database_client()
+            .job_definition_repository()
+            .resume_definition(&definition_id, definition.next_run_after(DateTime::now())?)
+            .await?;
+
+        Ok(Json(
+            Self::load_definition_response(&state, &definition_id).await?,
+        ))
+    }
+
+    /// Deletes a definition, the jobs it already created being kept.
+    #[tracing::instrument(skip(state))]
+    pub async fn delete_definition_endpoint_handler(
+        Path(definition_id): Path<String>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Deleting job definition {}", definition_id);
+
+        DELETE_JOB_DEFINITION_COUNTER.add(1, &[]);
+
+        state
+            .database_client()
+            .job_definition_repository()
+            .delete_definition(&definition_id)
+            .await?;
+
+        Ok(Body::empty())
+    }
+
+    /// Lists the jobs created from a definition, the most recent first.
+    #[tracing::instrument(skip(state))]
+    pub async fn get_definition_runs_endpoint_handler(
+        Path(definition_id): Path<String>,
+        Query(params): Query<PageParams>,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Getting the runs of job definition {}", definition_id);
+
+        GET_JOB_DEFINITION_RUNS_COUNTER.add(1, &[]);
+
+        if params.keyset()?.is_some() {
+            return Err(ErrorResponse::bad_request(
+                "Job definition runs are paged by number only",
+            ));
+        }
+
+        let page = params.page();
+        let page_size = params.size();
+        let jobs = state
+            .database_client()
+            .job_repository()
+            .get_jobs(
+                &JobFilter::default().with_definition_id(Some(definition_id)),
+                Some(JobSort::CreatedAt),
+                SortOrder::Desc,
+                page,
+                page_size,
+            )
+            .await?;
+
+        Ok(Json(
+            http::model::PageResponse::<http::model::MinimalJobResponse>::new(
+                page,
+                page_size,
+                &jobs,
+                domain::job::Job::id,
+            )
+            .without_cursors(),
+        ))
+    }
+
+    async fn load_definition_response(
+        state: &SharedApplicationState,
+        definition_id: &str,
+    ) -> anyhow::Result<http::model::JobDefinitionResponse> {
+        let definition = state
+            .database_client()
+            .job_definition_repository()
+            .get_definition(definition_id)
+            .await?;
+
+        Ok(http::model::JobDefinitionResponse::from(&definition))
+    }
+}
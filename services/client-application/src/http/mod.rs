@@ -1,8 +1,10 @@
 mod job_controller;
+mod job_definition_controller;
 mod operation_controller;
 
 pub mod model;
 pub mod utils;
 
 pub use job_controller::JobController;
+pub use job_definition_controller::JobDefinitionController;
 pub use operation_controller::OperationController;
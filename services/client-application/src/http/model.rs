@@ -1,8 +1,104 @@
+use crate::database::model::JobFilter;
+use crate::database::model::JobSort;
+use crate::database::model::Keyset;
+use crate::database::model::KeysetPage;
+use crate::database::model::PageSubset;
+use crate::database::model::SortOrder;
 use crate::domain;
+use crate::domain::job::CallbackDelivery;
+use crate::domain::job::CallbackState;
+use crate::domain::job::JobPriority;
 use crate::domain::job::JobStatus;
+use crate::domain::job::JobVariables;
+use crate::domain::operation::OperationError;
+use crate::domain::operation::OperationOutcome;
+use crate::domain::operation::OperationStatus;
+use crate::http::utils::ErrorResponse;
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use mongodb::bson::DateTime;
+use mongodb::bson::oid::ObjectId;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 // Job models
 
+/// Options of a job submitted as JSON.
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewJobOptions {
+    callback_url: Option<String>,
+    callback_secret: Option<String>,
+    priority: Option<JobPriority>,
+    /// Executor of the operations on the servers.
+    kind: Option<String>,
+    /// RFC 3339 time to hold the dispatch until.
+    run_at: Option<String>,
+    delay_seconds: Option<u64>,
+}
+
+impl NewJobOptions {
+    pub fn run_at(&self) -> Option<&str> {
+        self.run_at.as_deref()
+    }
+
+    pub const fn delay_seconds(&self) -> Option<u64> {
+        self.delay_seconds
+    }
+
+    pub const fn priority(&self) -> Option<JobPriority> {
+        self.priority
+    }
+
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    pub fn callback_url(&self) -> Option<&str> {
+        self.callback_url.as_deref()
+    }
+
+    pub fn callback_secret(&self) -> Option<&str> {
+        self.callback_secret.as_deref()
+    }
+}
+
+/// Job submitted as JSON, the text format only carrying the operations.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewJobRequest {
+    name: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    variables: JobVariables,
+    operations: Vec<String>,
+    #[serde(default)]
+    options: NewJobOptions,
+}
+
+impl NewJobRequest {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub const fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    pub const fn variables(&self) -> &JobVariables {
+        &self.variables
+    }
+
+    pub fn operations(&self) -> &[String] {
+        &self.operations
+    }
+
+    pub const fn options(&self) -> &NewJobOptions {
+        &self.options
+    }
+}
+
 #[derive(serde::Serialize)]
 pub struct NewJobResponse {
     id: String,
@@ -11,40 +107,312 @@ pub struct NewJobResponse {
 }
 
 impl NewJobResponse {
-    pub fn new(id: impl Into<String>, created_operations: usize) -> Self {
+    pub fn new(id: impl Into<String>, created_operations: usize, status: JobStatus) -> Self {
         Self {
             id: id.into(),
             created_operations,
-            status: JobStatus::InProgress,
+            status,
         }
     }
 }
 
-#[derive(serde::Serialize)]
+/// Resolves the time a new job is held until, given either as an RFC 3339
+/// `run_at` time or as a delay from now.
+pub fn parse_run_at(
+    run_at: Option<&str>,
+    delay_seconds: Option<u64>,
+) -> Result<Option<DateTime>, ErrorResponse> {
+    match (parse_date_time("run_at", run_at)?, delay_seconds) {
+        (Some(_), Some(_)) => Err(ErrorResponse::bad_request(
+            "A job is scheduled either by run_at or by delay_seconds",
+        )),
+        (Some(run_at), None) => Ok(Some(run_at)),
+        (None, Some(delay_seconds)) => Ok(Some(
+            DateTime::now().saturating_add_duration(Duration::from_secs(delay_seconds)),
+        )),
+        (None, None) => Ok(None),
+    }
+}
+
+#[derive(PartialEq, Eq, serde::Serialize)]
 pub struct JobResponse {
     id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
     operations: usize,
+    completed_operations: usize,
+    failed_operations: usize,
     status: JobStatus,
+    priority: JobPriority,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: JobVariables,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    definition_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<String>,
+    /// Wall-clock time from the creation of the job to its finish.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<i64>,
+    /// Why the job could not run to its end.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callback: Option<CallbackResponse>,
 }
 
 impl JobResponse {
-    pub fn new(job: &domain::job::Job, total_completed_operations: usize) -> Self {
+    pub fn new(job: &domain::job::Job) -> Self {
+        let status = job.status();
+        let finished_at = job.finished_at().filter(|_| status.is_terminal());
+
         Self {
             id: job.id(),
+            name: job.name().map(str::to_string),
+            labels: job.labels().clone(),
             operations: job.operations(),
-            status: job.status(total_completed_operations),
+            completed_operations: job.completed_operations(),
+            failed_operations: job.failed_operations(),
+            status,
+            priority: job.priority(),
+            variables: job.variables().clone(),
+            kind: job.kind().map(str::to_string),
+            created_at: format_date_time(job.created_at()),
+            run_at: job.run_at().map(format_date_time),
+            definition_id: job.definition_id().map(str::to_string),
+            started_at: job.started_at().map(format_date_time),
+            finished_at: finished_at.map(format_date_time),
+            duration_ms: finished_at.map(|finished_at| {
+                finished_at.timestamp_millis() - job.created_at().timestamp_millis()
+            }),
+            error: job.error().map(str::to_string),
+            callback: job.callback().map(CallbackResponse::from),
         }
     }
+
+    pub const fn status(&self) -> JobStatus {
+        self.status
+    }
+}
+
+/// Delivery log of the callback of a job, its secret left out.
+#[derive(PartialEq, Eq, serde::Serialize)]
+pub struct CallbackResponse {
+    url: String,
+    state: CallbackState,
+    deliveries: Vec<CallbackDelivery>,
 }
 
+impl From<&domain::job::JobCallback> for CallbackResponse {
+    fn from(callback: &domain::job::JobCallback) -> Self {
+        Self {
+            url: callback.url().to_string(),
+            state: callback.state(),
+            deliveries: callback.deliveries().to_vec(),
+        }
+    }
+}
+
+/// Listed job, with enough of its progress to render a dashboard.
 #[derive(serde::Serialize)]
 pub struct MinimalJobResponse {
     id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    status: JobStatus,
+    priority: JobPriority,
+    operations: usize,
+    completed_operations: usize,
+    failed_operations: usize,
+    /// Percentage of the operations holding a result.
+    progress: usize,
+    created_at: String,
 }
 
 impl From<&domain::job::Job> for MinimalJobResponse {
     fn from(job: &domain::job::Job) -> Self {
-        Self { id: job.id() }
+        Self {
+            id: job.id(),
+            name: job.name().map(str::to_string),
+            status: job.status(),
+            priority: job.priority(),
+            operations: job.operations(),
+            completed_operations: job.completed_operations(),
+            failed_operations: job.failed_operations(),
+            progress: (job.completed_operations() * 100)
+                .checked_div(job.operations())
+                .unwrap_or_default(),
+            created_at: format_date_time(job.created_at()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ReconcileResponse {
+    reconciled_jobs: usize,
+}
+
+impl ReconcileResponse {
+    pub const fn new(reconciled_jobs: usize) -> Self {
+        Self { reconciled_jobs }
+    }
+}
+
+/// Filters and order of the job listing.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct JobListParams {
+    status: Option<JobStatus>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    /// Label written as `key:value`.
+    label: Option<String>,
+    name_prefix: Option<String>,
+    definition_id: Option<String>,
+    sort: Option<JobSort>,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+impl JobListParams {
+    pub fn filter(&self) -> Result<JobFilter, ErrorResponse> {
+        let label = self
+            .label
+            .as_deref()
+            .map(|label| match label.split_once(':') {
+                Some((key, value)) if !key.is_empty() && !key.contains(['.', '$']) => {
+                    Ok((key.to_string(), value.to_string()))
+                }
+                _ => Err(ErrorResponse::bad_request(
+                    "The label must be written as key:value",
+                )),
+            })
+            .transpose()?;
+
+        Ok(JobFilter::default()
+            .with_status(self.status)
+            .with_created_range(
+                parse_date_time("created_after", self.created_after.as_deref())?,
+                parse_date_time("created_before", self.created_before.as_deref())?,
+            )
+            .with_label(label)
+            .with_name_prefix(self.name_prefix.clone().filter(|prefix| !prefix.is_empty()))
+            .with_definition_id(self.definition_id.clone().filter(|id| !id.is_empty())))
+    }
+
+    pub const fn sort(&self) -> Option<JobSort> {
+        self.sort
+    }
+
+    pub const fn order(&self) -> SortOrder {
+        self.order
+    }
+
+    /// Whether the jobs are listed in insertion order, the order followed
+    /// by the cursors.
+    pub fn is_insertion_order(&self) -> bool {
+        self.sort.is_none() && self.order == SortOrder::Asc
+    }
+}
+
+fn parse_date_time(name: &str, value: Option<&str>) -> Result<Option<DateTime>, ErrorResponse> {
+    value
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            DateTime::parse_rfc3339_str(value).map_err(|_| {
+                ErrorResponse::bad_request(format!("The {name} parameter must be an RFC 3339 date"))
+            })
+        })
+        .transpose()
+}
+
+fn format_date_time(date_time: DateTime) -> String {
+    date_time.try_to_rfc3339_string().unwrap_or_default()
+}
+
+// Job definition models
+
+/// Recurring job, created from a JSON body.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewJobDefinitionRequest {
+    name: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    cron: String,
+    operations: Vec<String>,
+    #[serde(default)]
+    priority: JobPriority,
+    #[serde(default)]
+    variables: JobVariables,
+    kind: Option<String>,
+}
+
+impl NewJobDefinitionRequest {
+    pub fn into_definition(self) -> Result<domain::job_definition::JobDefinition, ErrorResponse> {
+        domain::job::validate_variables(&self.variables)
+            .and_then(|()| {
+                domain::job_definition::JobDefinition::new(self.name, self.cron, self.operations)
+            })
+            .map(|definition| {
+                definition
+                    .with_labels(self.labels)
+                    .with_priority(self.priority)
+                    .with_variables(self.variables)
+                    .with_kind(self.kind)
+            })
+            .map_err(|err| ErrorResponse::bad_request(err.to_string()))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct JobDefinitionResponse {
+    id: String,
+    name: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    cron: String,
+    operations: usize,
+    priority: JobPriority,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: JobVariables,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    paused: bool,
+    next_run_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_job_id: Option<String>,
+    runs: u64,
+    created_at: String,
+}
+
+impl From<&domain::job_definition::JobDefinition> for JobDefinitionResponse {
+    fn from(definition: &domain::job_definition::JobDefinition) -> Self {
+        Self {
+            id: definition.id(),
+            name: definition.name().to_string(),
+            labels: definition.labels().clone(),
+            cron: definition.cron().to_string(),
+            operations: definition.operations().len(),
+            priority: definition.priority(),
+            variables: definition.variables().clone(),
+            kind: definition.kind().map(str::to_string),
+            paused: definition.paused(),
+            next_run_at: format_date_time(definition.next_run_at()),
+            last_run_at: definition.last_run_at().map(format_date_time),
+            last_job_id: definition.last_job_id().map(str::to_string),
+            runs: definition.runs(),
+            created_at: format_date_time(definition.created_at()),
+        }
     }
 }
 
@@ -53,17 +421,46 @@ impl From<&domain::job::Job> for MinimalJobResponse {
 #[derive(serde::Serialize)]
 pub struct OperationResponse {
     id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
     request: String,
+    status: OperationStatus,
     #[serde(skip_serializing_if = "Option::is_none")]
     result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<OperationError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflicting_results: Option<usize>,
+    created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dispatched_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_at: Option<String>,
 }
 
 impl From<domain::operation::Operation> for OperationResponse {
     fn from(operation: domain::operation::Operation) -> Self {
+        let (result, error) = match operation.result() {
+            Some(OperationOutcome::Succeeded { value }) => (Some(value.clone()), None),
+            Some(OperationOutcome::Failed { error }) => (None, Some(error.clone())),
+            Some(OperationOutcome::Cancelled) | None => (None, None),
+        };
+
         Self {
             id: operation.id(),
+            line: operation.line(),
             request: operation.request().to_string(),
-            result: operation.result().map(ToString::to_string),
+            status: operation.status(),
+            result,
+            error,
+            conflicting_results: Some(operation.conflicting_results())
+                .filter(|conflicting_results| *conflicting_results > 0),
+            created_at: format_date_time(operation.created_at()),
+            dispatched_at: operation.dispatched_at().map(format_date_time),
+            completed_at: operation.completed_at().map(format_date_time),
+            failed_at: operation.failed_at().map(format_date_time),
         }
     }
 }
@@ -71,20 +468,219 @@ impl From<domain::operation::Operation> for OperationResponse {
 #[derive(serde::Serialize)]
 pub struct MinimalOperationResponse {
     id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+    status: OperationStatus,
 }
 
 impl From<&domain::operation::Operation> for MinimalOperationResponse {
     fn from(operation: &domain::operation::Operation) -> Self {
-        Self { id: operation.id() }
+        Self {
+            id: operation.id(),
+            line: operation.line(),
+            status: operation.status(),
+        }
+    }
+}
+
+/// Filter of the operation listing of a job.
+#[derive(Debug, serde::Deserialize)]
+pub struct OperationListParams {
+    state: Option<OperationStatus>,
+}
+
+impl OperationListParams {
+    pub const fn state(&self) -> Option<OperationStatus> {
+        self.state
+    }
+}
+
+/// Format of the results export, negotiated through the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultFormat {
+    Csv,
+    Ndjson,
+    /// One line per submitted operation, holding its result.
+    Text,
+}
+
+impl ResultFormat {
+    const CSV_HEADER: &str = "line,id,request,status,result,error\n";
+
+    /// Picks the supported media type `accept` weighs the most, NDJSON being
+    /// the default when any type is accepted. Each format takes the weight of
+    /// the most specific range matching it, a zero weight excluding it, and
+    /// the ties go to the range listed first.
+    pub fn negotiate(accept: Option<&str>) -> Option<Self> {
+        let Some(accept) = accept else {
+            return Some(Self::Ndjson);
+        };
+
+        let media_ranges: Vec<_> = accept.split(',').filter_map(Self::media_range).collect();
+
+        // In order of preference, for the ranges matching several formats such as */*
+        [Self::Ndjson, Self::Text, Self::Csv]
+            .into_iter()
+            .filter_map(|format| {
+                let (_, position, quality) = media_ranges
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(position, (media_range, quality))| {
+                        Some((format.specificity(media_range)?, position, *quality))
+                    })
+                    .max_by_key(|(specificity, ..)| *specificity)?;
+
+                (quality > 0.0).then_some((format, position, quality))
+            })
+            .min_by(
+                |(_, position, quality), (_, other_position, other_quality)| {
+                    other_quality
+                        .total_cmp(quality)
+                        .then(position.cmp(other_position))
+                },
+            )
+            .map(|(format, ..)| format)
+    }
+
+    /// Media range of an `Accept` header entry, with its weight, or `None`
+    /// when the weight is invalid.
+    fn media_range(entry: &str) -> Option<(String, f32)> {
+        let mut parts = entry.split(';');
+        let media_range = parts.next()?.trim().to_ascii_lowercase();
+
+        let mut quality = 1.0;
+        for parameter in parts {
+            let Some((name, value)) = parameter.split_once('=') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("q") {
+                quality = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|quality| (0.0..=1.0).contains(quality))?;
+            }
+        }
+
+        Some((media_range, quality))
+    }
+
+    /// How closely `media_range` matches the format, from `*/*` to its exact
+    /// media type, or `None` when it does not.
+    fn specificity(self, media_range: &str) -> Option<u8> {
+        let media_types: &[&str] = match self {
+            Self::Csv => &["text/csv"],
+            Self::Ndjson => &["application/x-ndjson", "application/ndjson"],
+            Self::Text => &["text/plain"],
+        };
+
+        if media_types.contains(&media_range) {
+            return Some(2);
+        }
+        if media_range == "*/*" {
+            return Some(0);
+        }
+
+        let (kind, subtype) = media_range.split_once('/')?;
+        let matches_kind = media_types.iter().any(|media_type| {
+            media_type
+                .split_once('/')
+                .is_some_and(|(other, _)| other == kind)
+        });
+
+        (subtype == "*" && matches_kind).then_some(1)
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub const fn header(self) -> Option<&'static str> {
+        match self {
+            Self::Csv => Some(Self::CSV_HEADER),
+            Self::Ndjson | Self::Text => None,
+        }
+    }
+
+    pub fn render(self, operation: domain::operation::Operation, output: &mut String) {
+        match self {
+            Self::Csv => {
+                let (result, error) = match operation.result() {
+                    Some(OperationOutcome::Succeeded { value }) => (value.as_str(), ""),
+                    Some(OperationOutcome::Failed { error }) => ("", error.message()),
+                    Some(OperationOutcome::Cancelled) | None => ("", ""),
+                };
+                let fields = [
+                    &operation
+                        .line()
+                        .map(|line| line.to_string())
+                        .unwrap_or_default(),
+                    &operation.id(),
+                    operation.request(),
+                    operation.status().as_str(),
+                    result,
+                    error,
+                ];
+                for (index, field) in fields.into_iter().enumerate() {
+                    if index > 0 {
+                        output.push(',');
+                    }
+                    push_csv_field(field, output);
+                }
+            }
+            Self::Ndjson => {
+                // Serializing plain strings and numbers cannot fail
+                if let Ok(json) = serde_json::to_string(&OperationResponse::from(operation)) {
+                    output.push_str(&json);
+                }
+            }
+            Self::Text => match operation.result() {
+                Some(OperationOutcome::Succeeded { value }) => push_text_line(value, output),
+                Some(OperationOutcome::Failed { error }) => {
+                    output.push_str("error: ");
+                    push_text_line(error.message(), output);
+                }
+                Some(OperationOutcome::Cancelled) | None => {}
+            },
+        }
+        output.push('\n');
+    }
+}
+
+fn push_csv_field(field: &str, output: &mut String) {
+    if field.contains([',', '"', '\n', '\r']) {
+        output.push('"');
+        output.push_str(&field.replace('"', "\"\""));
+        output.push('"');
+    } else {
+        output.push_str(field);
     }
 }
 
+/// Keeps a value on a single line, so the text export stays aligned with the
+/// submitted operations.
+fn push_text_line(value: &str, output: &mut String) {
+    output.push_str(
+        &value
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+    );
+}
+
 // Misc models
 
 #[derive(Debug, serde::Deserialize)]
 pub struct PageParams {
     page: Option<u32>,
     size: Option<u32>,
+    cursor: Option<String>,
+    #[serde(default)]
+    estimate_total: bool,
 }
 
 impl PageParams {
@@ -104,31 +700,139 @@ impl PageParams {
             .unwrap_or(Self::DEFAULT_SIZE)
             .clamp(Self::MIN_SIZE, Self::MAX_SIZE)
     }
+
+    /// Position requested through the opaque `cursor` of a previous page,
+    /// `None` when paging by number.
+    pub fn keyset(&self) -> Result<Option<Keyset>, ErrorResponse> {
+        self.cursor
+            .as_deref()
+            .filter(|cursor| !cursor.is_empty())
+            .map(|cursor| {
+                decode_cursor(cursor).map_err(|_| ErrorResponse::bad_request("Invalid cursor"))
+            })
+            .transpose()
+    }
+
+    pub const fn estimate_total(&self) -> bool {
+        self.estimate_total
+    }
+}
+
+fn encode_cursor(keyset: &Keyset) -> String {
+    // Serializing plain strings cannot fail
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(keyset).unwrap_or_default())
 }
 
+fn decode_cursor(cursor: &str) -> anyhow::Result<Keyset> {
+    let keyset = serde_json::from_slice::<Keyset>(&URL_SAFE_NO_PAD.decode(cursor)?)?;
+    match &keyset {
+        Keyset::After(id) | Keyset::Before(id) => ObjectId::parse_str(id)?,
+    };
+
+    Ok(keyset)
+}
+
+/// Page of items, addressed either by number, with the exact `total`, or by
+/// the `prev` and `next` cursors, with an optional `estimated_total`.
 #[derive(serde::Serialize)]
 #[serde(bound = "T: serde::Serialize")]
 pub struct PageResponse<T> {
-    page: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u32>,
     size: u32,
-    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
     items: Vec<T>,
 }
 
 impl<T> PageResponse<T> {
-    pub const fn new(page: u32, size: u32, total: usize, items: Vec<T>) -> Self {
+    /// Numbered page, which also carries the cursors of its neighbours, so a
+    /// client can switch to cursors at any point.
+    pub fn new<S>(page: u32, size: u32, subset: &PageSubset<S>, id: impl Fn(&S) -> String) -> Self
+    where
+        T: for<'a> From<&'a S>,
+    {
+        let items = subset.items_subset();
+        let seen = (page as usize - 1) * size as usize + items.len();
+
         Self {
-            page,
+            page: Some(page),
             size,
-            total,
-            items,
+            total: Some(subset.total()),
+            estimated_total: None,
+            prev: items
+                .first()
+                .filter(|_| page > 1)
+                .map(|first| encode_cursor(&Keyset::Before(id(first)))),
+            next: items
+                .last()
+                .filter(|_| seen < subset.total())
+                .map(|last| encode_cursor(&Keyset::After(id(last)))),
+            items: items.iter().map(T::from).collect(),
         }
     }
+
+    pub fn from_keyset<S>(size: u32, keyset_page: &KeysetPage<S>, id: impl Fn(&S) -> String) -> Self
+    where
+        T: for<'a> From<&'a S>,
+    {
+        let items = keyset_page.items();
+
+        Self {
+            page: None,
+            size,
+            total: None,
+            estimated_total: None,
+            prev: items
+                .first()
+                .filter(|_| keyset_page.has_previous())
+                .map(|first| encode_cursor(&Keyset::Before(id(first)))),
+            next: items
+                .last()
+                .filter(|_| keyset_page.has_next())
+                .map(|last| encode_cursor(&Keyset::After(id(last)))),
+            items: items.iter().map(T::from).collect(),
+        }
+    }
+
+    /// Drops the cursors, which only follow the insertion order of the
+    /// unfiltered items.
+    #[must_use]
+    pub fn without_cursors(mut self) -> Self {
+        self.prev = None;
+        self.next = None;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_estimated_total(mut self, estimated_total: Option<usize>) -> Self {
+        self.estimated_total = estimated_total;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::JobListParams;
+    use super::JobResponse;
+    use super::NewJobRequest;
+    use super::OperationListParams;
     use super::PageParams;
+    use super::ResultFormat;
+    use super::decode_cursor;
+    use super::encode_cursor;
+    use super::parse_run_at;
+    use crate::database::model::Keyset;
+    use crate::domain::job::Job;
+    use crate::domain::operation::Operation;
+    use crate::domain::operation::OperationStatus;
+    use mongodb::bson::DateTime;
 
     #[test]
     fn page_defaults_when_missing() {
@@ -136,6 +840,8 @@ mod tests {
         let params = PageParams {
             page: None,
             size: None,
+            cursor: None,
+            estimate_total: false,
         };
 
         // Act
@@ -151,6 +857,8 @@ mod tests {
         let params = PageParams {
             page: Some(0),
             size: None,
+            cursor: None,
+            estimate_total: false,
         };
 
         // Act
@@ -166,6 +874,8 @@ mod tests {
         let params = PageParams {
             page: Some(7),
             size: None,
+            cursor: None,
+            estimate_total: false,
         };
 
         // Act
@@ -174,4 +884,153 @@ mod tests {
         // Assert
         assert_eq!(page, 7);
     }
+
+    #[test]
+    fn job_list_params_reject_malformed_labels() {
+        // Arrange
+        let params = JobListParams {
+            label: Some("team.name:billing".to_string()),
+            ..JobListParams::default()
+        };
+
+        // Act
+        let filter = params.filter();
+
+        // Assert
+        assert!(filter.is_err());
+    }
+
+    #[test]
+    fn run_at_rejects_both_a_time_and_a_delay() {
+        // Arrange
+        let run_at = "2030-01-01T00:00:00Z";
+
+        // Act
+        let result = parse_run_at(Some(run_at), Some(60));
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn job_response_leaves_running_jobs_unfinished() {
+        // Arrange
+        let mut document = mongodb::bson::to_document(&Job::new(3).unwrap()).unwrap();
+        document.insert("started_at", DateTime::now());
+        document.insert("finished_at", DateTime::now());
+        let job: Job = mongodb::bson::from_document(document).unwrap();
+
+        // Act
+        let job_response = JobResponse::new(&job);
+
+        // Assert
+        assert!(job_response.started_at.is_some());
+        assert_eq!(job_response.finished_at, None);
+        assert_eq!(job_response.duration_ms, None);
+    }
+
+    #[test]
+    fn new_job_request_defaults_its_metadata() {
+        // Arrange
+        let body = r#"{ "operations": ["1 + 1", "2 * 3"] }"#;
+
+        // Act
+        let request = serde_json::from_str::<NewJobRequest>(body).unwrap();
+
+        // Assert
+        assert_eq!(request.name(), None);
+        assert!(request.labels().is_empty());
+        assert_eq!(request.operations(), ["1 + 1", "2 * 3"]);
+        assert_eq!(request.options().callback_url(), None);
+    }
+
+    #[test]
+    fn new_job_request_rejects_unknown_fields() {
+        // Arrange
+        let body = r#"{ "operations": ["1 + 1"], "priority": "high" }"#;
+
+        // Act
+        let request = serde_json::from_str::<NewJobRequest>(body);
+
+        // Assert
+        assert!(request.is_err());
+    }
+
+    #[test]
+    fn operation_list_params_read_the_state() {
+        // Arrange
+        let params = r#"{ "state": "failed" }"#;
+
+        // Act
+        let params = serde_json::from_str::<OperationListParams>(params).unwrap();
+
+        // Assert
+        assert_eq!(params.state(), Some(OperationStatus::Failed));
+    }
+
+    #[test]
+    fn result_format_follows_the_accept_header() {
+        // Arrange
+        let accepts = [
+            None,
+            Some("text/csv"),
+            Some("text/plain;q=0.9, application/x-ndjson"),
+            Some("text/*;q=0.5, text/plain;q=0"),
+            Some("text/csv, text/plain"),
+            Some("image/png"),
+        ];
+
+        // Act
+        let formats = accepts.map(ResultFormat::negotiate);
+
+        // Assert
+        assert_eq!(
+            formats,
+            [
+                Some(ResultFormat::Ndjson),
+                Some(ResultFormat::Csv),
+                Some(ResultFormat::Ndjson),
+                Some(ResultFormat::Csv),
+                Some(ResultFormat::Csv),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_result_quotes_special_characters() {
+        // Arrange
+        let operation = Operation::new("job", 3, "max(1, 2)");
+        let mut output = String::new();
+
+        // Act
+        ResultFormat::Csv.render(operation, &mut output);
+
+        // Assert
+        assert_eq!(output, "3,,\"max(1, 2)\",pending,,\n");
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        // Arrange
+        let keyset = Keyset::Before("65f1c2a4e4b0a1b2c3d4e5f6".to_string());
+
+        // Act
+        let decoded = decode_cursor(&encode_cursor(&keyset)).unwrap();
+
+        // Assert
+        assert_eq!(decoded, keyset);
+    }
+
+    #[test]
+    fn cursor_rejects_tampered_ids() {
+        // Arrange
+        let cursor = encode_cursor(&Keyset::After("not-an-id".to_string()));
+
+        // Act
+        let decoded = decode_cursor(&cursor);
+
+        // Assert
+        assert!(decoded.is_err());
+    }
 }
@@ -1,14 +1,22 @@
 use crate::application::context::SharedApplicationState;
+use crate::domain;
 use crate::http;
+use crate::http::model::OperationListParams;
 use crate::http::model::PageParams;
 use crate::http::utils::ErrorResponse;
 use anyhow::Result;
 use axum::Json;
+use axum::body::Body;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::header;
 use axum::response::IntoResponse;
 use common::counter;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument as _;
 
 counter!(
     GET_OPERATION_COUNTER,
@@ -21,9 +29,19 @@ counter!(
     "Number of get operations requests"
 );
 
+counter!(
+    GET_RESULTS_COUNTER,
+    "http_server_get_results_requests",
+    "Number of get results requests"
+);
+
 pub struct OperationController;
 
 impl OperationController {
+    const RESULTS_BATCH_SIZE: u32 = 512;
+    // Rendered chunks buffered ahead of a slow client
+    const RESULTS_BUFFERED_CHUNKS: usize = 4;
+
     #[tracing::instrument(skip(state))]
     pub async fn get_operation_endpoint_handler(
         Path((job_id, operation_id)): Path<(String, String)>,
@@ -46,30 +64,149 @@ impl OperationController {
     pub async fn get_operations_endpoint_handler(
         Path(job_id): Path<String>,
         Query(params): Query<PageParams>,
+        Query(list_params): Query<OperationListParams>,
         State(state): State<SharedApplicationState>,
     ) -> Result<impl IntoResponse, ErrorResponse> {
         tracing::info!("Getting all the operations job {}", job_id);
 
         GET_OPERATIONS_COUNTER.add(1, &[]);
 
-        let page = params.page();
         let page_size = params.size();
+        let operation_state = list_params.state();
+
+        if let Some(keyset) = params.keyset()? {
+            let operations = state
+                .database_client()
+                .operation_repository()
+                .get_operations_page(&job_id, operation_state, &keyset, page_size)
+                .await?;
+
+            // The job knows how many operations it holds, while the ones in a given state are
+            // counted through the result status index
+            let estimated_total = match (params.estimate_total(), operation_state) {
+                (false, _) => None,
+                (true, None) => Some(
+                    state
+                        .database_client()
+                        .job_repository()
+                        .get_job(&job_id)
+                        .await?
+                        .operations(),
+                ),
+                (true, Some(operation_state)) => Some(
+                    state
+                        .database_client()
+                        .operation_repository()
+                        .count_operations(&job_id, operation_state)
+                        .await?,
+                ),
+            };
 
+            return Ok(Json(
+                http::model::PageResponse::<http::model::MinimalOperationResponse>::from_keyset(
+                    page_size,
+                    &operations,
+                    domain::operation::Operation::id,
+                )
+                .with_estimated_total(estimated_total),
+            ));
+        }
+
+        let page = params.page();
         let operations = state
             .database_client()
             .operation_repository()
-            .get_operations(&job_id, page, page_size)
+            .get_operations(&job_id, operation_state, page, page_size)
             .await?;
 
-        Ok(Json(http::model::PageResponse::new(
+        Ok(Json(http::model::PageResponse::<
+            http::model::MinimalOperationResponse,
+        >::new(
             page,
             page_size,
-            operations.total(),
-            operations
-                .items_subset()
-                .iter()
-                .map(http::model::MinimalOperationResponse::from)
-                .collect(),
+            &operations,
+            domain::operation::Operation::id,
         )))
     }
+
+    /// Streams the request and result of every operation of a job, in the
+    /// submission order, as CSV, NDJSON or plain text depending on `Accept`.
+    #[tracing::instrument(skip(headers, state))]
+    pub async fn get_results_endpoint_handler(
+        Path(job_id): Path<String>,
+        headers: HeaderMap,
+        State(state): State<SharedApplicationState>,
+    ) -> Result<impl IntoResponse, ErrorResponse> {
+        tracing::info!("Exporting the results of job {}", job_id);
+
+        GET_RESULTS_COUNTER.add(1, &[]);
+
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok());
+        let Some(format) = http::model::ResultFormat::negotiate(accept) else {
+            return Err(ErrorResponse::not_acceptable(
+                "Supported formats are text/csv, application/x-ndjson and text/plain",
+            ));
+        };
+
+        // Fail before streaming anything when the job does not exist
+        state
+            .database_client()
+            .job_repository()
+            .get_job(&job_id)
+            .await?;
+
+        let (sender, mut receiver) = mpsc::channel::<Result<String>>(Self::RESULTS_BUFFERED_CHUNKS);
+
+        let parent_span = tracing::Span::current();
+        tokio::spawn(
+            async move {
+                if let Some(header) = format.header()
+                    && sender.send(Ok(header.to_string())).await.is_err()
+                {
+                    return;
+                }
+
+                let result = state
+                    .database_client()
+                    .operation_repository()
+                    .get_batch_operations(
+                        &job_id,
+                        None,
+                        Self::RESULTS_BATCH_SIZE,
+                        &CancellationToken::new(),
+                        |operations| {
+                            let sender = &sender;
+                            async move {
+                                let mut chunk = String::new();
+                                for operation in operations {
+                                    format.render(operation, &mut chunk);
+                                }
+
+                                sender.send(Ok(chunk)).await.map_err(|_| {
+                                    anyhow::anyhow!("The client stopped reading the results")
+                                })
+                            }
+                        },
+                    )
+                    .await;
+
+                if let Err(err) = result {
+                    tracing::warn!("Export of the results of job {job_id} interrupted: {err}");
+
+                    // Cut the response short, so the client sees the export is incomplete
+                    let _ = sender.send(Err(err)).await;
+                }
+            }
+            .instrument(parent_span),
+        );
+
+        let chunks = futures::stream::poll_fn(move |context| receiver.poll_recv(context));
+
+        Ok((
+            [(header::CONTENT_TYPE, format.content_type())],
+            Body::from_stream(chunks),
+        ))
+    }
 }
@@ -14,6 +14,20 @@ impl ErrorResponse {
             error: anyhow::anyhow!(message.into()),
         }
     }
+
+    pub fn not_acceptable(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_ACCEPTABLE,
+            error: anyhow::anyhow!(message.into()),
+        }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            error: anyhow::anyhow!(message.into()),
+        }
+    }
 }
 
 impl IntoResponse for ErrorResponse {
@@ -17,6 +17,7 @@ async fn main() -> Result<()> {
 
     // Start the application
     let result = async {
+        common::application::init_instance_id()?;
         let application = create_application().await?;
         start_application(application).await
     }
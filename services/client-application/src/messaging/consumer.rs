@@ -1,53 +1,289 @@
+use crate::application::dependency_resolver::DependencyResolver;
+use crate::application::dispatch_registry::DispatchRegistry;
+use crate::application::job_event_hub::JobEventHub;
+use crate::application::job_event_notifier::JobEventNotifier;
 use crate::database::database_client::DatabaseClient;
+use crate::database::database_client::is_transient_error;
+use crate::database::model::ResultWrite;
+use crate::messaging::model::JobControl;
+use crate::messaging::model::JobControlAction;
+use crate::messaging::model::JobEvent;
 use crate::messaging::model::OperationResult;
 use anyhow::Result;
+use common::counter;
+use common::histogram;
+use common::messaging::consumer::HandlerOutcome;
 use common::messaging::consumer::MessageConsumer as CommonConsumer;
 use common::messaging::consumer::MessageHandler;
+use common::messaging::dead_letter::DeadLetterPolicy;
+use common::messaging::dead_letter::DeadLetterReplayer;
+use common::messaging::retry::RetryPolicy;
+use mongodb::bson::DateTime;
 use std::future::Future;
 use std::sync::Arc;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
-const TOPIC_NAME: &str = "application.operation.response";
-const GROUP_ID: &str = "operation-response-group";
-const CONCURRENCY: usize = 20;
+counter!(
+    DUPLICATE_RESULT_COUNTER,
+    "operation_results_duplicated",
+    "Number of operation results received more than once"
+);
+counter!(
+    CONFLICTING_RESULT_COUNTER,
+    "operation_results_conflicting",
+    "Number of operation results conflicting with the stored one"
+);
+histogram!(
+    OPERATION_LATENCY_HISTOGRAM,
+    "operation_end_to_end_latency",
+    "Time from the creation of an operation to the storage of its result",
+    "ms"
+);
+
+const OPERATION_RESULT_TOPIC_NAME: &str = "application.operation.response";
+const OPERATION_RESULT_GROUP_ID: &str = "operation-response-group";
+const OPERATION_RESULT_MAX_ATTEMPTS: u32 = 5;
+const OPERATION_RESULT_CONCURRENCY: usize = 20;
+
+const JOB_CONTROL_TOPIC_NAME: &str = "application.job.control";
+const JOB_CONTROL_GROUP_ID_PREFIX: &str = "client-job-control-group";
+const JOB_CONTROL_CONCURRENCY: usize = 1;
+
+const JOB_EVENT_TOPIC_NAME: &str = "application.job.event";
+const JOB_EVENT_GROUP_ID_PREFIX: &str = "client-job-event-group";
+const JOB_EVENT_CONCURRENCY: usize = 1;
 
 pub struct OperationResultHandler {
     database_client: Arc<DatabaseClient>,
+    dependency_resolver: Arc<DependencyResolver>,
+    job_event_notifier: Arc<JobEventNotifier>,
 }
 
 impl OperationResultHandler {
-    pub const fn new(database_client: Arc<DatabaseClient>) -> Self {
-        Self { database_client }
+    pub const fn new(
+        database_client: Arc<DatabaseClient>,
+        dependency_resolver: Arc<DependencyResolver>,
+        job_event_notifier: Arc<JobEventNotifier>,
+    ) -> Self {
+        Self {
+            database_client,
+            dependency_resolver,
+            job_event_notifier,
+        }
+    }
+
+    /// Releases the operations waiting on the stored result. On failure,
+    /// the result is redelivered as a duplicate, which releases them again.
+    async fn release_dependents(
+        dependency_resolver: &DependencyResolver,
+        message: &OperationResult,
+        line: Option<u64>,
+    ) -> HandlerOutcome {
+        let Some(line) = line else {
+            return HandlerOutcome::Ack;
+        };
+
+        match dependency_resolver
+            .release_dependents(message.job_id(), line, message.attempt())
+            .await
+        {
+            Ok(()) => HandlerOutcome::Ack,
+            Err(err) => HandlerOutcome::retry(format!(
+                "Failed to release the operations waiting on operation {}: {err}",
+                message.operation_id()
+            )),
+        }
     }
 }
 
 impl MessageHandler<OperationResult> for OperationResultHandler {
-    fn handle(&self, message: OperationResult) -> impl Future<Output = Result<()>> + Send {
+    fn handle(&self, message: OperationResult) -> impl Future<Output = HandlerOutcome> + Send {
         let database_client = Arc::clone(&self.database_client);
+        let dependency_resolver = Arc::clone(&self.dependency_resolver);
+        let job_event_notifier = Arc::clone(&self.job_event_notifier);
         async move {
-            database_client
-                .operation_repository()
-                .update_operation(message.job_id(), message.operation_id(), message.result())
+            match database_client
+                .store_result(
+                    message.job_id(),
+                    message.operation_id(),
+                    message.outcome(),
+                    message.attempt(),
+                )
                 .await
+            {
+                Ok(ResultWrite::Applied {
+                    created_at, line, ..
+                }) => {
+                    let latency =
+                        DateTime::now().timestamp_millis() - created_at.timestamp_millis();
+                    OPERATION_LATENCY_HISTOGRAM.record(
+                        u64::try_from(latency).unwrap_or_default(),
+                        &[opentelemetry::KeyValue::new(
+                            "status",
+                            message.outcome().status().as_str(),
+                        )],
+                    );
+
+                    let outcome =
+                        Self::release_dependents(&dependency_resolver, &message, line).await;
+
+                    job_event_notifier.notify(message.job_id());
+
+                    outcome
+                }
+                Ok(ResultWrite::Duplicate { line }) => {
+                    tracing::debug!("Duplicate result for operation {}", message.operation_id());
+
+                    DUPLICATE_RESULT_COUNTER.add(1, &[]);
+
+                    Self::release_dependents(&dependency_resolver, &message, line).await
+                }
+                Ok(ResultWrite::Conflict) => {
+                    tracing::warn!(
+                        "Conflicting result for operation {} from attempt {}, recorded aside",
+                        message.operation_id(),
+                        message.attempt()
+                    );
+
+                    CONFLICTING_RESULT_COUNTER.add(1, &[]);
+
+                    HandlerOutcome::Ack
+                }
+                Ok(ResultWrite::Discarded) => {
+                    tracing::debug!(
+                        "Discarding the result of cancelled operation {}",
+                        message.operation_id()
+                    );
+
+                    HandlerOutcome::Ack
+                }
+                Err(err) if is_transient_error(&err) => HandlerOutcome::retry(err),
+                // The operation is unknown, most likely because its job was deleted
+                Err(err) => HandlerOutcome::dead_letter(format!(
+                    "Failed to store the result of operation {}: {err}",
+                    message.operation_id()
+                )),
+            }
         }
     }
 }
 
-pub struct MessageConsumer(CommonConsumer<OperationResult, OperationResultHandler>);
+pub struct JobControlHandler {
+    dispatch_registry: Arc<DispatchRegistry>,
+}
+
+impl JobControlHandler {
+    pub const fn new(dispatch_registry: Arc<DispatchRegistry>) -> Self {
+        Self { dispatch_registry }
+    }
+}
+
+impl MessageHandler<JobControl> for JobControlHandler {
+    fn handle(&self, message: JobControl) -> impl Future<Output = HandlerOutcome> + Send {
+        let dispatch_registry = Arc::clone(&self.dispatch_registry);
+        async move {
+            match message.action() {
+                JobControlAction::Cancel => dispatch_registry.cancel(message.job_id()),
+            }
+            HandlerOutcome::Ack
+        }
+    }
+}
+
+pub struct JobEventHandler {
+    job_event_hub: Arc<JobEventHub>,
+}
+
+impl JobEventHandler {
+    pub const fn new(job_event_hub: Arc<JobEventHub>) -> Self {
+        Self { job_event_hub }
+    }
+}
+
+impl MessageHandler<JobEvent> for JobEventHandler {
+    fn handle(&self, message: JobEvent) -> impl Future<Output = HandlerOutcome> + Send {
+        self.job_event_hub.notify(message.job_id());
+        std::future::ready(HandlerOutcome::Ack)
+    }
+}
+
+pub struct MessageConsumer {
+    operation_results: CommonConsumer<OperationResult, OperationResultHandler>,
+    job_controls: CommonConsumer<JobControl, JobControlHandler>,
+    job_events: CommonConsumer<JobEvent, JobEventHandler>,
+}
 
 impl MessageConsumer {
-    pub fn new(database_client: Arc<DatabaseClient>) -> Result<Self> {
-        let handler = Arc::new(OperationResultHandler::new(database_client));
-        Ok(Self(CommonConsumer::new(
-            handler,
-            TOPIC_NAME,
-            GROUP_ID,
-            CONCURRENCY,
-        )?))
+    pub fn new(
+        database_client: Arc<DatabaseClient>,
+        dependency_resolver: Arc<DependencyResolver>,
+        dispatch_registry: Arc<DispatchRegistry>,
+        job_event_hub: Arc<JobEventHub>,
+        job_event_notifier: Arc<JobEventNotifier>,
+    ) -> Result<Self> {
+        let operation_result_handler = Arc::new(OperationResultHandler::new(
+            database_client,
+            dependency_resolver,
+            job_event_notifier,
+        ));
+        let job_control_handler = Arc::new(JobControlHandler::new(dispatch_registry));
+        let job_event_handler = Arc::new(JobEventHandler::new(job_event_hub));
+
+        // Every host must see every control message, hence a consumer group per host
+        let job_control_group_id = format!(
+            "{JOB_CONTROL_GROUP_ID_PREFIX}-{}",
+            common::application::host_id()
+        );
+        // Likewise for the job events, every host may be streaming the progress of a job
+        let job_event_group_id = format!(
+            "{JOB_EVENT_GROUP_ID_PREFIX}-{}",
+            common::application::host_id()
+        );
+
+        Ok(Self {
+            operation_results: CommonConsumer::new(
+                operation_result_handler,
+                OPERATION_RESULT_TOPIC_NAME,
+                OPERATION_RESULT_GROUP_ID,
+                OPERATION_RESULT_CONCURRENCY,
+                RetryPolicy::exponential(OPERATION_RESULT_MAX_ATTEMPTS)
+                    .through_topic(OPERATION_RESULT_TOPIC_NAME),
+                Some(DeadLetterPolicy::for_topic(OPERATION_RESULT_TOPIC_NAME)),
+            )?,
+            job_controls: CommonConsumer::new(
+                job_control_handler,
+                JOB_CONTROL_TOPIC_NAME,
+                &job_control_group_id,
+                JOB_CONTROL_CONCURRENCY,
+                RetryPolicy::none(),
+                None,
+            )?,
+            job_events: CommonConsumer::new(
+                job_event_handler,
+                JOB_EVENT_TOPIC_NAME,
+                &job_event_group_id,
+                JOB_EVENT_CONCURRENCY,
+                RetryPolicy::none(),
+                None,
+            )?,
+        })
+    }
+
+    pub fn dead_letter_replayers(&self) -> Result<Vec<DeadLetterReplayer>> {
+        Ok(self
+            .operation_results
+            .dead_letter_replayer()?
+            .into_iter()
+            .collect())
     }
 
     pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
-        self.0.start(shutdown)
+        self.operation_results
+            .start(shutdown)
+            .into_iter()
+            .chain(self.job_controls.start(shutdown))
+            .chain(self.job_events.start(shutdown))
+            .collect()
     }
 }
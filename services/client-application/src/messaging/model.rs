@@ -1,29 +1,66 @@
 use crate::domain;
 use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
 
 #[derive(serde::Serialize)]
 pub struct OperationRequest {
     job_id: String,
     operation_id: String,
     request: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    attempt: u32,
+    /// Results of the operations the request references, by line.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    inputs: BTreeMap<u64, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: domain::job::JobVariables,
 }
 
-impl From<domain::operation::Operation> for OperationRequest {
-    fn from(operation: domain::operation::Operation) -> Self {
+impl OperationRequest {
+    pub fn new(operation: &domain::operation::Operation, attempt: u32) -> Self {
         Self {
             job_id: operation.job_id().to_string(),
             operation_id: operation.id(),
             request: operation.request().to_string(),
+            kind: None,
+            attempt,
+            inputs: BTreeMap::new(),
+            variables: domain::job::JobVariables::new(),
         }
     }
+
+    pub fn with_variables(mut self, variables: domain::job::JobVariables) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: Option<&str>) -> Self {
+        self.kind = kind.map(str::to_string);
+        self
+    }
+
+    pub fn with_inputs(mut self, inputs: BTreeMap<u64, String>) -> Self {
+        self.inputs = inputs;
+        self
+    }
 }
 
+/// Unknown fields are ignored, and the plain `result` of the servers not
+/// upgraded yet is read as a success, so that both sides can be upgraded in
+/// any order.
 #[derive(serde::Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct OperationResult {
     job_id: String,
     operation_id: String,
-    result: String,
+    #[serde(
+        alias = "result",
+        deserialize_with = "domain::operation::deserialize_any_outcome"
+    )]
+    outcome: domain::operation::OperationOutcome,
+    #[serde(default)]
+    attempt: u32,
 }
 
 impl OperationResult {
@@ -35,8 +72,12 @@ impl OperationResult {
         &self.operation_id
     }
 
-    pub fn result(&self) -> &str {
-        &self.result
+    pub const fn outcome(&self) -> &domain::operation::OperationOutcome {
+        &self.outcome
+    }
+
+    pub const fn attempt(&self) -> u32 {
+        self.attempt
     }
 }
 
@@ -47,3 +88,116 @@ impl TryFrom<&str> for OperationResult {
         serde_json::from_str::<Self>(message).map_err(|err| anyhow::anyhow!(err))
     }
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobControlAction {
+    Cancel,
+}
+
+/// Lenient about the fields it does not know, as `sent_at` was added to it
+/// after the instances started exchanging it.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct JobControl {
+    job_id: String,
+    action: JobControlAction,
+    /// Milliseconds since the Unix epoch, telling the instances replaying the
+    /// topic on start how old the message is.
+    #[serde(default)]
+    sent_at: u64,
+}
+
+impl JobControl {
+    pub fn cancel(job_id: impl Into<String>) -> Self {
+        let sent_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or_default();
+
+        Self {
+            job_id: job_id.into(),
+            action: JobControlAction::Cancel,
+            sent_at,
+        }
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub const fn action(&self) -> JobControlAction {
+        self.action
+    }
+}
+
+impl TryFrom<&str> for JobControl {
+    type Error = anyhow::Error;
+
+    fn try_from(message: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<Self>(message).map_err(|err| anyhow::anyhow!(err))
+    }
+}
+
+/// Signals that a job changed, without telling how: the receivers reload the
+/// job when they care about it.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobEvent {
+    job_id: String,
+}
+
+impl JobEvent {
+    pub fn new(job_id: impl Into<String>) -> Self {
+        Self {
+            job_id: job_id.into(),
+        }
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+}
+
+impl TryFrom<&str> for JobEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(message: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<Self>(message).map_err(|err| anyhow::anyhow!(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OperationResult;
+    use crate::domain::operation::OperationOutcome;
+
+    #[test]
+    fn operation_result_of_a_legacy_server_succeeded_with_its_value() {
+        // Arrange
+        let payload = r#"{"job_id":"1","operation_id":"2","result":"42"}"#;
+
+        // Act
+        let result = OperationResult::try_from(payload).unwrap();
+
+        // Assert
+        assert_eq!(
+            result.outcome(),
+            &OperationOutcome::Succeeded {
+                value: "42".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn operation_result_ignores_the_fields_it_does_not_know() {
+        // Arrange
+        let payload =
+            r#"{"job_id":"1","operation_id":"2","outcome":{"status":"cancelled"},"duration_ms":3}"#;
+
+        // Act
+        let result = OperationResult::try_from(payload);
+
+        // Assert
+        assert!(result.is_ok());
+    }
+}
@@ -1,18 +1,101 @@
 use crate::domain;
+use crate::messaging::model::JobControl;
+use crate::messaging::model::JobEvent;
 use crate::messaging::model::OperationRequest;
 use anyhow::Result;
+use common::messaging::producer::Delivery;
 use common::messaging::producer::MessageProducer as CommonProducer;
+use std::collections::BTreeMap;
 
-pub struct MessageProducer(CommonProducer<OperationRequest>);
+pub struct MessageProducer {
+    high_operation_requests: CommonProducer<OperationRequest>,
+    operation_requests: CommonProducer<OperationRequest>,
+    bulk_operation_requests: CommonProducer<OperationRequest>,
+    job_controls: CommonProducer<JobControl>,
+    job_events: CommonProducer<JobEvent>,
+}
 
 impl MessageProducer {
-    const TOPIC_NAME: &'static str = "application.operation.request";
+    const HIGH_OPERATION_REQUEST_TOPIC_NAME: &'static str = "application.operation.request.high";
+    const OPERATION_REQUEST_TOPIC_NAME: &'static str = "application.operation.request";
+    const BULK_OPERATION_REQUEST_TOPIC_NAME: &'static str = "application.operation.request.bulk";
+    const JOB_CONTROL_TOPIC_NAME: &'static str = "application.job.control";
+    const JOB_EVENT_TOPIC_NAME: &'static str = "application.job.event";
 
     pub fn new() -> Result<Self> {
-        Ok(Self(CommonProducer::new(Self::TOPIC_NAME)?))
+        Ok(Self {
+            high_operation_requests: CommonProducer::new(Self::HIGH_OPERATION_REQUEST_TOPIC_NAME)?,
+            operation_requests: CommonProducer::new(Self::OPERATION_REQUEST_TOPIC_NAME)?,
+            bulk_operation_requests: CommonProducer::new(Self::BULK_OPERATION_REQUEST_TOPIC_NAME)?,
+            job_controls: CommonProducer::new(Self::JOB_CONTROL_TOPIC_NAME)?,
+            job_events: CommonProducer::new(Self::JOB_EVENT_TOPIC_NAME)?,
+        })
+    }
+
+    /// Sends the operations on the topic of `priority`, the normal lane
+    /// keeping the topic used before the lanes existed, each along with the
+    /// `variables` and the `kind` of its job.
+    pub async fn send_operation_requests(
+        &self,
+        operations: &[domain::operation::Operation],
+        attempt: u32,
+        priority: domain::job::JobPriority,
+        variables: &domain::job::JobVariables,
+        kind: Option<&str>,
+    ) -> Result<Vec<Delivery>> {
+        let requests = operations
+            .iter()
+            .map(|operation| {
+                OperationRequest::new(operation, attempt)
+                    .with_variables(variables.clone())
+                    .with_kind(kind)
+            })
+            .collect::<Vec<_>>();
+
+        self.operation_request_producer(priority)
+            .send_all(&requests)
+            .await
+    }
+
+    /// Sends an operation referencing other results, along with the values
+    /// of those results.
+    pub async fn send_resolved_operation_request(
+        &self,
+        operation: &domain::operation::Operation,
+        inputs: BTreeMap<u64, String>,
+        attempt: u32,
+        priority: domain::job::JobPriority,
+        variables: &domain::job::JobVariables,
+        kind: Option<&str>,
+    ) -> Result<Delivery> {
+        let request = OperationRequest::new(operation, attempt)
+            .with_inputs(inputs)
+            .with_variables(variables.clone())
+            .with_kind(kind);
+
+        self.operation_request_producer(priority)
+            .deliver(&request)
+            .await
+    }
+
+    const fn operation_request_producer(
+        &self,
+        priority: domain::job::JobPriority,
+    ) -> &CommonProducer<OperationRequest> {
+        match priority {
+            domain::job::JobPriority::High => &self.high_operation_requests,
+            domain::job::JobPriority::Normal => &self.operation_requests,
+            domain::job::JobPriority::Bulk => &self.bulk_operation_requests,
+        }
     }
 
-    pub fn send_operation_request(&self, operation: domain::operation::Operation) {
-        self.0.send(&OperationRequest::from(operation));
+    pub async fn send_job_cancellation(&self, job_id: &str) -> Result<Delivery> {
+        self.job_controls.deliver(&JobControl::cancel(job_id)).await
+    }
+
+    pub async fn send_job_events(&self, job_ids: &[String]) -> Result<Vec<Delivery>> {
+        let events = job_ids.iter().map(JobEvent::new).collect::<Vec<_>>();
+
+        self.job_events.send_all(&events).await
     }
 }
@@ -1,9 +1,15 @@
 pub mod opentelemetry;
 
+use anyhow::Result;
 use std::sync::OnceLock;
+use std::time::SystemTime;
 
 static APPLICATION_NAME: OnceLock<&'static str> = OnceLock::new();
 
+static HOST_ID: OnceLock<String> = OnceLock::new();
+
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
 pub fn set_application_name(name: &'static str) {
     let _ = APPLICATION_NAME.set(name);
 }
@@ -12,6 +18,64 @@ pub fn application_name() -> &'static str {
     APPLICATION_NAME.get().copied().unwrap_or("unknown")
 }
 
+/// Resolves the identifiers of the host and of the running process, see
+/// [`host_id`] and [`instance_id`].
+///
+/// The hostname is read from the `HOSTNAME` environment variable, or from
+/// `/etc/hostname` when it is not exported.
+///
+/// # Errors
+///
+/// Returns an error when the hostname cannot be resolved.
+pub fn init_instance_id() -> Result<()> {
+    let hostname = match std::env::var("HOSTNAME") {
+        Ok(hostname) if !hostname.trim().is_empty() => hostname.trim().to_string(),
+        _ => std::fs::read_to_string("/etc/hostname")
+            .map(|hostname| hostname.trim().to_string())
+            .ok()
+            .filter(|hostname| !hostname.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Cannot resolve the hostname, set the HOSTNAME environment variable"
+                )
+            })?,
+    };
+    let started_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+
+    let _ = INSTANCE_ID.set(format!("{hostname}-{}-{started_at}", std::process::id()));
+    let _ = HOST_ID.set(hostname);
+
+    Ok(())
+}
+
+/// Identifier of the host, kept across the restarts of the process.
+///
+/// # Panics
+///
+/// Panics when [`init_instance_id`] was not called first.
+pub fn host_id() -> &'static str {
+    HOST_ID
+        .get()
+        .expect("The host id must be initialized on start")
+}
+
+/// Identifier unique to the running process.
+///
+/// Built from the hostname, the process id and the start time, so that an
+/// instance can be told apart from its replicas and from its own restarts.
+///
+/// # Panics
+///
+/// Panics when [`init_instance_id`] was not called first.
+pub fn instance_id() -> &'static str {
+    INSTANCE_ID
+        .get()
+        .expect("The instance id must be initialized on start")
+}
+
 #[macro_export]
 macro_rules! counter {
     ($name:ident, $metric:literal, $description:literal) => {
@@ -24,3 +88,17 @@ macro_rules! counter {
             });
     };
 }
+
+#[macro_export]
+macro_rules! histogram {
+    ($name:ident, $metric:literal, $description:literal, $unit:literal) => {
+        static $name: ::std::sync::LazyLock<::opentelemetry::metrics::Histogram<u64>> =
+            ::std::sync::LazyLock::new(|| {
+                ::opentelemetry::global::meter($crate::application::application_name())
+                    .u64_histogram($metric)
+                    .with_description($description)
+                    .with_unit($unit)
+                    .build()
+            });
+    };
+}
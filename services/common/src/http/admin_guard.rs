@@ -0,0 +1,82 @@
+use axum::Router;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// Restricts the admin routes to the requests carrying the token of the
+/// `ADMIN_TOKEN` environment variable as a bearer token. The routes are
+/// refused altogether when no token is configured.
+pub struct AdminGuard;
+
+impl AdminGuard {
+    const ADMIN_TOKEN_ENV_VAR: &str = "ADMIN_TOKEN";
+
+    pub fn protect<S>(router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let admin_token = std::env::var(Self::ADMIN_TOKEN_ENV_VAR)
+            .ok()
+            .filter(|token| !token.is_empty());
+        if admin_token.is_none() {
+            tracing::warn!(
+                "No admin token configured, set the {} environment variable to enable the admin routes",
+                Self::ADMIN_TOKEN_ENV_VAR
+            );
+        }
+
+        router.route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(admin_token),
+            Self::check_token,
+        ))
+    }
+
+    async fn check_token(
+        State(admin_token): State<Arc<Option<String>>>,
+        request: Request,
+        next: Next,
+    ) -> Response {
+        let Some(admin_token) = admin_token.as_deref() else {
+            return (StatusCode::FORBIDDEN, "The admin routes are disabled").into_response();
+        };
+
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !token.is_some_and(|token| tokens_match(token, admin_token)) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+
+        next.run(request).await
+    }
+}
+
+/// Compares the tokens in a time independent of where they differ.
+fn tokens_match(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokens_match;
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        // Act & Assert
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret-but-longer", "secret"));
+    }
+}
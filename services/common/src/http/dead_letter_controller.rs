@@ -0,0 +1,78 @@
+use crate::counter;
+use crate::http::AdminGuard;
+use crate::messaging::dead_letter::DeadLetterReplayer;
+use axum::Json;
+use axum::Router;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::post;
+use std::sync::Arc;
+
+counter!(
+    REPLAY_DEAD_LETTERS_COUNTER,
+    "http_server_replay_dead_letters_requests",
+    "Number of replay dead letters requests"
+);
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ReplayParams {
+    max: Option<usize>,
+}
+
+pub struct DeadLetterController;
+
+impl DeadLetterController {
+    const DEFAULT_MAX_MESSAGES: usize = 1000;
+
+    /// Builds the admin routes replaying the dead letter topics of
+    /// `replayers`, addressed by their source topic, any other topic being
+    /// refused. The routes are behind the [`AdminGuard`].
+    pub fn router(replayers: Vec<DeadLetterReplayer>) -> Router {
+        AdminGuard::protect(
+            Router::new()
+                .route(
+                    "/admin/dead-letters/{topic}/replay",
+                    post(Self::replay_dead_letters_endpoint_handler),
+                )
+                .with_state(Arc::new(replayers)),
+        )
+    }
+
+    #[tracing::instrument(skip(replayers))]
+    async fn replay_dead_letters_endpoint_handler(
+        State(replayers): State<Arc<Vec<DeadLetterReplayer>>>,
+        Path(topic): Path<String>,
+        Query(params): Query<ReplayParams>,
+    ) -> Response {
+        tracing::info!("Replaying the dead letters of topic {topic}");
+
+        REPLAY_DEAD_LETTERS_COUNTER.add(1, &[]);
+
+        let Some(replayer) = replayers
+            .iter()
+            .find(|replayer| replayer.source_topic() == topic)
+        else {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("No dead letter topic configured for {topic}"),
+            )
+                .into_response();
+        };
+
+        match replayer
+            .replay(params.max.unwrap_or(Self::DEFAULT_MAX_MESSAGES))
+            .await
+        {
+            Ok(report) => Json(report).into_response(),
+            Err(err) => {
+                tracing::error!("Failed to replay the dead letters of topic {topic}: {err}");
+
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        }
+    }
+}
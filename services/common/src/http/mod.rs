@@ -1,6 +1,10 @@
+mod admin_guard;
+mod dead_letter_controller;
 mod fallback_controller;
 mod health_check;
 mod http_server;
 mod model;
 
+pub use admin_guard::AdminGuard;
+pub use dead_letter_controller::DeadLetterController;
 pub use http_server::HttpServer;
@@ -1,12 +1,20 @@
 use crate::counter;
+use crate::messaging::dead_letter::DeadLetterPolicy;
+use crate::messaging::dead_letter::DeadLetterPublisher;
+use crate::messaging::dead_letter::DeadLetterReplayer;
 use crate::messaging::opentelemetry::KafkaHeaderContextExtractor;
 use crate::messaging::opentelemetry::should_instrument_kafka;
+use crate::messaging::retry;
+use crate::messaging::retry::RetryMode;
+use crate::messaging::retry::RetryPolicy;
+use crate::messaging::retry::RetryPublisher;
 use anyhow::Result;
 use rdkafka::Message as _;
 use rdkafka::consumer::Consumer as _;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::Instrument as _;
@@ -22,6 +30,16 @@ counter!(
     "consumer_messages_error",
     "Number of messages that encountered an error by the Kafka consumer"
 );
+counter!(
+    MESSAGE_RETRIED_COUNTER,
+    "consumer_messages_retried",
+    "Number of messages scheduled for another attempt by the Kafka consumer"
+);
+counter!(
+    MESSAGE_GAVE_UP_COUNTER,
+    "consumer_messages_gave_up",
+    "Number of messages given up by the Kafka consumer after their last attempt"
+);
 
 #[derive(Default)]
 pub struct KafkaConsumerContext;
@@ -54,15 +72,52 @@ impl rdkafka::consumer::ConsumerContext for KafkaConsumerContext {
 
 pub type KafkaConsumer = rdkafka::consumer::StreamConsumer<KafkaConsumerContext>;
 
+/// Outcome of [`MessageHandler::handle`], deciding what becomes of the
+/// message.
+#[derive(Debug)]
+pub enum HandlerOutcome {
+    /// The message is processed, its offset is stored.
+    Ack,
+
+    /// The message failed for a reason that may go away. It is attempted
+    /// again after `after`, or after the backoff of the retry policy when
+    /// unset, until it runs out of attempts.
+    Retry {
+        after: Option<Duration>,
+        reason: String,
+    },
+
+    /// The message can never be processed, it is quarantined right away.
+    DeadLetter(String),
+
+    /// The message is left unacknowledged and handled again once `pause` has
+    /// elapsed, without using an attempt. Meant for a dependency known to be
+    /// unavailable for a while.
+    Nack { pause: Duration },
+}
+
+impl HandlerOutcome {
+    /// Retries the message after the backoff of the retry policy.
+    pub fn retry(reason: impl std::fmt::Display) -> Self {
+        Self::Retry {
+            after: None,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn dead_letter(reason: impl std::fmt::Display) -> Self {
+        Self::DeadLetter(reason.to_string())
+    }
+}
+
 pub trait MessageHandler<T>: Send + Sync + 'static {
-    fn handle(&self, message: T) -> impl Future<Output = Result<()>> + Send;
+    fn handle(&self, message: T) -> impl Future<Output = HandlerOutcome> + Send;
 }
 
 pub struct MessageConsumer<T, H> {
     consumers: Vec<Arc<KafkaConsumer>>,
-    handler: Arc<H>,
-    topic: &'static str,
-    _marker: PhantomData<fn() -> T>,
+    processor: Arc<MessageProcessor<T, H>>,
+    commit_offsets: bool,
 }
 
 impl<T, H> MessageConsumer<T, H>
@@ -97,38 +152,95 @@ where
     /// back to the default URI when unset. Start the consumers by calling
     /// [`Self::start`].
     ///
+    /// A message whose handler asks for a retry is attempted again as
+    /// described by `retry`, either in place or through the retry topic,
+    /// which another `concurrency` consumers then subscribe to, so that the
+    /// retries waiting for their time never hold the source partitions. The
+    /// messages that cannot be decoded, that the handler rejects, or that
+    /// failed their last attempt, are published on the topic of `dead_letter`
+    /// when set, and only dropped otherwise.
+    ///
     /// # Errors
     ///
     /// Returns an error when a Kafka consumer cannot be created from the
-    /// resolved configuration or when subscribing it to `topic` fails.
+    /// resolved configuration, when subscribing it to `topic` fails or when
+    /// the retry or dead letter producers cannot be created.
     pub fn new(
         handler: Arc<H>,
         topic: &'static str,
-        group_id: &'static str,
+        group_id: &str,
         concurrency: usize,
+        retry: RetryPolicy,
+        dead_letter: Option<DeadLetterPolicy>,
     ) -> Result<Self> {
         tracing::debug!("Initializing the Kafka consumer");
 
         let kafka_uri = std::env::var(Self::KAFKA_URI_ENV_VAR)
             .unwrap_or_else(|_| Self::DEFAULT_KAFKA_URI.to_string());
-        let topics = vec![topic];
+        let retry_publisher = match retry.mode() {
+            RetryMode::InPlace => None,
+            RetryMode::Topic(retry_topic) => Some(RetryPublisher::new(retry_topic)?),
+        };
 
         let mut consumers = Vec::with_capacity(concurrency);
         for _ in 0..concurrency {
             let consumer = Arc::new(Self::create_consumer(&kafka_uri, group_id)?);
-            consumer.subscribe(&topics)?;
+            consumer.subscribe(&[topic])?;
 
             consumers.push(consumer);
         }
 
+        // A retry waits for its time in the worker, so the retry topic gets workers of its own
+        if let RetryMode::Topic(retry_topic) = retry.mode() {
+            for _ in 0..concurrency {
+                let consumer = Arc::new(Self::create_consumer(&kafka_uri, group_id)?);
+                consumer.subscribe(&[retry_topic.as_str()])?;
+
+                consumers.push(consumer);
+            }
+        }
+
+        let dead_letter = dead_letter.map(DeadLetterPublisher::new).transpose()?;
+
         Ok(Self {
             consumers,
-            handler,
-            topic,
-            _marker: PhantomData,
+            processor: Arc::new(MessageProcessor {
+                handler,
+                topic,
+                retry,
+                retry_publisher,
+                dead_letter,
+                _marker: PhantomData,
+            }),
+            commit_offsets: true,
         })
     }
 
+    /// Never commits the offsets of the handled messages, so that every start
+    /// reads the topic again from its earliest retained message. Meant for
+    /// the state kept in memory, rebuilt from the topic on start.
+    #[must_use]
+    pub const fn without_offset_commits(mut self) -> Self {
+        self.commit_offsets = false;
+        self
+    }
+
+    /// Returns a replayer moving the dead letter messages back onto the
+    /// consumed topic, or `None` when no dead letter topic is configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the replayer producer cannot be created.
+    pub fn dead_letter_replayer(&self) -> Result<Option<DeadLetterReplayer>> {
+        self.processor
+            .dead_letter
+            .as_ref()
+            .map(|dead_letter| {
+                DeadLetterReplayer::new(self.processor.topic, dead_letter.policy().clone())
+            })
+            .transpose()
+    }
+
     pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
         tracing::debug!("Start the Kafka consumer");
 
@@ -136,19 +248,20 @@ where
             .iter()
             .map(|consumer| {
                 let consumer_cloned = Arc::clone(consumer);
-                let handler = Arc::clone(&self.handler);
-                let topic = self.topic;
+                let processor = Arc::clone(&self.processor);
+                let commit_offsets = self.commit_offsets;
                 let shutdown = shutdown.clone();
 
                 tokio::spawn(async move {
-                    Self::worker_consumer(consumer_cloned, handler, topic, shutdown).await;
+                    Self::worker_consumer(consumer_cloned, processor, commit_offsets, shutdown)
+                        .await;
                     Ok(())
                 })
             })
             .collect()
     }
 
-    fn create_consumer(uri: impl AsRef<str>, group_id: &'static str) -> Result<KafkaConsumer> {
+    fn create_consumer(uri: impl AsRef<str>, group_id: &str) -> Result<KafkaConsumer> {
         let consumer_config = Self::create_config(uri, group_id);
 
         consumer_config
@@ -156,7 +269,7 @@ where
             .map_err(|err| anyhow::anyhow!(format!("Failed to create Kafka consumer: {err}")))
     }
 
-    fn create_config(uri: impl AsRef<str>, group_id: &'static str) -> rdkafka::ClientConfig {
+    fn create_config(uri: impl AsRef<str>, group_id: &str) -> rdkafka::ClientConfig {
         let mut consumer_config = rdkafka::ClientConfig::new();
 
         consumer_config.set(Self::KAFKA_CONFIG_BOOTSTRAP_SERVERS, uri.as_ref());
@@ -196,13 +309,15 @@ where
 
     async fn worker_consumer(
         consumer: Arc<KafkaConsumer>,
-        handler: Arc<H>,
-        topic: &'static str,
+        processor: Arc<MessageProcessor<T, H>>,
+        commit_offsets: bool,
         shutdown: CancellationToken,
     ) {
+        let topic = processor.topic;
+
         loop {
             let received = tokio::select! {
-                () = shutdown.cancelled() => return,
+                () = shutdown.cancelled() => break,
                 result = consumer.recv() => result,
             };
             match received {
@@ -216,65 +331,227 @@ where
                             },
                         ));
                     }
-                    async {
-                        tracing::info!("Received Kafka message on topic {}", topic);
+                    let processed = async {
+                        tracing::info!("Received Kafka message on topic {}", message.topic());
 
                         MESSAGE_RECEIVED_COUNTER
                             .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
 
-                        let payload = match message.payload_view::<str>() {
-                            None => {
-                                tracing::warn!("No message found");
+                        processor.process(&message, &shutdown).await
+                    }
+                    .instrument(span)
+                    .await;
 
-                                MESSAGE_ERROR_COUNTER
-                                    .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                    // Interrupted by the shutdown, the message is left for the next run
+                    if !processed {
+                        break;
+                    }
 
-                                return;
-                            }
-                            Some(Ok(value)) => match T::try_from(value) {
-                                Ok(deserialize_value) => deserialize_value,
-                                Err(err) => {
-                                    tracing::error!("Error while deserializing message: {err:?}");
+                    if !commit_offsets {
+                        continue;
+                    }
 
-                                    MESSAGE_ERROR_COUNTER
-                                        .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                    if let Err(err) = consumer.store_offset_from_message(&message) {
+                        tracing::error!("Failed to store the offset from the message: {err}");
 
-                                    return;
-                                }
-                            },
-                            Some(Err(err)) => {
-                                tracing::error!("Error while converting message payload: {err:?}");
+                        MESSAGE_ERROR_COUNTER
+                            .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("Kafka error: {err}");
+
+                    MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                }
+            }
+        }
+
+        if commit_offsets {
+            Self::commit_stored_offsets(consumer, topic).await;
+        }
+    }
+
+    /// Commits the offsets stored for the processed messages right away,
+    /// rather than waiting for the next automatic commit, which never comes
+    /// once the process exits.
+    async fn commit_stored_offsets(consumer: Arc<KafkaConsumer>, topic: &'static str) {
+        let committed = tokio::task::spawn_blocking(move || {
+            consumer.commit_consumer_state(rdkafka::consumer::CommitMode::Sync)
+        })
+        .await;
+
+        match committed {
+            Ok(Ok(())) => tracing::debug!("Offsets of topic {topic} committed on shutdown"),
+            // Nothing was processed since the last commit
+            Ok(Err(rdkafka::error::KafkaError::ConsumerCommit(
+                rdkafka::types::RDKafkaErrorCode::NoOffset,
+            ))) => {}
+            Ok(Err(err)) => {
+                tracing::error!("Failed to commit the offsets of topic {topic} on shutdown: {err}");
+            }
+            Err(err) => {
+                tracing::error!("Failed to commit the offsets of topic {topic} on shutdown: {err}");
+            }
+        }
+    }
+}
 
-                                MESSAGE_ERROR_COUNTER
-                                    .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+/// Processing shared by the workers of a consumer: decoding, handling, and
+/// the retry and dead letter policies applied on failure.
+struct MessageProcessor<T, H> {
+    handler: Arc<H>,
+    topic: &'static str,
+    retry: RetryPolicy,
+    retry_publisher: Option<RetryPublisher>,
+    dead_letter: Option<DeadLetterPublisher>,
+    _marker: PhantomData<fn() -> T>,
+}
 
-                                return;
-                            }
-                        };
+impl<T, H> MessageProcessor<T, H>
+where
+    T: Send + 'static + for<'a> TryFrom<&'a str, Error = anyhow::Error>,
+    H: MessageHandler<T>,
+{
+    /// Processes `message` until it is handled, scheduled for another attempt
+    /// on the retry topic, or given up. Returns `false` when interrupted by
+    /// `shutdown`, in which case the offset of `message` must not be stored.
+    async fn process(
+        &self,
+        message: &rdkafka::message::BorrowedMessage<'_>,
+        shutdown: &CancellationToken,
+    ) -> bool {
+        let topic = self.topic;
+        let mut attempt = retry::message_attempt(message);
+
+        if let Some(delay) = retry::message_delay(message) {
+            tokio::select! {
+                () = shutdown.cancelled() => return false,
+                () = tokio::time::sleep(delay) => {}
+            }
+        }
 
-                        if let Err(err) = handler.handle(payload).await {
-                            tracing::error!("Failed to handle message: {err}");
+        loop {
+            let (after, reason) = match self.process_once(message).await {
+                HandlerOutcome::Ack => return true,
+                HandlerOutcome::DeadLetter(reason) => {
+                    tracing::error!("{reason}");
 
-                            MESSAGE_ERROR_COUNTER
-                                .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
-                        }
+                    MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
 
-                        if let Err(err) = consumer.store_offset_from_message(&message) {
-                            tracing::error!("Failed to store the offset from the message: {err}");
+                    return self.dead_letter(message, &reason, attempt, shutdown).await;
+                }
+                HandlerOutcome::Nack { pause } => {
+                    tracing::warn!("Message not acknowledged, handling it again in {pause:?}");
 
-                            MESSAGE_ERROR_COUNTER
-                                .add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
-                        }
+                    tokio::select! {
+                        () = shutdown.cancelled() => return false,
+                        () = tokio::time::sleep(pause) => {}
                     }
-                    .instrument(span)
-                    .await;
+                    continue;
                 }
-                Err(err) => {
-                    tracing::error!("Kafka error: {err}");
+                HandlerOutcome::Retry { after, reason } => (after, reason),
+            };
 
-                    MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+            tracing::error!(
+                "Attempt {attempt}/{} failed: {reason}",
+                self.retry.max_attempts()
+            );
+
+            MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+
+            if attempt >= self.retry.max_attempts() {
+                MESSAGE_GAVE_UP_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+
+                return self.dead_letter(message, &reason, attempt, shutdown).await;
+            }
+
+            let backoff = after.unwrap_or_else(|| self.retry.backoff(attempt));
+
+            MESSAGE_RETRIED_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+
+            if let Some(retry_publisher) = &self.retry_publisher {
+                match retry_publisher.publish(message, attempt + 1, backoff).await {
+                    Ok(()) => {
+                        tracing::debug!(
+                            "Message scheduled for attempt {} in {backoff:?}",
+                            attempt + 1
+                        );
+
+                        return true;
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            "Failed to send the message to the retry topic, retrying in place: {err}"
+                        );
+                    }
                 }
             }
+
+            tokio::select! {
+                () = shutdown.cancelled() => return false,
+                () = tokio::time::sleep(backoff) => {}
+            }
+            attempt += 1;
+        }
+    }
+
+    async fn process_once(
+        &self,
+        message: &rdkafka::message::BorrowedMessage<'_>,
+    ) -> HandlerOutcome {
+        let payload = match message.payload_view::<str>() {
+            None => return HandlerOutcome::dead_letter("No message found"),
+            Some(Ok(value)) => match T::try_from(value) {
+                Ok(deserialize_value) => deserialize_value,
+                Err(err) => {
+                    return HandlerOutcome::dead_letter(format!(
+                        "Error while deserializing message: {err}"
+                    ));
+                }
+            },
+            Some(Err(err)) => {
+                return HandlerOutcome::dead_letter(format!(
+                    "Error while converting message payload: {err}"
+                ));
+            }
+        };
+
+        self.handler.handle(payload).await
+    }
+
+    /// Publishes `message` on the dead letter topic, trying again until it
+    /// is delivered so that its offset is never stored while it exists
+    /// nowhere else. Returns `false` when interrupted by `shutdown`.
+    async fn dead_letter(
+        &self,
+        message: &rdkafka::message::BorrowedMessage<'_>,
+        reason: &str,
+        attempts: u32,
+        shutdown: &CancellationToken,
+    ) -> bool {
+        let Some(dead_letter) = &self.dead_letter else {
+            tracing::warn!("No dead letter topic configured, dropping the message");
+            return true;
+        };
+
+        let mut failures = 0;
+        loop {
+            let Err(err) = dead_letter.publish(message, reason, attempts).await else {
+                return true;
+            };
+            failures += 1;
+
+            let backoff = self.retry.backoff(failures);
+            tracing::error!(
+                "Failed to send the message to the dead letter topic, trying again in {backoff:?}: {err}"
+            );
+
+            MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", self.topic)]);
+
+            tokio::select! {
+                () = shutdown.cancelled() => return false,
+                () = tokio::time::sleep(backoff) => {}
+            }
         }
     }
 }
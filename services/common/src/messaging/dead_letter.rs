@@ -0,0 +1,375 @@
+use crate::counter;
+use crate::messaging::headers::copy_headers;
+use crate::messaging::headers::header;
+use crate::messaging::producer::RawMessageProducer;
+use crate::messaging::retry;
+use anyhow::Result;
+use rdkafka::Message as _;
+use rdkafka::consumer::Consumer as _;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+counter!(
+    MESSAGE_DEAD_LETTERED_COUNTER,
+    "consumer_messages_dead_lettered",
+    "Number of messages sent to a dead letter topic by the Kafka consumer"
+);
+counter!(
+    MESSAGE_REPLAYED_COUNTER,
+    "consumer_messages_replayed",
+    "Number of dead letter messages replayed onto their source topic"
+);
+
+pub const DEAD_LETTER_TOPIC_SUFFIX: &str = ".dlq";
+
+pub const HEADER_ERROR_REASON: &str = "dlq.error.reason";
+pub const HEADER_SOURCE_TOPIC: &str = "dlq.source.topic";
+pub const HEADER_SOURCE_PARTITION: &str = "dlq.source.partition";
+pub const HEADER_SOURCE_OFFSET: &str = "dlq.source.offset";
+pub const HEADER_ATTEMPTS: &str = "dlq.attempts";
+
+pub(crate) const HEADER_PREFIX: &str = "dlq.";
+
+/// Where the messages a consumer fails to process are quarantined.
+#[derive(Clone, Debug)]
+pub struct DeadLetterPolicy {
+    topic: String,
+}
+
+impl DeadLetterPolicy {
+    /// Uses the conventional `<source_topic>.dlq` topic.
+    #[must_use]
+    pub fn for_topic(source_topic: &str) -> Self {
+        Self {
+            topic: format!("{source_topic}{DEAD_LETTER_TOPIC_SUFFIX}"),
+        }
+    }
+
+    pub fn with_topic(topic: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+pub(crate) struct DeadLetterPublisher {
+    producer: RawMessageProducer,
+    policy: DeadLetterPolicy,
+}
+
+impl DeadLetterPublisher {
+    /// # Errors
+    ///
+    /// Returns an error when the underlying Kafka producer cannot be created.
+    pub fn new(policy: DeadLetterPolicy) -> Result<Self> {
+        Ok(Self {
+            producer: RawMessageProducer::new()?,
+            policy,
+        })
+    }
+
+    pub const fn policy(&self) -> &DeadLetterPolicy {
+        &self.policy
+    }
+
+    /// Publishes `message` unchanged on the dead letter topic. Its original
+    /// headers are kept, and the failure `reason`, the source coordinates and
+    /// the number of processing `attempts` are added to them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the message cannot be delivered to the dead
+    /// letter topic.
+    pub async fn publish(
+        &self,
+        message: &rdkafka::message::BorrowedMessage<'_>,
+        reason: &str,
+        attempts: u32,
+    ) -> Result<()> {
+        let headers = copy_headers(message.headers(), &[HEADER_PREFIX])
+            .insert(header(HEADER_ERROR_REASON, reason))
+            .insert(header(HEADER_SOURCE_TOPIC, message.topic()))
+            .insert(header(
+                HEADER_SOURCE_PARTITION,
+                &message.partition().to_string(),
+            ))
+            .insert(header(HEADER_SOURCE_OFFSET, &message.offset().to_string()))
+            .insert(header(HEADER_ATTEMPTS, &attempts.to_string()));
+
+        self.producer
+            .send_payload(
+                self.policy.topic(),
+                message.key(),
+                message.payload(),
+                headers,
+            )
+            .await?;
+
+        tracing::warn!(
+            "Message {}/{}/{} sent to dead letter topic {}: {reason}",
+            message.topic(),
+            message.partition(),
+            message.offset(),
+            self.policy.topic()
+        );
+
+        MESSAGE_DEAD_LETTERED_COUNTER.add(
+            1,
+            &[opentelemetry::KeyValue::new(
+                "topic",
+                message.topic().to_string(),
+            )],
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ReplayReport {
+    source_topic: String,
+    dead_letter_topic: String,
+    replayed: usize,
+}
+
+/// Moves the messages of a dead letter topic back onto their source topic.
+///
+/// The progress is committed under a dedicated consumer group, so a message
+/// is replayed once, whatever the number of replays requested afterward.
+pub struct DeadLetterReplayer {
+    producer: RawMessageProducer,
+    source_topic: String,
+    policy: DeadLetterPolicy,
+    replay_lock: tokio::sync::Mutex<()>,
+}
+
+impl DeadLetterReplayer {
+    const KAFKA_URI_ENV_VAR: &str = "KAFKA_URI";
+    const DEFAULT_KAFKA_URI: &str = "127.0.0.1:9092";
+
+    const GROUP_ID_SUFFIX: &str = ".replay";
+    const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+    const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+    const KAFKA_CONFIG_BOOTSTRAP_SERVERS: &str = "bootstrap.servers";
+    const KAFKA_CONFIG_ENABLE_AUTO_COMMIT: &str = "enable.auto.commit";
+    const KAFKA_CONFIG_GROUP_ID: &str = "group.id";
+
+    /// # Errors
+    ///
+    /// Returns an error when the underlying Kafka producer cannot be created.
+    pub fn new(source_topic: impl Into<String>, policy: DeadLetterPolicy) -> Result<Self> {
+        Ok(Self {
+            producer: RawMessageProducer::new()?,
+            source_topic: source_topic.into(),
+            policy,
+            replay_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn source_topic(&self) -> &str {
+        &self.source_topic
+    }
+
+    /// Replays up to `max_messages` dead letter messages, stopping early once
+    /// the messages present when the replay started have all been replayed.
+    /// The dead letter and retry headers are stripped from the replayed
+    /// messages, so they get a fresh set of attempts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the dead letter topic cannot be read, when a
+    /// message cannot be published on the source topic, or when the replay
+    /// progress cannot be committed. The progress made before the failure is
+    /// committed all the same.
+    pub async fn replay(&self, max_messages: usize) -> Result<ReplayReport> {
+        // Concurrent replays would share the same consumer group
+        let _guard = self.replay_lock.lock().await;
+        let dead_letter_topic = self.policy.topic().to_string();
+
+        tracing::info!(
+            "Replaying up to {max_messages} messages from {dead_letter_topic} onto {}",
+            self.source_topic
+        );
+
+        let consumer = Arc::new(Self::create_consumer(&dead_letter_topic)?);
+        let mut high_watermarks = tokio::task::spawn_blocking({
+            let consumer = Arc::clone(&consumer);
+            let topic = dead_letter_topic.clone();
+            move || Self::assign_partitions(&consumer, &topic)
+        })
+        .await??;
+
+        let mut next_offsets = HashMap::new();
+        let mut replayed = 0;
+        let mut failure = None;
+        while replayed < max_messages && !high_watermarks.is_empty() {
+            let Ok(received) = tokio::time::timeout(Self::POLL_TIMEOUT, consumer.recv()).await
+            else {
+                tracing::warn!("No message received from {dead_letter_topic}, stopping the replay");
+                break;
+            };
+            let message = match received {
+                Ok(message) => message,
+                Err(err) => {
+                    failure = Some(anyhow::Error::from(err));
+                    break;
+                }
+            };
+
+            let partition = message.partition();
+            let Some(&high_watermark) = high_watermarks.get(&partition) else {
+                continue;
+            };
+
+            if message.offset() < high_watermark {
+                if let Err(err) = self
+                    .producer
+                    .send_payload(
+                        &self.source_topic,
+                        message.key(),
+                        message.payload(),
+                        copy_headers(message.headers(), &[HEADER_PREFIX, retry::HEADER_PREFIX]),
+                    )
+                    .await
+                {
+                    failure = Some(err);
+                    break;
+                }
+
+                next_offsets.insert(partition, message.offset() + 1);
+                replayed += 1;
+
+                MESSAGE_REPLAYED_COUNTER.add(
+                    1,
+                    &[opentelemetry::KeyValue::new(
+                        "topic",
+                        self.source_topic.clone(),
+                    )],
+                );
+            }
+
+            if message.offset() + 1 >= high_watermark {
+                high_watermarks.remove(&partition);
+            }
+        }
+
+        // Committed even on failure, so the messages already replayed are not replayed again
+        let committed = tokio::task::spawn_blocking({
+            let consumer = Arc::clone(&consumer);
+            let topic = dead_letter_topic.clone();
+            move || Self::commit_progress(&consumer, &topic, next_offsets)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+        if let Some(err) = failure {
+            if let Err(commit_err) = committed {
+                tracing::error!(
+                    "Failed to commit the replay progress of {dead_letter_topic}: {commit_err}"
+                );
+            }
+            tracing::error!(
+                "Replay of {dead_letter_topic} failed after {replayed} messages: {err}"
+            );
+            return Err(err);
+        }
+        committed?;
+
+        tracing::info!("Replayed {replayed} messages from {dead_letter_topic}");
+
+        Ok(ReplayReport {
+            source_topic: self.source_topic.clone(),
+            dead_letter_topic,
+            replayed,
+        })
+    }
+
+    fn commit_progress(
+        consumer: &rdkafka::consumer::StreamConsumer,
+        dead_letter_topic: &str,
+        next_offsets: HashMap<i32, i64>,
+    ) -> Result<()> {
+        if next_offsets.is_empty() {
+            return Ok(());
+        }
+
+        let mut offsets = rdkafka::TopicPartitionList::new();
+        for (partition, offset) in next_offsets {
+            offsets.add_partition_offset(
+                dead_letter_topic,
+                partition,
+                rdkafka::Offset::Offset(offset),
+            )?;
+        }
+        consumer.commit(&offsets, rdkafka::consumer::CommitMode::Sync)?;
+
+        Ok(())
+    }
+
+    fn create_consumer(dead_letter_topic: &str) -> Result<rdkafka::consumer::StreamConsumer> {
+        let kafka_uri = std::env::var(Self::KAFKA_URI_ENV_VAR)
+            .unwrap_or_else(|_| Self::DEFAULT_KAFKA_URI.to_string());
+
+        let mut consumer_config = rdkafka::ClientConfig::new();
+        consumer_config.set(Self::KAFKA_CONFIG_BOOTSTRAP_SERVERS, kafka_uri);
+        consumer_config.set(
+            Self::KAFKA_CONFIG_GROUP_ID,
+            format!("{dead_letter_topic}{}", Self::GROUP_ID_SUFFIX),
+        );
+        consumer_config.set(Self::KAFKA_CONFIG_ENABLE_AUTO_COMMIT, "false");
+        consumer_config.set_log_level(rdkafka::config::RDKafkaLogLevel::Warning);
+
+        consumer_config
+            .create()
+            .map_err(|err| anyhow::anyhow!(format!("Failed to create Kafka consumer: {err}")))
+    }
+
+    /// Assigns the partitions still holding messages to replay, starting from
+    /// the committed offsets, and returns their current high watermarks.
+    fn assign_partitions(
+        consumer: &rdkafka::consumer::StreamConsumer,
+        topic: &str,
+    ) -> Result<HashMap<i32, i64>> {
+        let metadata = consumer.fetch_metadata(Some(topic), Self::METADATA_TIMEOUT)?;
+
+        let mut partitions = rdkafka::TopicPartitionList::new();
+        for metadata_topic in metadata.topics() {
+            for partition in metadata_topic.partitions() {
+                partitions.add_partition(topic, partition.id());
+            }
+        }
+
+        let committed = consumer.committed_offsets(partitions, Self::METADATA_TIMEOUT)?;
+
+        let mut assignment = rdkafka::TopicPartitionList::new();
+        let mut high_watermarks = HashMap::new();
+        for element in committed.elements_for_topic(topic) {
+            let (low_watermark, high_watermark) =
+                consumer.fetch_watermarks(topic, element.partition(), Self::METADATA_TIMEOUT)?;
+            let start = match element.offset() {
+                rdkafka::Offset::Offset(offset) => offset.max(low_watermark),
+                _ => low_watermark,
+            };
+
+            if start < high_watermark {
+                assignment.add_partition_offset(
+                    topic,
+                    element.partition(),
+                    rdkafka::Offset::Offset(start),
+                )?;
+                high_watermarks.insert(element.partition(), high_watermark);
+            }
+        }
+
+        consumer.assign(&assignment)?;
+
+        Ok(high_watermarks)
+    }
+}
@@ -0,0 +1,41 @@
+use rdkafka::message::Headers as _;
+
+pub const fn header<'a>(key: &'a str, value: &'a str) -> rdkafka::message::Header<'a, &'a str> {
+    rdkafka::message::Header {
+        key,
+        value: Some(value),
+    }
+}
+
+/// Copies `headers`, leaving out the ones whose key starts with any of the
+/// `excluded_prefixes`.
+pub fn copy_headers(
+    headers: Option<&rdkafka::message::BorrowedHeaders>,
+    excluded_prefixes: &[&str],
+) -> rdkafka::message::OwnedHeaders {
+    let mut owned = rdkafka::message::OwnedHeaders::new();
+    if let Some(headers) = headers {
+        for header in headers.iter() {
+            if !excluded_prefixes
+                .iter()
+                .any(|prefix| header.key.starts_with(prefix))
+            {
+                owned = owned.insert(header);
+            }
+        }
+    }
+    owned
+}
+
+/// Returns the UTF-8 value of the last header named `key`.
+pub fn header_value<'a>(
+    headers: Option<&'a rdkafka::message::BorrowedHeaders>,
+    key: &str,
+) -> Option<&'a str> {
+    headers?
+        .iter()
+        .filter(|header| header.key == key)
+        .last()
+        .and_then(|header| header.value)
+        .and_then(|value| std::str::from_utf8(value).ok())
+}
@@ -1,3 +1,6 @@
 pub mod consumer;
+pub mod dead_letter;
+mod headers;
 mod opentelemetry;
 pub mod producer;
+pub mod retry;
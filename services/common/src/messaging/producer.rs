@@ -2,7 +2,15 @@ use crate::counter;
 use crate::messaging::opentelemetry::KafkaHeaderContextInjector;
 use crate::messaging::opentelemetry::should_instrument_kafka;
 use anyhow::Result;
+use futures::StreamExt as _;
+use futures::stream::FuturesOrdered;
+use rdkafka::producer::Producer as _;
 use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use tracing::Instrument as _;
 use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 
@@ -43,20 +51,138 @@ impl rdkafka::producer::ProducerContext for KafkaProducerContext {
 
 type KafkaProducer = rdkafka::producer::FutureProducer<KafkaProducerContext>;
 
-pub struct MessageProducer<T> {
+static SHARED_PRODUCER: Mutex<Option<RawMessageProducer>> = Mutex::new(None);
+
+/// Number of messages handed to the producer whose delivery is not settled.
+static PENDING_SENDS: AtomicUsize = AtomicUsize::new(0);
+
+struct PendingSends(usize);
+
+impl PendingSends {
+    fn track(count: usize) -> Self {
+        PENDING_SENDS.fetch_add(count, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for PendingSends {
+    fn drop(&mut self) {
+        PENDING_SENDS.fetch_sub(self.0, Ordering::Relaxed);
+    }
+}
+
+/// Messages still outstanding when the producer was flushed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlushReport {
+    flushed: usize,
+    dropped: usize,
+}
+
+impl FlushReport {
+    #[must_use]
+    pub const fn flushed(&self) -> usize {
+        self.flushed
+    }
+
+    #[must_use]
+    pub const fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Waits, for at most `timeout`, for the outstanding messages of the shared
+/// producer to be delivered. Meant to be called once on shutdown, after the
+/// components sending messages are stopped.
+pub async fn flush_producer(timeout: Duration) -> FlushReport {
+    let producer = SHARED_PRODUCER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let Some(producer) = producer else {
+        return FlushReport::default();
+    };
+
+    let outstanding = producer.outstanding();
+    tracing::info!("Flushing {outstanding} outstanding Kafka messages");
+
+    // The pending sends settle on their own, then whatever is left in the queue is flushed
+    let deadline = tokio::time::Instant::now() + timeout;
+    while PENDING_SENDS.load(Ordering::Relaxed) > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(RawMessageProducer::FLUSH_POLL_INTERVAL).await;
+    }
+
+    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+    let flushed_producer = producer.clone();
+    match tokio::task::spawn_blocking(move || flushed_producer.producer.flush(remaining)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => tracing::error!("Failed to flush the Kafka producer: {err}"),
+        Err(err) => tracing::error!("Failed to flush the Kafka producer: {err}"),
+    }
+
+    let dropped = producer.outstanding();
+    let report = FlushReport {
+        flushed: outstanding.saturating_sub(dropped),
+        dropped,
+    };
+
+    if dropped > 0 {
+        tracing::warn!(
+            "Kafka producer flushed {} messages, {dropped} messages dropped",
+            report.flushed
+        );
+    } else {
+        tracing::info!("Kafka producer flushed {} messages", report.flushed);
+    }
+
+    report
+}
+
+/// Position of a message acknowledged by the broker.
+#[derive(Clone, Copy, Debug)]
+pub struct Delivery {
+    partition: i32,
+    offset: i64,
+}
+
+impl Delivery {
+    #[must_use]
+    pub const fn partition(&self) -> i32 {
+        self.partition
+    }
+
+    #[must_use]
+    pub const fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+impl From<rdkafka::producer::future_producer::Delivery> for Delivery {
+    fn from(delivery: rdkafka::producer::future_producer::Delivery) -> Self {
+        Self {
+            partition: delivery.partition,
+            offset: delivery.offset,
+        }
+    }
+}
+
+/// Kafka producer publishing already encoded payloads, on any topic. It backs
+/// [`MessageProducer`] and the dead letter handling of the consumers.
+#[derive(Clone)]
+pub struct RawMessageProducer {
     producer: KafkaProducer,
-    topic: &'static str,
-    _marker: PhantomData<fn() -> T>,
 }
 
-impl<T> MessageProducer<T>
-where
-    T: serde::Serialize + Send + 'static,
-{
+impl RawMessageProducer {
     const KAFKA_URI_ENV_VAR: &str = "KAFKA_URI";
     const DEFAULT_KAFKA_URI: &str = "127.0.0.1:9092";
 
     const QUEUE_TIMEOUT: u64 = 4;
+    const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(10);
+    const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Maximum number of messages of a batch waiting for their broker
+    /// acknowledgement.
+    pub const MAX_IN_FLIGHT: usize = 1024;
 
     const KAFKA_CONFIG_ACKS: &str = "acks";
     const KAFKA_CONFIG_BATCH_SIZE: &str = "batch.size";
@@ -69,26 +195,40 @@ where
     const KAFKA_CONFIG_COMPRESSION_TYPE_DEFAULT_VALUE: &str = "zstd";
     const KAFKA_CONFIG_LINGER_MS_DEFAULT_VALUE: &str = "50";
 
-    /// Builds a Kafka future producer bound to `topic`, reading the broker
-    /// address from the `KAFKA_URI` environment variable and falling back
-    /// to the default URI when unset. Outgoing payloads are JSON-serialized
-    /// and dispatched on a Tokio task by [`Self::send`].
+    /// Returns the Kafka future producer shared by the whole process, so
+    /// that [`flush_producer`] reaches every queued message. It is built on
+    /// first use, reading the broker address from the `KAFKA_URI`
+    /// environment variable and falling back to the default URI when unset.
     ///
     /// # Errors
     ///
     /// Returns an error when the underlying Kafka producer cannot be
     /// constructed from the resolved configuration.
-    pub fn new(topic: &'static str) -> Result<Self> {
-        tracing::debug!("Initializing the Kafka producer");
+    pub fn new() -> Result<Self> {
+        let mut shared_producer = SHARED_PRODUCER
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(producer) = shared_producer.as_ref() {
+            return Ok(producer.clone());
+        }
 
         let kafka_uri = std::env::var(Self::KAFKA_URI_ENV_VAR)
             .unwrap_or_else(|_| Self::DEFAULT_KAFKA_URI.to_string());
-
-        Ok(Self {
+        let producer = Self {
             producer: Self::create_producer(kafka_uri)?,
-            topic,
-            _marker: PhantomData,
-        })
+        };
+        *shared_producer = Some(producer.clone());
+        drop(shared_producer);
+
+        Ok(producer)
+    }
+
+    /// Number of messages not delivered yet, either queued by librdkafka or
+    /// about to be.
+    fn outstanding(&self) -> usize {
+        let queued = usize::try_from(self.producer.in_flight_count()).unwrap_or_default();
+
+        queued.max(PENDING_SENDS.load(Ordering::Relaxed))
     }
 
     fn create_producer(uri: impl AsRef<str>) -> Result<KafkaProducer> {
@@ -99,16 +239,183 @@ where
             .map_err(|err| anyhow::anyhow!(format!("Failed to create Kafka producer: {err}")))
     }
 
+    /// Publishes `payload` on `topic` with `key` and `headers`, and waits
+    /// for the broker acknowledgement.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the message cannot be queued before the queue
+    /// timeout, or when the broker rejects it.
+    pub async fn send_payload(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+        headers: rdkafka::message::OwnedHeaders,
+    ) -> Result<Delivery> {
+        let _pending = PendingSends::track(1);
+        let mut future_record: rdkafka::producer::FutureRecord<'_, [u8], [u8]> =
+            rdkafka::producer::FutureRecord::to(topic).headers(headers);
+        if let Some(key) = key {
+            future_record = future_record.key(key);
+        }
+        if let Some(payload) = payload {
+            future_record = future_record.payload(payload);
+        }
+
+        self.producer
+            .send(
+                future_record,
+                tokio::time::Duration::from_secs(Self::QUEUE_TIMEOUT),
+            )
+            .await
+            .map(Delivery::from)
+            .map_err(|(kafka_error, _borrowed_message)| anyhow::anyhow!(kafka_error))
+    }
+
+    /// Publishes the `messages` on `topic`, and waits for their broker
+    /// acknowledgements, returned in the same order.
+    ///
+    /// At most [`Self::MAX_IN_FLIGHT`] messages wait for an acknowledgement at
+    /// any time. When the producer queue is full, the oldest acknowledgement
+    /// is awaited before queuing the next message, so a large batch never
+    /// buffers more than the queue can hold.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error on the first message that cannot be queued or that
+    /// the broker rejects.
+    pub async fn send_batch(
+        &self,
+        topic: &str,
+        messages: Vec<(Vec<u8>, rdkafka::message::OwnedHeaders)>,
+    ) -> Result<Vec<Delivery>> {
+        let _pending = PendingSends::track(messages.len());
+        let mut deliveries = Vec::with_capacity(messages.len());
+        let mut in_flight = FuturesOrdered::new();
+
+        for (payload, headers) in messages {
+            if in_flight.len() >= Self::MAX_IN_FLIGHT
+                && let Some(delivery) = in_flight.next().await
+            {
+                deliveries.push(Self::delivery_result(delivery)?);
+            }
+
+            let mut future_record: rdkafka::producer::FutureRecord<'_, [u8], [u8]> =
+                rdkafka::producer::FutureRecord::to(topic)
+                    .payload(payload.as_slice())
+                    .headers(headers);
+            loop {
+                match self.producer.send_result(future_record) {
+                    Ok(delivery_future) => {
+                        in_flight.push_back(delivery_future);
+                        break;
+                    }
+                    Err((
+                        rdkafka::error::KafkaError::MessageProduction(
+                            rdkafka::types::RDKafkaErrorCode::QueueFull,
+                        ),
+                        returned_record,
+                    )) => {
+                        tracing::debug!("Kafka producer queue full, waiting for deliveries");
+
+                        future_record = returned_record;
+                        match in_flight.next().await {
+                            Some(delivery) => deliveries.push(Self::delivery_result(delivery)?),
+                            None => tokio::time::sleep(Self::QUEUE_FULL_BACKOFF).await,
+                        }
+                    }
+                    Err((kafka_error, _returned_record)) => {
+                        return Err(anyhow::anyhow!(kafka_error));
+                    }
+                }
+            }
+        }
+
+        while let Some(delivery) = in_flight.next().await {
+            deliveries.push(Self::delivery_result(delivery)?);
+        }
+
+        Ok(deliveries)
+    }
+
+    fn delivery_result(
+        result: Result<
+            rdkafka::producer::future_producer::OwnedDeliveryResult,
+            futures::channel::oneshot::Canceled,
+        >,
+    ) -> Result<Delivery> {
+        match result {
+            Ok(Ok(delivery)) => Ok(Delivery::from(delivery)),
+            Ok(Err((kafka_error, _owned_message))) => Err(anyhow::anyhow!(kafka_error)),
+            Err(_) => Err(anyhow::anyhow!("Kafka delivery cancelled")),
+        }
+    }
+
+    fn create_config(uri: impl AsRef<str>) -> rdkafka::ClientConfig {
+        let mut producer_config = rdkafka::config::ClientConfig::new();
+
+        producer_config.set(Self::KAFKA_CONFIG_BOOTSTRAP_SERVERS, uri.as_ref());
+        producer_config.set(
+            Self::KAFKA_CONFIG_ACKS,
+            Self::KAFKA_CONFIG_ACKS_DEFAULT_VALUE,
+        );
+        producer_config.set(
+            Self::KAFKA_CONFIG_COMPRESSION_TYPE,
+            Self::KAFKA_CONFIG_COMPRESSION_TYPE_DEFAULT_VALUE,
+        );
+        producer_config.set(
+            Self::KAFKA_CONFIG_LINGER_MS,
+            Self::KAFKA_CONFIG_LINGER_MS_DEFAULT_VALUE,
+        );
+        producer_config.set(
+            Self::KAFKA_CONFIG_BATCH_SIZE,
+            Self::KAFKA_CONFIG_BATCH_SIZE_DEFAULT_VALUE,
+        );
+        producer_config.set_log_level(rdkafka::config::RDKafkaLogLevel::Warning);
+
+        producer_config
+    }
+}
+
+pub struct MessageProducer<T> {
+    producer: RawMessageProducer,
+    topic: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> MessageProducer<T>
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    /// Binds the shared Kafka future producer to `topic`, see
+    /// [`RawMessageProducer::new`]. Outgoing payloads are JSON-serialized
+    /// and dispatched on a Tokio task by [`Self::send`], or awaited with
+    /// [`Self::deliver`] and [`Self::send_all`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the underlying Kafka producer cannot be
+    /// constructed from the resolved configuration.
+    pub fn new(topic: &'static str) -> Result<Self> {
+        tracing::debug!("Initializing the Kafka producer");
+
+        Ok(Self {
+            producer: RawMessageProducer::new()?,
+            topic,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Sends `payload` without waiting for the broker, a failure is only
+    /// logged.
     pub fn send(&self, payload: &T) {
         let topic = self.topic;
 
-        let serialized = match serde_json::to_string(payload) {
+        let serialized = match Self::serialize(topic, payload) {
             Ok(value) => value,
             Err(err) => {
-                tracing::error!("Failed to serialize message: {err}");
-
-                MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
-
+                tracing::error!("{err}");
                 return;
             }
         };
@@ -122,28 +429,14 @@ where
                 async {
                     tracing::debug!("Sending message");
 
-                    let mut context_injector = KafkaHeaderContextInjector::default();
-                    if should_instrument_kafka() {
-                        opentelemetry::global::get_text_map_propagator(|propagator| {
-                            let opentelemetry_context = tracing::Span::current().context();
-                            propagator
-                                .inject_context(&opentelemetry_context, &mut context_injector);
-                        });
-                    }
-
-                    let headers = rdkafka::message::OwnedHeaders::from(context_injector);
-                    let future_record: rdkafka::producer::FutureRecord<'_, str, _> =
-                        rdkafka::producer::FutureRecord::to(topic)
-                            .payload(&serialized)
-                            .headers(headers);
-
                     if let Err(err) = producer
-                        .send(
-                            future_record,
-                            tokio::time::Duration::from_secs(Self::QUEUE_TIMEOUT),
+                        .send_payload(
+                            topic,
+                            None,
+                            Some(serialized.as_slice()),
+                            Self::create_headers(),
                         )
                         .await
-                        .map_err(|(kafka_error, _borrowed_message)| kafka_error)
                     {
                         tracing::error!("Failed to send message to Kafka: {err}");
 
@@ -163,28 +456,103 @@ where
         );
     }
 
-    fn create_config(uri: impl AsRef<str>) -> rdkafka::ClientConfig {
-        let mut producer_config = rdkafka::config::ClientConfig::new();
+    /// Sends `payload` and waits for the broker acknowledgement.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when `payload` cannot be serialized, or when the
+    /// message is not accepted by the broker.
+    pub async fn deliver(&self, payload: &T) -> Result<Delivery> {
+        let topic = self.topic;
+        let serialized = Self::serialize(topic, payload)?;
+        let span = tracing::info_span!("messaging.send", topic = topic);
 
-        producer_config.set(Self::KAFKA_CONFIG_BOOTSTRAP_SERVERS, uri.as_ref());
-        producer_config.set(
-            Self::KAFKA_CONFIG_ACKS,
-            Self::KAFKA_CONFIG_ACKS_DEFAULT_VALUE,
-        );
-        producer_config.set(
-            Self::KAFKA_CONFIG_COMPRESSION_TYPE,
-            Self::KAFKA_CONFIG_COMPRESSION_TYPE_DEFAULT_VALUE,
-        );
-        producer_config.set(
-            Self::KAFKA_CONFIG_LINGER_MS,
-            Self::KAFKA_CONFIG_LINGER_MS_DEFAULT_VALUE,
-        );
-        producer_config.set(
-            Self::KAFKA_CONFIG_BATCH_SIZE,
-            Self::KAFKA_CONFIG_BATCH_SIZE_DEFAULT_VALUE,
-        );
-        producer_config.set_log_level(rdkafka::config::RDKafkaLogLevel::Warning);
+        async {
+            tracing::debug!("Sending message");
 
-        producer_config
+            let delivery = self
+                .producer
+                .send_payload(
+                    topic,
+                    None,
+                    Some(serialized.as_slice()),
+                    Self::create_headers(),
+                )
+                .await
+                .inspect_err(|_| {
+                    MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                })?;
+
+            tracing::debug!("Message sent to Kafka");
+
+            MESSAGE_SENT_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+
+            Ok(delivery)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Sends `payloads` and waits for all the broker acknowledgements, which
+    /// are returned in the same order. The number of messages waiting for an
+    /// acknowledgement is bounded, and the sending slows down whenever the
+    /// producer queue is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when a payload cannot be serialized, or when a
+    /// message is not accepted by the broker. The messages sent before the
+    /// failing one may have been delivered.
+    pub async fn send_all(&self, payloads: &[T]) -> Result<Vec<Delivery>> {
+        let topic = self.topic;
+        let span = tracing::info_span!("messaging.send", topic = topic, count = payloads.len());
+
+        async {
+            tracing::debug!("Sending {} messages", payloads.len());
+
+            let messages = payloads
+                .iter()
+                .map(|payload| Ok((Self::serialize(topic, payload)?, Self::create_headers())))
+                .collect::<Result<Vec<_>>>()?;
+            let deliveries = self
+                .producer
+                .send_batch(topic, messages)
+                .await
+                .inspect_err(|_| {
+                    MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+                })?;
+
+            tracing::debug!("{} messages sent to Kafka", deliveries.len());
+
+            MESSAGE_SENT_COUNTER.add(
+                deliveries.len() as u64,
+                &[opentelemetry::KeyValue::new("topic", topic)],
+            );
+
+            Ok(deliveries)
+        }
+        .instrument(span)
+        .await
+    }
+
+    fn serialize(topic: &'static str, payload: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(payload).map_err(|err| {
+            MESSAGE_ERROR_COUNTER.add(1, &[opentelemetry::KeyValue::new("topic", topic)]);
+
+            anyhow::anyhow!("Failed to serialize message: {err}")
+        })
+    }
+
+    /// Headers propagating the context of the current span.
+    fn create_headers() -> rdkafka::message::OwnedHeaders {
+        let mut context_injector = KafkaHeaderContextInjector::default();
+        if should_instrument_kafka() {
+            opentelemetry::global::get_text_map_propagator(|propagator| {
+                let opentelemetry_context = tracing::Span::current().context();
+                propagator.inject_context(&opentelemetry_context, &mut context_injector);
+            });
+        }
+
+        rdkafka::message::OwnedHeaders::from(context_injector)
     }
 }
@@ -0,0 +1,222 @@
+use crate::messaging::headers::copy_headers;
+use crate::messaging::headers::header;
+use crate::messaging::headers::header_value;
+use crate::messaging::producer::RawMessageProducer;
+use anyhow::Result;
+use rdkafka::Message as _;
+use std::time::Duration;
+use std::time::SystemTime;
+
+pub const RETRY_TOPIC_SUFFIX: &str = ".retry";
+
+pub const HEADER_ATTEMPT: &str = "retry.attempt";
+pub const HEADER_NOT_BEFORE: &str = "retry.not_before";
+
+pub(crate) const HEADER_PREFIX: &str = "retry.";
+
+/// How a failed message waits for its next attempt.
+#[derive(Clone, Debug)]
+pub enum RetryMode {
+    /// The consumer sleeps, then handles the message again. The partition is
+    /// blocked in the meantime.
+    InPlace,
+
+    /// The message is republished on a delayed retry topic, consumed by
+    /// workers of its own, which keeps the source partition flowing.
+    Topic(String),
+}
+
+/// Number of attempts given to a message whose handling fails, and the
+/// exponential backoff applied between them.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    mode: RetryMode,
+}
+
+impl RetryPolicy {
+    const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+    const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+    const DEFAULT_MULTIPLIER: f64 = 2.0;
+    const DEFAULT_JITTER: f64 = 0.2;
+
+    /// Handles a message once, a failure is final.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self::exponential(1)
+    }
+
+    /// Retries a message in place up to `max_attempts` attempts in total,
+    /// with the default backoff.
+    #[must_use]
+    pub const fn exponential(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: if max_attempts == 0 { 1 } else { max_attempts },
+            initial_backoff: Self::DEFAULT_INITIAL_BACKOFF,
+            max_backoff: Self::DEFAULT_MAX_BACKOFF,
+            multiplier: Self::DEFAULT_MULTIPLIER,
+            jitter: Self::DEFAULT_JITTER,
+            mode: RetryMode::InPlace,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Spreads each backoff randomly by up to `jitter` of its value, so the
+    /// consumers failing together do not retry together.
+    #[must_use]
+    pub const fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Retries through the conventional `<source_topic>.retry` topic.
+    #[must_use]
+    pub fn through_topic(mut self, source_topic: &str) -> Self {
+        self.mode = RetryMode::Topic(format!("{source_topic}{RETRY_TOPIC_SUFFIX}"));
+        self
+    }
+
+    #[must_use]
+    pub const fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    #[must_use]
+    pub const fn mode(&self) -> &RetryMode {
+        &self.mode
+    }
+
+    /// Delay to wait after the failed `attempt`, the first one being 1.
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let max_backoff = self.max_backoff.as_secs_f64();
+        let backoff =
+            (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent)).min(max_backoff);
+
+        let jittered = if self.jitter > 0.0 {
+            backoff * rand::random_range(1.0 - self.jitter..=1.0 + self.jitter)
+        } else {
+            backoff
+        };
+
+        Duration::from_secs_f64(jittered.min(max_backoff))
+    }
+}
+
+/// Attempt of `message`, as recorded by the retry topic.
+pub(crate) fn message_attempt(message: &rdkafka::message::BorrowedMessage<'_>) -> u32 {
+    header_value(message.headers(), HEADER_ATTEMPT)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Time left before `message` may be handled again, as recorded by the retry
+/// topic.
+pub(crate) fn message_delay(message: &rdkafka::message::BorrowedMessage<'_>) -> Option<Duration> {
+    let not_before = header_value(message.headers(), HEADER_NOT_BEFORE)?
+        .parse::<u64>()
+        .ok()?;
+    let not_before = SystemTime::UNIX_EPOCH + Duration::from_millis(not_before);
+
+    not_before.duration_since(SystemTime::now()).ok()
+}
+
+pub(crate) struct RetryPublisher {
+    producer: RawMessageProducer,
+    topic: String,
+}
+
+impl RetryPublisher {
+    pub fn new(topic: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            producer: RawMessageProducer::new()?,
+            topic: topic.into(),
+        })
+    }
+
+    /// Publishes `message` on the retry topic, to be handled for the given
+    /// `attempt` once `delay` has elapsed.
+    pub async fn publish(
+        &self,
+        message: &rdkafka::message::BorrowedMessage<'_>,
+        attempt: u32,
+        delay: Duration,
+    ) -> Result<()> {
+        let not_before = (SystemTime::now() + delay)
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+        let headers = copy_headers(message.headers(), &[HEADER_PREFIX])
+            .insert(header(HEADER_ATTEMPT, &attempt.to_string()))
+            .insert(header(HEADER_NOT_BEFORE, &not_before.to_string()));
+
+        self.producer
+            .send_payload(&self.topic, message.key(), message.payload(), headers)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_max() {
+        // Arrange
+        let policy = RetryPolicy::exponential(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(0.0);
+
+        // Act
+        let backoffs = (1..=6)
+            .map(|attempt| policy.backoff(attempt))
+            .collect::<Vec<_>>();
+
+        // Assert
+        assert_eq!(
+            backoffs,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(800),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        // Arrange
+        let policy = RetryPolicy::exponential(3)
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(10))
+            .with_jitter(0.5);
+
+        // Act & Assert
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff >= Duration::from_millis(500));
+            assert!(backoff <= Duration::from_millis(1500));
+        }
+    }
+}
@@ -1,15 +1,20 @@
+use crate::domain::executor::ExecutorRegistry;
 use crate::messaging::consumer::MessageConsumer;
 use crate::messaging::producer::MessageProducer;
 use anyhow::Result;
 use axum::Router;
+use common::http::DeadLetterController;
 use common::http::HttpServer;
+use common::messaging::producer::flush_producer;
 use futures::future::try_join_all;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal::unix::SignalKind;
 use tokio::signal::unix::signal;
 use tokio_util::sync::CancellationToken;
 
 const HTTP_PORT: u16 = 8080;
+const PRODUCER_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Application {
     consumer: MessageConsumer,
@@ -18,8 +23,13 @@ pub struct Application {
 
 pub async fn create_application() -> Result<Application> {
     let message_producer = Arc::new(MessageProducer::new()?);
-    let consumer = MessageConsumer::new(message_producer)?;
-    let http_server = HttpServer::new(HTTP_PORT, Router::new());
+    // Executors for other operation kinds are registered here
+    let executor_registry = Arc::new(ExecutorRegistry::default());
+    let consumer = MessageConsumer::new(&message_producer, &executor_registry)?;
+    let router = Router::new().merge(DeadLetterController::router(
+        consumer.dead_letter_replayers()?,
+    ));
+    let http_server = HttpServer::new(HTTP_PORT, router);
 
     Ok(Application {
         consumer,
@@ -43,13 +53,18 @@ pub async fn start_application(application: Application) -> Result<()> {
     let signal = wait_for_shutdown_signal(shutdown.clone());
     tokio::pin!(services, signal);
 
-    tokio::select! {
+    let result = tokio::select! {
         res = &mut services => {
             shutdown.cancel();
             first_error(res.map(|_| ()), signal.await)
         }
         res = &mut signal => first_error(res, services.await.map(|_| ())),
-    }
+    };
+
+    // Every service is stopped, deliver what is left in the producer queue
+    flush_producer(PRODUCER_FLUSH_TIMEOUT).await;
+
+    result
 }
 
 fn first_error(primary: Result<()>, secondary: Result<()>) -> Result<()> {
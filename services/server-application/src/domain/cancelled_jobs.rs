@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Jobs cancelled by a client, as announced on the job control topic. Queued
+/// operations of these jobs are skipped instead of being evaluated.
+///
+/// A cancellation is forgotten once it is older than the TTL, by which time
+/// the operations queued before it were consumed, so the set does not grow
+/// with every job ever cancelled. The control topic only retains its messages
+/// for as long, and is read from its start by every instance, so a restarted
+/// instance learns the cancellations it still has to honor again.
+pub struct CancelledJobs {
+    job_ids: RwLock<HashMap<String, SystemTime>>,
+    ttl: Duration,
+}
+
+impl CancelledJobs {
+    const DEFAULT_TTL: Duration = Duration::from_hours(24);
+
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            job_ids: RwLock::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Records the cancellation of `job_id` sent at `cancelled_at`, ignored
+    /// when it already expired.
+    pub fn insert(&self, job_id: impl Into<String>, cancelled_at: SystemTime) {
+        if !self.is_alive(cancelled_at) {
+            return;
+        }
+
+        let mut job_ids = self.job_ids.write().expect("Cancelled jobs lock poisoned");
+
+        // Cancellations are rare, so the expired ones are only swept on insert
+        job_ids.retain(|_, cancelled_at| self.is_alive(*cancelled_at));
+        job_ids.insert(job_id.into(), cancelled_at);
+    }
+
+    pub fn contains(&self, job_id: &str) -> bool {
+        self.job_ids
+            .read()
+            .expect("Cancelled jobs lock poisoned")
+            .get(job_id)
+            .is_some_and(|cancelled_at| self.is_alive(*cancelled_at))
+    }
+
+    /// A cancellation sent from a clock ahead of this one is kept.
+    fn is_alive(&self, cancelled_at: SystemTime) -> bool {
+        cancelled_at
+            .elapsed()
+            .map_or(true, |elapsed| elapsed < self.ttl)
+    }
+}
+
+impl Default for CancelledJobs {
+    fn default() -> Self {
+        Self::with_ttl(Self::DEFAULT_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancelledJobs;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    #[test]
+    fn cancellations_are_forgotten_after_their_ttl() {
+        // Arrange
+        let cancelled_jobs = CancelledJobs::with_ttl(Duration::ZERO);
+
+        // Act
+        cancelled_jobs.insert("job", SystemTime::now());
+
+        // Assert
+        assert!(!cancelled_jobs.contains("job"));
+    }
+
+    #[test]
+    fn replayed_cancellations_keep_their_original_time() {
+        // Arrange
+        let cancelled_jobs = CancelledJobs::with_ttl(Duration::from_hours(1));
+        let cancelled_at = SystemTime::now() - Duration::from_hours(2);
+
+        // Act
+        cancelled_jobs.insert("job", cancelled_at);
+
+        // Assert
+        assert!(!cancelled_jobs.contains("job"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::domain::operation::OperationError;
+use crate::domain::operation::OperationErrorKind;
+use crate::domain::operation::OperationOutcome;
+use evalexpr::ContextWithMutableVariables as _;
+use evalexpr::HashMapContext;
+use evalexpr::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+/// Runs the requests of one operation kind.
+///
+/// An executor only turns a request into an outcome: the scheduling, the
+/// cancellations and the delivery of the result are left to the consumer.
+pub trait OperationExecutor: Send + Sync {
+    /// Runs `request`, `inputs` holding the results it references by line and
+    /// `variables` the variables of its job.
+    fn execute(
+        &self,
+        request: &str,
+        inputs: &BTreeMap<u64, String>,
+        variables: &BTreeMap<String, serde_json::Value>,
+    ) -> OperationOutcome;
+}
+
+/// Executors by operation kind, the built-in ones included.
+pub struct ExecutorRegistry {
+    executors: HashMap<String, Box<dyn OperationExecutor>>,
+}
+
+impl ExecutorRegistry {
+    /// Kind of the operations sent without one.
+    pub const DEFAULT_KIND: &'static str = "evalexpr";
+
+    /// Registry holding no executor, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self {
+            executors: HashMap::new(),
+        }
+    }
+
+    /// Registers `executor` for the operations of `kind`, replacing the
+    /// executor registered for it before.
+    #[must_use]
+    pub fn with_executor(
+        mut self,
+        kind: impl Into<String>,
+        executor: impl OperationExecutor + 'static,
+    ) -> Self {
+        self.executors.insert(kind.into(), Box::new(executor));
+        self
+    }
+
+    /// Runs `request` with the executor of `kind`, an unknown kind failing
+    /// the operation.
+    pub fn execute(
+        &self,
+        kind: &str,
+        request: &str,
+        inputs: &BTreeMap<u64, String>,
+        variables: &BTreeMap<String, serde_json::Value>,
+    ) -> OperationOutcome {
+        self.executors.get(kind).map_or_else(
+            || OperationOutcome::Failed {
+                error: OperationError::new(
+                    OperationErrorKind::UnknownKind,
+                    format!("No executor for operations of kind {kind}"),
+                ),
+            },
+            |executor| executor.execute(request, inputs, variables),
+        )
+    }
+}
+
+impl Default for ExecutorRegistry {
+    fn default() -> Self {
+        Self::empty().with_executor(Self::DEFAULT_KIND, EvalexprExecutor)
+    }
+}
+
+/// Evaluates the requests as `evalexpr` expressions, the variables of the job
+/// being bound to their names and the results referenced as `$N` to the
+/// value of line `N`.
+pub struct EvalexprExecutor;
+
+impl EvalexprExecutor {
+    fn context(
+        inputs: &BTreeMap<u64, String>,
+        variables: &BTreeMap<String, serde_json::Value>,
+    ) -> evalexpr::EvalexprResult<HashMapContext> {
+        let mut context = HashMapContext::new();
+        for (name, value) in variables {
+            context.set_value(name.clone(), Self::variable_value(value))?;
+        }
+        for (line, value) in inputs {
+            // The results are rendered values, which read back as the same value
+            let value = evalexpr::eval(value).unwrap_or_else(|_| Value::String(value.clone()));
+            context.set_value(format!("${line}"), value)?;
+        }
+
+        Ok(context)
+    }
+
+    fn variable_value(value: &serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::Null => Value::Empty,
+            serde_json::Value::Bool(boolean) => Value::Boolean(*boolean),
+            serde_json::Value::Number(number) => number.as_i64().map_or_else(
+                || Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+                Value::Int,
+            ),
+            serde_json::Value::String(string) => Value::String(string.clone()),
+            serde_json::Value::Array(values) => {
+                Value::Tuple(values.iter().map(Self::variable_value).collect())
+            }
+            serde_json::Value::Object(_) => Value::String(value.to_string()),
+        }
+    }
+}
+
+impl OperationExecutor for EvalexprExecutor {
+    fn execute(
+        &self,
+        request: &str,
+        inputs: &BTreeMap<u64, String>,
+        variables: &BTreeMap<String, serde_json::Value>,
+    ) -> OperationOutcome {
+        let result = Self::context(inputs, variables)
+            .and_then(|context| evalexpr::eval_with_context(request, &context));
+
+        match result {
+            Ok(value) => OperationOutcome::Succeeded {
+                value: value.to_string(),
+            },
+            Err(err) => OperationOutcome::Failed {
+                error: OperationError::from(err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EvalexprExecutor;
+    use super::ExecutorRegistry;
+    use super::OperationExecutor as _;
+    use crate::domain::operation::OperationErrorKind;
+    use crate::domain::operation::OperationOutcome;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn inputs_are_bound_to_their_line_references() {
+        // Arrange
+        let inputs = BTreeMap::from([(1, "4".to_string()), (3, "1.5".to_string())]);
+
+        // Act
+        let outcome = EvalexprExecutor.execute("$1 * 2 + $3", &inputs, &BTreeMap::new());
+
+        // Assert
+        assert!(matches!(
+            outcome,
+            OperationOutcome::Succeeded { value } if value == "9.5"
+        ));
+    }
+
+    #[test]
+    fn variables_are_bound_to_their_names() {
+        // Arrange
+        let variables = BTreeMap::from([
+            ("rate".to_string(), serde_json::json!(0.5)),
+            ("n".to_string(), serde_json::json!(12)),
+        ]);
+
+        // Act
+        let outcome = EvalexprExecutor.execute("n * rate + 1", &BTreeMap::new(), &variables);
+
+        // Assert
+        assert!(matches!(
+            outcome,
+            OperationOutcome::Succeeded { value } if value == "7"
+        ));
+    }
+
+    #[test]
+    fn unknown_kinds_fail_the_operation() {
+        // Arrange
+        let registry = ExecutorRegistry::default();
+
+        // Act
+        let outcome = registry.execute("sha256", "abc", &BTreeMap::new(), &BTreeMap::new());
+
+        // Assert
+        assert!(matches!(
+            outcome,
+            OperationOutcome::Failed { error } if error.kind() == OperationErrorKind::UnknownKind
+        ));
+    }
+}
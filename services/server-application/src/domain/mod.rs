@@ -1 +1,4 @@
+pub mod cancelled_jobs;
+pub mod executor;
 pub mod operation;
+pub mod priority_scheduler;
@@ -1,9 +1,94 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationErrorKind {
+    Syntax,
+    UnknownIdentifier,
+    Type,
+    Arithmetic,
+    Evaluation,
+    /// No executor is registered for the kind of the operation.
+    UnknownKind,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct OperationError {
+    kind: OperationErrorKind,
+    message: String,
+}
+
+impl OperationError {
+    pub fn new(kind: OperationErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    #[cfg(test)]
+    pub const fn kind(&self) -> OperationErrorKind {
+        self.kind
+    }
+}
+
+impl From<evalexpr::EvalexprError> for OperationError {
+    fn from(err: evalexpr::EvalexprError) -> Self {
+        use evalexpr::EvalexprError;
+
+        let kind = match err {
+            EvalexprError::AppendedToLeafNode
+            | EvalexprError::PrecedenceViolation
+            | EvalexprError::UnmatchedLBrace
+            | EvalexprError::UnmatchedRBrace
+            | EvalexprError::UnmatchedDoubleQuote
+            | EvalexprError::MissingOperatorOutsideOfBrace
+            | EvalexprError::UnmatchedPartialToken { .. }
+            | EvalexprError::IllegalEscapeSequence(_)
+            | EvalexprError::WrongOperatorArgumentAmount { .. } => OperationErrorKind::Syntax,
+            EvalexprError::VariableIdentifierNotFound(_)
+            | EvalexprError::FunctionIdentifierNotFound(_) => OperationErrorKind::UnknownIdentifier,
+            EvalexprError::ExpectedString { .. }
+            | EvalexprError::ExpectedInt { .. }
+            | EvalexprError::ExpectedFloat { .. }
+            | EvalexprError::ExpectedNumber { .. }
+            | EvalexprError::ExpectedNumberOrString { .. }
+            | EvalexprError::ExpectedBoolean { .. }
+            | EvalexprError::ExpectedTuple { .. }
+            | EvalexprError::ExpectedFixedLengthTuple { .. }
+            | EvalexprError::ExpectedRangedLengthTuple { .. }
+            | EvalexprError::ExpectedEmpty { .. }
+            | EvalexprError::TypeError { .. }
+            | EvalexprError::WrongTypeCombination { .. }
+            | EvalexprError::WrongFunctionArgumentAmount { .. } => OperationErrorKind::Type,
+            EvalexprError::AdditionError { .. }
+            | EvalexprError::SubtractionError { .. }
+            | EvalexprError::NegationError { .. }
+            | EvalexprError::MultiplicationError { .. }
+            | EvalexprError::DivisionError { .. }
+            | EvalexprError::ModulationError { .. }
+            | EvalexprError::OutOfBoundsAccess
+            | EvalexprError::IntFromUsize { .. }
+            | EvalexprError::IntIntoUsize { .. } => OperationErrorKind::Arithmetic,
+            _ => OperationErrorKind::Evaluation,
+        };
+
+        Self::new(kind, err.to_string())
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OperationOutcome {
+    Succeeded { value: String },
+    Failed { error: OperationError },
+}
+
 #[allow(unused, clippy::struct_field_names)]
 pub struct Operation {
     job_id: String,
     operation_id: String,
     request: String,
-    result: String,
+    outcome: OperationOutcome,
+    attempt: u32,
 }
 
 impl Operation {
@@ -11,13 +96,15 @@ impl Operation {
         job_id: impl Into<String>,
         operation_id: impl Into<String>,
         request: impl Into<String>,
-        result: impl Into<String>,
+        outcome: OperationOutcome,
+        attempt: u32,
     ) -> Self {
         Self {
             job_id: job_id.into(),
             operation_id: operation_id.into(),
             request: request.into(),
-            result: result.into(),
+            outcome,
+            attempt,
         }
     }
 
@@ -34,7 +121,55 @@ impl Operation {
         &self.request
     }
 
-    pub fn result(&self) -> &str {
-        &self.result
+    pub const fn outcome(&self) -> &OperationOutcome {
+        &self.outcome
+    }
+
+    /// Dispatch attempt of the request, echoed so the client can discard the
+    /// results of a superseded dispatch.
+    pub const fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OperationError;
+    use super::OperationErrorKind;
+
+    #[test]
+    fn syntax_error_maps_to_syntax_kind() {
+        // Arrange
+        let err = evalexpr::eval("1 + 2 +").unwrap_err();
+
+        // Act
+        let error = OperationError::from(err);
+
+        // Assert
+        assert_eq!(error.kind(), OperationErrorKind::Syntax);
+    }
+
+    #[test]
+    fn division_by_zero_maps_to_arithmetic_kind() {
+        // Arrange
+        let err = evalexpr::eval("1 / 0").unwrap_err();
+
+        // Act
+        let error = OperationError::from(err);
+
+        // Assert
+        assert_eq!(error.kind(), OperationErrorKind::Arithmetic);
+    }
+
+    #[test]
+    fn unknown_variable_maps_to_unknown_identifier_kind() {
+        // Arrange
+        let err = evalexpr::eval("a + 1").unwrap_err();
+
+        // Act
+        let error = OperationError::from(err);
+
+        // Assert
+        assert_eq!(error.kind(), OperationErrorKind::UnknownIdentifier);
     }
 }
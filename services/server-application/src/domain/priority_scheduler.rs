@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Lane an operation request is received on, matching the priority of its
+/// job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Normal,
+    Bulk,
+}
+
+impl Priority {
+    pub const ALL: [Self; 3] = [Self::High, Self::Normal, Self::Bulk];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Bulk => "bulk",
+        }
+    }
+
+    /// Share of the slots the lane gets while the other lanes are waiting too.
+    const fn weight(self) -> i64 {
+        match self {
+            Self::High => 6,
+            Self::Normal => 3,
+            Self::Bulk => 1,
+        }
+    }
+
+    const fn index(self) -> usize {
+        match self {
+            Self::High => 0,
+            Self::Normal => 1,
+            Self::Bulk => 2,
+        }
+    }
+}
+
+struct Lanes {
+    available: usize,
+    waiters: [VecDeque<oneshot::Sender<SchedulerPermit>>; 3],
+    /// Credits of the smooth weighted round robin between the waiting lanes.
+    credits: [i64; 3],
+}
+
+impl Lanes {
+    /// Picks the waiting lane to hand the next slot to. Every waiting lane
+    /// earns its weight, the richest one is picked and pays for the others,
+    /// so the lanes are interleaved by weight rather than served in bursts.
+    fn next_lane(&mut self) -> Option<Priority> {
+        let mut total_weight = 0;
+        let mut next_lane: Option<Priority> = None;
+
+        for priority in Priority::ALL {
+            let index = priority.index();
+            // An idle lane does not save up credits for later
+            if self.waiters[index].is_empty() {
+                self.credits[index] = 0;
+                continue;
+            }
+
+            self.credits[index] += priority.weight();
+            total_weight += priority.weight();
+            if next_lane.is_none_or(|lane| self.credits[index] > self.credits[lane.index()]) {
+                next_lane = Some(priority);
+            }
+        }
+
+        let next_lane = next_lane?;
+        self.credits[next_lane.index()] -= total_weight;
+
+        Some(next_lane)
+    }
+}
+
+/// Bounds how many operations are handled at once across the lanes.
+///
+/// A lane alone may use every slot. Once slots run short, the freed ones are
+/// handed to the waiting lanes by weight, so a large bulk job keeps moving
+/// but no longer delays the high priority ones queued behind it.
+pub struct PriorityScheduler {
+    lanes: Arc<Mutex<Lanes>>,
+}
+
+impl PriorityScheduler {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            lanes: Arc::new(Mutex::new(Lanes {
+                available: slots,
+                waiters: Default::default(),
+                credits: [0; 3],
+            })),
+        }
+    }
+
+    /// Waits for a slot on the lane of `priority`, held until the returned
+    /// permit is dropped.
+    pub async fn acquire(&self, priority: Priority) -> SchedulerPermit {
+        let receiver = {
+            let mut lanes = self.lanes.lock().expect("Scheduler lock poisoned");
+            if lanes.available > 0 {
+                lanes.available -= 1;
+                return SchedulerPermit {
+                    lanes: Some(Arc::clone(&self.lanes)),
+                };
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            lanes.waiters[priority.index()].push_back(sender);
+            receiver
+        };
+
+        receiver
+            .await
+            .expect("Scheduler dropped a waiter without a permit")
+    }
+}
+
+/// Slot of the [`PriorityScheduler`], handed to the next waiter on drop.
+pub struct SchedulerPermit {
+    lanes: Option<Arc<Mutex<Lanes>>>,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        let Some(lanes) = self.lanes.take() else {
+            return;
+        };
+
+        loop {
+            let sender = {
+                let mut guard = lanes.lock().expect("Scheduler lock poisoned");
+                let Some(lane) = guard.next_lane() else {
+                    guard.available += 1;
+                    return;
+                };
+                guard.waiters[lane.index()]
+                    .pop_front()
+                    .expect("Picked lane has a waiter")
+            };
+
+            match sender.send(Self {
+                lanes: Some(Arc::clone(&lanes)),
+            }) {
+                Ok(()) => return,
+                // The waiter gave up, the slot goes to the next one
+                Err(mut permit) => permit.lanes = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lanes;
+    use super::Priority;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn busy_lanes_share_the_slots_by_weight() {
+        // Arrange
+        let mut lanes = Lanes {
+            available: 0,
+            waiters: Default::default(),
+            credits: [0; 3],
+        };
+        for priority in [Priority::High, Priority::Bulk] {
+            for _ in 0..10 {
+                let (sender, _receiver) = oneshot::channel();
+                lanes.waiters[priority.index()].push_back(sender);
+            }
+        }
+
+        // Act
+        let picked: Vec<_> = (0..7)
+            .map(|_| {
+                let lane = lanes.next_lane().unwrap();
+                lanes.waiters[lane.index()].pop_front();
+                lane
+            })
+            .collect();
+
+        // Assert
+        assert_eq!(
+            picked
+                .iter()
+                .filter(|lane| **lane == Priority::High)
+                .count(),
+            6
+        );
+        assert_eq!(
+            picked
+                .iter()
+                .filter(|lane| **lane == Priority::Bulk)
+                .count(),
+            1
+        );
+    }
+}
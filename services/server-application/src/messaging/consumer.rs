@@ -1,63 +1,252 @@
 use crate::domain;
+use crate::domain::cancelled_jobs::CancelledJobs;
+use crate::domain::executor::ExecutorRegistry;
+use crate::domain::priority_scheduler::Priority;
+use crate::domain::priority_scheduler::PriorityScheduler;
+use crate::messaging::model::JobControl;
+use crate::messaging::model::JobControlAction;
 use crate::messaging::model::OperationRequest;
 use crate::messaging::producer::MessageProducer;
 use anyhow::Result;
+use common::counter;
+use common::histogram;
+use common::messaging::consumer::HandlerOutcome;
 use common::messaging::consumer::MessageConsumer as CommonConsumer;
 use common::messaging::consumer::MessageHandler;
+use common::messaging::dead_letter::DeadLetterPolicy;
+use common::messaging::dead_letter::DeadLetterReplayer;
+use common::messaging::retry::RetryPolicy;
 use std::future::Future;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
-const TOPIC_NAME: &str = "application.operation.request";
-const GROUP_ID: &str = "operation-request-group";
-const CONCURRENCY: usize = 10;
+counter!(
+    SKIPPED_OPERATION_COUNTER,
+    "operation_requests_skipped",
+    "Number of operation requests skipped because their job was cancelled"
+);
+counter!(
+    HANDLED_OPERATION_COUNTER,
+    "operation_requests_handled",
+    "Number of operation requests run by an executor"
+);
+histogram!(
+    SCHEDULING_DELAY_HISTOGRAM,
+    "operation_request_scheduling_delay",
+    "Time an operation request waited for a slot of the priority scheduler",
+    "ms"
+);
 
+const HIGH_OPERATION_REQUEST_TOPIC_NAME: &str = "application.operation.request.high";
+const OPERATION_REQUEST_TOPIC_NAME: &str = "application.operation.request";
+const BULK_OPERATION_REQUEST_TOPIC_NAME: &str = "application.operation.request.bulk";
+const OPERATION_REQUEST_GROUP_ID: &str = "operation-request-group";
+const OPERATION_REQUEST_MAX_ATTEMPTS: u32 = 3;
+const OPERATION_REQUEST_CONCURRENCY: usize = 10;
+
+const JOB_CONTROL_TOPIC_NAME: &str = "application.job.control";
+const JOB_CONTROL_GROUP_ID_PREFIX: &str = "server-job-control-group";
+const JOB_CONTROL_CONCURRENCY: usize = 1;
+
+/// Runs the operation requests of one lane with the executor of their kind,
+/// once the scheduler it shares with the other lanes hands it a slot.
 pub struct OperationRequestHandler {
     message_producer: Arc<MessageProducer>,
+    cancelled_jobs: Arc<CancelledJobs>,
+    scheduler: Arc<PriorityScheduler>,
+    executor_registry: Arc<ExecutorRegistry>,
+    priority: Priority,
 }
 
 impl OperationRequestHandler {
-    pub const fn new(message_producer: Arc<MessageProducer>) -> Self {
-        Self { message_producer }
+    pub const fn new(
+        message_producer: Arc<MessageProducer>,
+        cancelled_jobs: Arc<CancelledJobs>,
+        scheduler: Arc<PriorityScheduler>,
+        executor_registry: Arc<ExecutorRegistry>,
+        priority: Priority,
+    ) -> Self {
+        Self {
+            message_producer,
+            cancelled_jobs,
+            scheduler,
+            executor_registry,
+            priority,
+        }
     }
 }
 
 impl MessageHandler<OperationRequest> for OperationRequestHandler {
-    fn handle(&self, message: OperationRequest) -> impl Future<Output = Result<()>> + Send {
+    fn handle(&self, message: OperationRequest) -> impl Future<Output = HandlerOutcome> + Send {
         let message_producer = Arc::clone(&self.message_producer);
+        let cancelled_jobs = Arc::clone(&self.cancelled_jobs);
+        let scheduler = Arc::clone(&self.scheduler);
+        let executor_registry = Arc::clone(&self.executor_registry);
+        let priority = self.priority;
         async move {
-            let result = match evalexpr::eval(message.request()) {
-                Ok(value) => value.to_string(),
-                Err(err) => err.to_string(),
+            let attributes = [opentelemetry::KeyValue::new("priority", priority.as_str())];
+
+            let is_cancelled = || {
+                let cancelled = cancelled_jobs.contains(message.job_id());
+                if cancelled {
+                    tracing::debug!(
+                        "Skipping operation {} of cancelled job {}",
+                        message.operation_id(),
+                        message.job_id()
+                    );
+
+                    SKIPPED_OPERATION_COUNTER.add(1, &attributes);
+                }
+                cancelled
             };
+
+            if is_cancelled() {
+                return HandlerOutcome::Ack;
+            }
+
+            // The slot is held until the result is sent
+            let waiting_since = Instant::now();
+            let _permit = scheduler.acquire(priority).await;
+            SCHEDULING_DELAY_HISTOGRAM.record(
+                u64::try_from(waiting_since.elapsed().as_millis()).unwrap_or(u64::MAX),
+                &attributes,
+            );
+
+            // The job may have been cancelled while the operation waited for its slot
+            if is_cancelled() {
+                return HandlerOutcome::Ack;
+            }
+            HANDLED_OPERATION_COUNTER.add(1, &attributes);
+
+            let outcome = executor_registry.execute(
+                message.kind(),
+                message.request(),
+                message.inputs(),
+                message.variables(),
+            );
             let operation = domain::operation::Operation::new(
                 message.job_id(),
                 message.operation_id(),
                 message.request(),
-                result,
+                outcome,
+                message.attempt(),
             );
 
-            message_producer.send_operation_result(operation);
-            Ok(())
+            match message_producer.send_operation_result(operation).await {
+                Ok(_delivery) => HandlerOutcome::Ack,
+                Err(err) => HandlerOutcome::retry(format!(
+                    "Failed to send the result of operation {}: {err}",
+                    message.operation_id()
+                )),
+            }
         }
     }
 }
 
-pub struct MessageConsumer(CommonConsumer<OperationRequest, OperationRequestHandler>);
+pub struct JobControlHandler {
+    cancelled_jobs: Arc<CancelledJobs>,
+}
+
+impl JobControlHandler {
+    pub const fn new(cancelled_jobs: Arc<CancelledJobs>) -> Self {
+        Self { cancelled_jobs }
+    }
+}
+
+impl MessageHandler<JobControl> for JobControlHandler {
+    fn handle(&self, message: JobControl) -> impl Future<Output = HandlerOutcome> + Send {
+        let cancelled_jobs = Arc::clone(&self.cancelled_jobs);
+        async move {
+            match message.action() {
+                JobControlAction::Cancel => {
+                    tracing::info!("Job {} has been cancelled", message.job_id());
+
+                    cancelled_jobs.insert(message.job_id(), message.sent_at());
+                }
+            }
+            HandlerOutcome::Ack
+        }
+    }
+}
+
+pub struct MessageConsumer {
+    /// One consumer per lane, sharing the slots of a priority scheduler.
+    operation_request_consumers: Vec<CommonConsumer<OperationRequest, OperationRequestHandler>>,
+    job_control_consumer: CommonConsumer<JobControl, JobControlHandler>,
+}
 
 impl MessageConsumer {
-    pub fn new(message_producer: Arc<MessageProducer>) -> Result<Self> {
-        let handler = Arc::new(OperationRequestHandler::new(message_producer));
-        Ok(Self(CommonConsumer::new(
-            handler,
-            TOPIC_NAME,
-            GROUP_ID,
-            CONCURRENCY,
-        )?))
+    pub fn new(
+        message_producer: &Arc<MessageProducer>,
+        executor_registry: &Arc<ExecutorRegistry>,
+    ) -> Result<Self> {
+        let cancelled_jobs = Arc::new(CancelledJobs::default());
+        let scheduler = Arc::new(PriorityScheduler::new(OPERATION_REQUEST_CONCURRENCY));
+        let operation_request_consumers = Priority::ALL
+            .into_iter()
+            .map(|priority| {
+                let topic = match priority {
+                    Priority::High => HIGH_OPERATION_REQUEST_TOPIC_NAME,
+                    Priority::Normal => OPERATION_REQUEST_TOPIC_NAME,
+                    Priority::Bulk => BULK_OPERATION_REQUEST_TOPIC_NAME,
+                };
+                let handler = Arc::new(OperationRequestHandler::new(
+                    Arc::clone(message_producer),
+                    Arc::clone(&cancelled_jobs),
+                    Arc::clone(&scheduler),
+                    Arc::clone(executor_registry),
+                    priority,
+                ));
+
+                CommonConsumer::new(
+                    handler,
+                    topic,
+                    OPERATION_REQUEST_GROUP_ID,
+                    OPERATION_REQUEST_CONCURRENCY,
+                    RetryPolicy::exponential(OPERATION_REQUEST_MAX_ATTEMPTS),
+                    Some(DeadLetterPolicy::for_topic(topic)),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let job_control_handler = Arc::new(JobControlHandler::new(cancelled_jobs));
+
+        // Every host must see every control message, hence a consumer group per host. Its offsets
+        // are never committed, a restart reading the retained cancellations again
+        let job_control_group_id = format!(
+            "{JOB_CONTROL_GROUP_ID_PREFIX}-{}",
+            common::application::host_id()
+        );
+
+        Ok(Self {
+            operation_request_consumers,
+            job_control_consumer: CommonConsumer::new(
+                job_control_handler,
+                JOB_CONTROL_TOPIC_NAME,
+                &job_control_group_id,
+                JOB_CONTROL_CONCURRENCY,
+                RetryPolicy::none(),
+                None,
+            )?
+            .without_offset_commits(),
+        })
+    }
+
+    pub fn dead_letter_replayers(&self) -> Result<Vec<DeadLetterReplayer>> {
+        let mut replayers = Vec::new();
+        for consumer in &self.operation_request_consumers {
+            replayers.extend(consumer.dead_letter_replayer()?);
+        }
+
+        Ok(replayers)
     }
 
     pub fn start(&self, shutdown: &CancellationToken) -> Vec<JoinHandle<Result<()>>> {
-        self.0.start(shutdown)
+        self.operation_request_consumers
+            .iter()
+            .flat_map(|consumer| consumer.start(shutdown))
+            .chain(self.job_control_consumer.start(shutdown))
+            .collect()
     }
 }
@@ -1,11 +1,27 @@
 use crate::domain;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::SystemTime;
 
+/// Unknown fields are ignored, so that the clients can send new ones before
+/// every server is upgraded.
 #[derive(serde::Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct OperationRequest {
     job_id: String,
     operation_id: String,
     request: String,
+    /// Executor the request is run by, the built-in expression evaluator
+    /// by default.
+    #[serde(default = "OperationRequest::default_kind")]
+    kind: String,
+    #[serde(default)]
+    attempt: u32,
+    /// Results of the operations the request references, by line.
+    #[serde(default)]
+    inputs: BTreeMap<u64, String>,
+    /// Variables of the job, bound to their names in the request.
+    #[serde(default)]
+    variables: BTreeMap<String, serde_json::Value>,
 }
 
 impl OperationRequest {
@@ -20,6 +36,26 @@ impl OperationRequest {
     pub fn request(&self) -> &str {
         &self.request
     }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    fn default_kind() -> String {
+        domain::executor::ExecutorRegistry::DEFAULT_KIND.to_string()
+    }
+
+    pub const fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub const fn inputs(&self) -> &BTreeMap<u64, String> {
+        &self.inputs
+    }
+
+    pub const fn variables(&self) -> &BTreeMap<String, serde_json::Value> {
+        &self.variables
+    }
 }
 
 impl TryFrom<&str> for OperationRequest {
@@ -30,11 +66,55 @@ impl TryFrom<&str> for OperationRequest {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobControlAction {
+    Cancel,
+}
+
+/// Lenient about the fields it does not know, as `sent_at` was added to it
+/// after the clients started sending it.
+#[derive(serde::Deserialize)]
+pub struct JobControl {
+    job_id: String,
+    action: JobControlAction,
+    /// Milliseconds since the Unix epoch, missing from the messages sent
+    /// before it was.
+    #[serde(default)]
+    sent_at: Option<u64>,
+}
+
+impl JobControl {
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub const fn action(&self) -> JobControlAction {
+        self.action
+    }
+
+    /// Time the message was sent, taken as now when unknown.
+    pub fn sent_at(&self) -> SystemTime {
+        self.sent_at.map_or_else(SystemTime::now, |sent_at| {
+            SystemTime::UNIX_EPOCH + Duration::from_millis(sent_at)
+        })
+    }
+}
+
+impl TryFrom<&str> for JobControl {
+    type Error = anyhow::Error;
+
+    fn try_from(message: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<Self>(message).map_err(|err| anyhow::anyhow!(err))
+    }
+}
+
 #[derive(serde::Serialize)]
 pub struct OperationResult {
     job_id: String,
     operation_id: String,
-    result: String,
+    outcome: domain::operation::OperationOutcome,
+    attempt: u32,
 }
 
 impl From<domain::operation::Operation> for OperationResult {
@@ -42,7 +122,38 @@ impl From<domain::operation::Operation> for OperationResult {
         Self {
             job_id: operation.job_id().to_string(),
             operation_id: operation.operation_id().to_string(),
-            result: operation.result().to_string(),
+            outcome: operation.outcome().clone(),
+            attempt: operation.attempt(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::JobControl;
+    use super::OperationRequest;
+
+    #[test]
+    fn operation_request_ignores_the_fields_it_does_not_know() {
+        // Arrange
+        let payload = r#"{"job_id":"1","operation_id":"2","request":"1 + 1","deadline":"soon"}"#;
+
+        // Act
+        let request = serde_json::from_str::<OperationRequest>(payload);
+
+        // Assert
+        assert!(request.is_ok());
+    }
+
+    #[test]
+    fn job_control_ignores_the_fields_it_does_not_know() {
+        // Arrange
+        let payload = r#"{"job_id":"1","action":"cancel","sent_at":1,"reason":"manual"}"#;
+
+        // Act
+        let control = serde_json::from_str::<JobControl>(payload);
+
+        // Assert
+        assert!(control.is_ok());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::domain;
 use crate::messaging::model::OperationResult;
 use anyhow::Result;
+use common::messaging::producer::Delivery;
 use common::messaging::producer::MessageProducer as CommonProducer;
 
 pub struct MessageProducer(CommonProducer<OperationResult>);
@@ -12,7 +13,10 @@ impl MessageProducer {
         Ok(Self(CommonProducer::new(Self::TOPIC_NAME)?))
     }
 
-    pub fn send_operation_result(&self, operation: domain::operation::Operation) {
-        self.0.send(&OperationResult::from(operation));
+    pub async fn send_operation_result(
+        &self,
+        operation: domain::operation::Operation,
+    ) -> Result<Delivery> {
+        self.0.deliver(&OperationResult::from(operation)).await
     }
 }